@@ -0,0 +1,504 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! ABC notation output and input. Chant is written unmetered (`M:none`) in the treble clef at the
+//! pitch of `GabcFile::as_lilypond()`, with eighth notes (quarter notes under a punctum mora),
+//! a slur over each neume, lyrics in `w:` lines, and `|` for the divisions of the chant: `||`
+//! after a double bar, and a dotted bar `.|` for quarter bars and virgulae. Accidentals are
+//! written out wherever they differ from what ABC's rules would otherwise give.
+//!
+//! `from_abc()` reads this subset of ABC back into gabc.
+
+use events::{events, pitch_name, Event};
+use formatter::{format_gabc, FormatOptions};
+use {choose_clef, clef_offset, GabcFile, Syllabic};
+
+///Header fields written from (and read into) gabc attributes.
+static FIELDS: [(char, &str); 4] = [('T', "name"), ('C', "author"), ('S', "book"), ('Z', "transcriber")];
+
+///Translate a `GabcFile` into an ABC tune.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("name:Test;
+///mode:8;
+///%%
+///(c4) Al(gh)le(j)lu(jh/ixi)ia.(g.) (::)");
+///assert_eq!(f.as_abc(), "X:1
+///T:Test
+///N:mode: 8
+///M:none
+///L:1/8
+///K:C
+///(GA) c (cA) _B G2 ||
+///w:Al-_le-lu-__ia.
+///");
+///```
+pub fn to_abc(file: &GabcFile) -> String {
+    let mut result = String::from("X:1\n");
+    for (key, value) in &file.attributes {
+        let (key, value) = (key.trim(), value.trim());
+        match FIELDS.iter().find(|f| f.1 == key) {
+            Some((field, _)) => result.push_str(&format!("{}:{}\n", field, value)),
+            None => result.push_str(&format!("N:{}: {}\n", key, value)),
+        }
+    }
+    result.push_str("M:none\nL:1/8\nK:C\n");
+    let lyrics = file.syllable_lyrics();
+    let events = events(file);
+    let mut music = String::new();
+    let mut words: Vec<WordToken> = Vec::new();
+    //Alterations written since the last bar, which ABC applies to the rest of the bar
+    let mut written: Vec<(usize, i32)> = Vec::new();
+    let mut previous_syllable = None;
+    for (k, event) in events.iter().enumerate() {
+        match event {
+            Event::Note(n) => {
+                let next_joined = matches!(events.get(k + 1), Some(Event::Note(next)) if next.joined);
+                if !n.joined && !music.is_empty() {
+                    music.push(' ');
+                }
+                if !n.joined && next_joined {
+                    music.push('(');
+                }
+                let current = written.iter().find(|a| a.0 == n.pitch).map_or(0, |a| a.1);
+                if current != n.alter {
+                    music.push_str(match n.alter {
+                        -1 => "_",
+                        1 => "^",
+                        _ => "=",
+                    });
+                    written.retain(|a| a.0 != n.pitch);
+                    written.push((n.pitch, n.alter));
+                }
+                music.push_str(&abc_pitch(n.pitch));
+                if n.morae > 0 {
+                    music.push('2');
+                }
+                if n.joined && !next_joined {
+                    music.push(')');
+                }
+                let first = previous_syllable != Some(n.syllable);
+                previous_syllable = Some(n.syllable);
+                words.push(match (&lyrics[n.syllable], first) {
+                    (Some((text, syllabic)), true) => {
+                        WordToken::Text(escape_lyric(text), matches!(syllabic, Syllabic::Begin | Syllabic::Middle))
+                    }
+                    (Some(_), false) => WordToken::Hold,
+                    (None, _) => WordToken::Skip,
+                });
+            }
+            Event::Barline(bar) => {
+                written.clear();
+                let bar = match *bar {
+                    "," | ",_" | ",0" | "'" | "`" => ".|",
+                    "::" => "||",
+                    _ => "|",
+                };
+                if music.is_empty() {
+                    continue;
+                }
+                music.push(' ');
+                music.push_str(bar);
+                if bar != ".|" {
+                    write_line(&mut result, &music, &words);
+                    music.clear();
+                    words.clear();
+                }
+            }
+        }
+    }
+    if !music.is_empty() {
+        write_line(&mut result, &music, &words);
+    }
+    result
+}
+
+///What a note carries in a `w:` line.
+#[derive(Debug, PartialEq)]
+enum WordToken {
+    ///A syllable, and whether its word continues in the next syllable
+    Text(String, bool),
+    ///The syllable of the note before, held ("_")
+    Hold,
+    ///No syllable ("*")
+    Skip,
+}
+
+///Write a line of music and its `w:` line.
+fn write_line(result: &mut String, music: &str, words: &[WordToken]) {
+    result.push_str(music);
+    result.push('\n');
+    if words.iter().all(|w| *w == WordToken::Skip) {
+        return;
+    }
+    result.push_str("w:");
+    let mut continues = false;
+    for (i, word) in words.iter().enumerate() {
+        let attached = *word == WordToken::Hold || continues && matches!(word, WordToken::Text(..));
+        if i > 0 && !attached {
+            result.push(' ');
+        }
+        match word {
+            WordToken::Text(text, c) => {
+                result.push_str(text);
+                if *c {
+                    result.push('-');
+                }
+                continues = *c;
+            }
+            WordToken::Hold => result.push('_'),
+            WordToken::Skip => {
+                result.push('*');
+                continues = false;
+            }
+        }
+    }
+    result.push('\n');
+}
+
+///Escape the characters of a syllable that have a meaning in `w:` lines.
+fn escape_lyric(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        match c {
+            ' ' => result.push('~'),
+            '-' | '_' | '*' | '~' | '|' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '%' => {}
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+///The ABC note name of a pitch, an octave above its Lilypond (untransposed) pitch.
+fn abc_pitch(pitch: usize) -> String {
+    let (letter, octave) = pitch_name(pitch);
+    let octave = octave + 1;
+    if octave >= 5 {
+        format!("{}{}", letter, "'".repeat(octave as usize - 5))
+    } else {
+        format!("{}{}", letter.to_ascii_uppercase(), ",".repeat(4 - octave as usize))
+    }
+}
+
+///A note or bar read from ABC.
+enum AbcToken {
+    Note {
+        ///Pitch index, as returned by `Note::pitch_index()`
+        pitch: usize,
+        ///Explicit accidental, if any
+        accidental: Option<i32>,
+        long: bool,
+        ///Whether the note continues the slur (neume) of the note before it
+        joined: bool,
+    },
+    Bar(&'static str),
+}
+
+///Read an ABC tune in the subset written by `to_abc()` into gabc: unmetered single-voice music
+///in C with notes of one or two eighths, slurs, bars and `w:` lyrics. Slurred notes become
+///neumes; `w:` syllables, held notes ("_") and skipped notes ("*") give the syllables. The clef
+///is chosen to centre the melody on the staff, so it may not be the clef of the original gabc.
+///"T", "C", "S" and "Z" header fields and "N:key: value" notes become gabc attributes. The gabc
+///is written in canonical form (see the `formatter` module). Anything outside this subset is an
+///error.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::abc::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh/ixi)ia.(g.) (::)");
+/////The same melody, a step lower on the staff under a C clef on the third line
+///assert_eq!(from_abc(&f.as_abc()).unwrap(), "name: Test;
+///%%
+///(c3) Al(ef)le(h)lu(hf/gxg)ia.(e.) (::)
+///");
+///assert!(from_abc("X:1\nK:C\n[CEG]\n").is_err());
+///```
+pub fn from_abc(abc: &str) -> Result<String, String> {
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let mut in_body = false;
+    let mut tokens: Vec<AbcToken> = Vec::new();
+    let mut words: Vec<Option<WordToken>> = Vec::new();
+    //Index in `tokens` of the start of the last line of music, for its `w:` line
+    let mut line_start = 0;
+    let mut in_slur = false;
+    for line in abc.lines() {
+        let line = line.split('%').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let field = match line.as_bytes() {
+            [f, b':', ..] if f.is_ascii_alphabetic() => Some((*f as char, line[2..].trim())),
+            _ => None,
+        };
+        match field {
+            Some(('w', text)) if in_body => {
+                let notes = tokens[line_start..].iter().filter(|t| matches!(t, AbcToken::Note { .. })).count();
+                let mut line_words: Vec<Option<WordToken>> = read_words(text).into_iter().map(Some).collect();
+                line_words.resize_with(notes, || None);
+                line_words.truncate(notes);
+                let start = words.len() - notes;
+                words.truncate(start);
+                words.extend(line_words);
+            }
+            Some(('K', key)) => {
+                let key = key.replace("clef=treble", "");
+                if !matches!(key.trim(), "" | "C" | "Cmaj" | "Am" | "none") {
+                    return Err(format!("unsupported key: {}", key.trim()));
+                }
+                in_body = true;
+            }
+            Some(('L', length)) if length != "1/8" => return Err(format!("unsupported unit note length: {}", length)),
+            Some(('N', note)) => {
+                let (key, value) = note.split_once(':').unwrap_or(("notes", note));
+                attributes.push((key.trim().to_string(), value.trim().to_string()));
+            }
+            Some((f, value)) => {
+                if let Some((_, key)) = FIELDS.iter().find(|(c, _)| *c == f) {
+                    attributes.push((key.to_string(), value.to_string()));
+                }
+            }
+            None if !in_body => return Err(format!("music before the K: field: {}", line)),
+            None => {
+                line_start = tokens.len();
+                read_music(line, &mut tokens, &mut in_slur)?;
+                let notes = tokens[line_start..].iter().filter(|t| matches!(t, AbcToken::Note { .. })).count();
+                words.extend((0..notes).map(|_| None));
+            }
+        }
+    }
+    let mut gabc = String::new();
+    for (key, value) in attributes {
+        gabc.push_str(&format!("{}:{};\n", key.replace([':', ';'], ","), value.replace([':', ';'], ",")));
+    }
+    gabc.push_str("%%\n");
+    gabc.push_str(&write_gabc(&tokens, words)?);
+    format_gabc(&gabc, &FormatOptions::default())
+}
+
+///Read the syllables of a `w:` line.
+fn read_words(text: &str) -> Vec<WordToken> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let flush = |current: &mut String, result: &mut Vec<WordToken>, continues: bool| {
+        if !current.is_empty() {
+            result.push(WordToken::Text(std::mem::take(current), continues));
+        } else if continues {
+            //A hyphen after a space still joins the syllable before it to the next one
+            if let Some(WordToken::Text(_, c)) = result.iter_mut().rev().find(|w| matches!(w, WordToken::Text(..))) {
+                *c = true;
+            }
+        }
+    };
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '|' => flush(&mut current, &mut result, false),
+            '-' => flush(&mut current, &mut result, true),
+            '_' => {
+                flush(&mut current, &mut result, false);
+                result.push(WordToken::Hold);
+            }
+            '*' => {
+                flush(&mut current, &mut result, false);
+                result.push(WordToken::Skip);
+            }
+            '~' => current.push(' '),
+            '\\' => current.extend(chars.next()),
+            c => current.push(c),
+        }
+    }
+    flush(&mut current, &mut result, false);
+    result
+}
+
+///Read a line of ABC music into notes and bars.
+fn read_music(line: &str, tokens: &mut Vec<AbcToken>, in_slur: &mut bool) -> Result<(), String> {
+    let mut chars = line.chars().peekable();
+    let mut accidental = None;
+    let mut slur_start = false;
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {}
+            '(' if !*in_slur => {
+                *in_slur = true;
+                slur_start = true;
+            }
+            ')' if *in_slur => *in_slur = false,
+            '|' if chars.peek() == Some(&'|') => {
+                chars.next();
+                tokens.push(AbcToken::Bar("||"));
+            }
+            '|' => tokens.push(AbcToken::Bar("|")),
+            '.' if chars.peek() == Some(&'|') => {
+                chars.next();
+                tokens.push(AbcToken::Bar(".|"));
+            }
+            '_' | '=' | '^' if accidental.is_none() => {
+                accidental = Some(match c {
+                    '_' => -1,
+                    '^' => 1,
+                    _ => 0,
+                });
+            }
+            'A'..='G' | 'a'..='g' => {
+                let letter = c.to_ascii_lowercase() as i32 - 'a' as i32;
+                let mut octave = if c.is_ascii_uppercase() { 4 } else { 5 };
+                let mut length = String::new();
+                while let Some(&m) = chars.peek() {
+                    match m {
+                        '\'' => octave += 1,
+                        ',' => octave -= 1,
+                        '0'..='9' | '/' => length.push(m),
+                        _ => break,
+                    }
+                    chars.next();
+                }
+                let long = match length.as_str() {
+                    "" | "1" => false,
+                    "2" => true,
+                    _ => return Err(format!("unsupported note length: {}{}", c, length)),
+                };
+                //Inverse of `abc_pitch()`
+                let pitch = 7 * (octave - 1 - if letter >= 2 { 3 } else { 2 }) + letter;
+                if pitch < 0 {
+                    return Err(format!("note too low: {}", c));
+                }
+                tokens.push(AbcToken::Note {
+                    pitch: pitch as usize,
+                    accidental: accidental.take(),
+                    long,
+                    joined: *in_slur && !slur_start,
+                });
+                slur_start = false;
+            }
+            c => return Err(format!("unsupported ABC: {}", c)),
+        }
+    }
+    Ok(())
+}
+
+///A gabc syllable being written: its text and music.
+struct GabcSyllable {
+    text: String,
+    music: String,
+}
+
+///Write ABC notes and bars, with a syllable for each note (`None` where the `w:` lines give
+///none), as a gabc body.
+fn write_gabc(tokens: &[AbcToken], words: Vec<Option<WordToken>>) -> Result<String, String> {
+    let pitches: Vec<usize> = tokens
+        .iter()
+        .filter_map(|t| match t {
+            AbcToken::Note { pitch, .. } => Some(*pitch),
+            _ => None,
+        })
+        .collect();
+    let clef = choose_clef(&pitches).ok_or("the range of the melody is too wide for a clef")?;
+    let offset = clef_offset(clef).unwrap();
+    let mut body = format!("({})", clef);
+    let mut open: Option<GabcSyllable> = None;
+    //Whether the open syllable continues its word in the next one
+    let mut continues = false;
+    //Alterations in force since the last bar, in ABC and in the gabc written
+    let mut abc_alterations: Vec<(usize, i32)> = Vec::new();
+    let mut gabc_alterations: Vec<(usize, i32)> = Vec::new();
+    let mut words = words.into_iter();
+    let close = |body: &mut String, open: &mut Option<GabcSyllable>| {
+        if let Some(s) = open.take() {
+            body.push_str(&format!("{}({})", s.text, s.music));
+        }
+    };
+    for (k, token) in tokens.iter().enumerate() {
+        match token {
+            AbcToken::Bar(bar) => {
+                abc_alterations.clear();
+                gabc_alterations.clear();
+                let barline = match *bar {
+                    "||" => "::",
+                    "|" => ":",
+                    _ => ",",
+                };
+                //A bar followed by a held note falls inside a syllable
+                let next = tokens[k + 1..].iter().position(|t| matches!(t, AbcToken::Note { .. }));
+                let held = next.is_some() && matches!(words.as_slice().first(), Some(Some(WordToken::Hold)));
+                match open.as_mut() {
+                    Some(s) if held => s.music.push_str(barline),
+                    _ => {
+                        close(&mut body, &mut open);
+                        body.push_str(&format!(" ({})", barline));
+                        continues = false;
+                    }
+                }
+            }
+            AbcToken::Note {
+                pitch,
+                accidental,
+                long,
+                joined,
+            } => {
+                let word = words.next().unwrap_or(None);
+                let new_syllable = match &word {
+                    Some(WordToken::Text(..)) => true,
+                    Some(WordToken::Hold) => open.is_none(),
+                    _ => !matches!(&open, Some(s) if s.text.trim().is_empty()),
+                };
+                if new_syllable {
+                    close(&mut body, &mut open);
+                    let text = match word {
+                        Some(WordToken::Text(text, c)) => {
+                            let space = if continues { "" } else { " " };
+                            continues = c;
+                            format!("{}{}", space, text.replace(['(', '%'], ""))
+                        }
+                        _ => {
+                            continues = false;
+                            " ".to_string()
+                        }
+                    };
+                    open = Some(GabcSyllable {
+                        text,
+                        music: String::new(),
+                    });
+                }
+                let syllable = open.as_mut().unwrap();
+                let position = (b'a' + (pitch - offset) as u8) as char;
+                if let Some(alter) = accidental {
+                    abc_alterations.retain(|a| a.0 != *pitch);
+                    abc_alterations.push((*pitch, *alter));
+                }
+                let alter = abc_alterations.iter().find(|a| a.0 == *pitch).map_or(0, |a| a.1);
+                let shown = gabc_alterations.iter().find(|a| a.0 == *pitch).map_or(0, |a| a.1);
+                //As in gabc, a sign starts a new neume
+                let separate = (!*joined || alter != shown) && !syllable.music.is_empty() && !syllable.music.ends_with([':', ',']);
+                if separate {
+                    syllable.music.push('/');
+                }
+                if alter != shown {
+                    syllable.music.push(position);
+                    syllable.music.push(match alter {
+                        -1 => 'x',
+                        1 => '#',
+                        _ => 'y',
+                    });
+                    gabc_alterations.retain(|a| a.0 != *pitch);
+                    gabc_alterations.push((*pitch, alter));
+                }
+                syllable.music.push(position);
+                if *long {
+                    syllable.music.push('.');
+                }
+            }
+        }
+    }
+    close(&mut body, &mut open);
+    body.push('\n');
+    Ok(body)
+}
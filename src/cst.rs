@@ -0,0 +1,238 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Lossless concrete syntax tree of a gabc file, for tools that edit gabc in place. Unlike
+//! `GabcFile`, a `Cst` keeps every byte of its input: whitespace, line breaks, `%` comments and
+//! the order of the header lines. Every node covers a byte range of the source; the text between
+//! the rules of a node (e.g. the parentheses of a syllable) is kept as `Kind::Literal` children,
+//! so the leaves of the tree spell out the input exactly. `GabcFile`s are derived from this tree.
+//!
+//! Edits replace a byte range of the source and leave every other byte untouched, so a file
+//! edited this way keeps its formatting.
+
+use pest::iterators::Pair;
+use pest::Parser;
+use std::fmt;
+use std::ops::Range;
+use {file_from_node, GABCParser, GabcFile, Rule};
+
+///What a `Node` of the syntax tree is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    ///Text matched by a rule of the grammar
+    Rule(Rule),
+    ///Text between the rules of its parent, e.g. "(" and ")" around the music of a syllable
+    Literal,
+}
+
+///A node of a concrete syntax tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    ///What this node is
+    pub kind: Kind,
+    ///Byte range of this node in the source
+    pub span: Range<usize>,
+    ///Child nodes in source order, covering this node's range without gaps (empty for leaves)
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    ///Build the tree of a parse result, filling the gaps between rules with literals.
+    pub(crate) fn from_pair(pair: Pair<Rule>) -> Node {
+        let rule = pair.as_rule();
+        let span = pair.clone().into_span();
+        let (start, end) = (span.start(), span.end());
+        let mut children = Vec::new();
+        let mut position = start;
+        for inner in pair.into_inner() {
+            let child = Node::from_pair(inner);
+            if child.span.start > position {
+                children.push(Node::literal(position..child.span.start));
+            }
+            position = child.span.end;
+            children.push(child);
+        }
+        if !children.is_empty() && end > position {
+            children.push(Node::literal(position..end));
+        }
+        Node {
+            kind: Kind::Rule(rule),
+            span: start..end,
+            children,
+        }
+    }
+    ///A literal leaf.
+    fn literal(span: Range<usize>) -> Node {
+        Node {
+            kind: Kind::Literal,
+            span,
+            children: Vec::new(),
+        }
+    }
+    ///The grammar rule of this node, or `None` for literals.
+    pub fn rule(&self) -> Option<Rule> {
+        match self.kind {
+            Kind::Rule(r) => Some(r),
+            Kind::Literal => None,
+        }
+    }
+    ///The first child of the given rule; panics if there is none.
+    fn child(&self, rule: Rule) -> &Node {
+        self.children.iter().find(|c| c.rule() == Some(rule)).unwrap()
+    }
+    ///The text of this node in `source`, which must be the source of its tree.
+    pub fn text<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.span.clone()]
+    }
+    ///The leaves of the tree below this node (or this node itself, if it is a leaf), in order.
+    pub fn leaves(&self) -> Vec<&Node> {
+        if self.children.is_empty() {
+            return vec![self];
+        }
+        self.children.iter().flat_map(|c| c.leaves()).collect()
+    }
+}
+
+///A gabc file with its concrete syntax tree.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::cst::*;
+///let s = "name: Test;  % a comment
+///%%
+///(c4) Hel(e.)lo(hi~)
+///%another comment
+/// (::)
+///";
+///let mut cst = Cst::parse(s).unwrap();
+///assert_eq!(cst.to_string(), s);
+///assert_eq!(cst.gabc_file().syllables.len(), 4);
+///cst.replace_syllable(2, "lo(h)").unwrap();
+///assert_eq!(cst.source(), s.replace("lo(hi~)", "lo(h)"));
+///```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+    source: String,
+    root: Node,
+}
+
+impl Cst {
+    ///Parse gabc into a syntax tree, failing with pest's error message if it is not valid gabc.
+    pub fn parse(gabc_input: &str) -> Result<Cst, String> {
+        let mut pairs = GABCParser::parse(Rule::file, gabc_input).map_err(|e| format!("{}", e))?;
+        Ok(Cst {
+            source: gabc_input.to_string(),
+            root: Node::from_pair(pairs.next().unwrap()),
+        })
+    }
+    ///The gabc source of this tree.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    ///The root (`Rule::file`) node of this tree.
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+    ///The `GabcFile` model of this tree.
+    pub fn gabc_file(&self) -> GabcFile<'_> {
+        file_from_node(&self.source, &self.root)
+    }
+    ///The syllable nodes of this tree, in the order of `GabcFile::syllables`.
+    pub fn syllables(&self) -> Vec<&Node> {
+        self.nodes(Rule::syllable)
+    }
+    ///The attribute nodes of this tree, in the order of `GabcFile::attributes`.
+    pub fn attributes(&self) -> Vec<&Node> {
+        self.nodes(Rule::attribute)
+    }
+    ///Replace the syllable at `index` (as in `GabcFile::syllables`) with a single gabc syllable,
+    ///e.g. " Po(eh/hi)", returning the byte range of the new syllable in the source.
+    pub fn replace_syllable(&mut self, index: usize, syllable: &str) -> Result<Range<usize>, String> {
+        let old = match self.syllables().get(index) {
+            Some(node) => node.span.clone(),
+            None => return Err(format!("no syllable {}", index)),
+        };
+        let mut parsed = GABCParser::parse(Rule::syllable, syllable).map_err(|e| format!("{}", e))?;
+        let count = self.syllables().len();
+        let previous = self.clone();
+        let range = self.replace(old, syllable)?;
+        //The new text must not run into its neighbours
+        if parsed.next().unwrap().as_str().len() != syllable.len()
+            || self.syllables().len() != count
+            || self.syllables()[index].span != range
+        {
+            *self = previous;
+            return Err(format!("not a single syllable: {}", syllable));
+        }
+        Ok(range)
+    }
+    ///Set the value of a header attribute, e.g. `set_attribute("mode", "8")`. An existing
+    ///attribute keeps its place in the header; a new one is added after the last attribute.
+    ///Returns the byte range of the changed text.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::cst::*;
+    ///let mut cst = Cst::parse("name:Test;\n%%\n(c4) A(g)\n").unwrap();
+    ///cst.set_attribute("name", " Other").unwrap();
+    ///cst.set_attribute("mode", "8").unwrap();
+    ///assert_eq!(cst.source(), "name: Other;\nmode:8;\n%%\n(c4) A(g)\n");
+    ///assert!(cst.set_attribute("mode", "8;").is_err());
+    ///```
+    pub fn set_attribute(&mut self, key: &str, value: &str) -> Result<Range<usize>, String> {
+        if value.contains([':', ';']) {
+            return Err(format!("invalid attribute value: {}", value));
+        }
+        if key.starts_with('%') || key.contains([':', ';', '\n']) {
+            return Err(format!("invalid attribute key: {}", key));
+        }
+        let source = &self.source;
+        let existing = self
+            .attributes()
+            .into_iter()
+            .find(|a| a.child(Rule::attribute_key).text(source) == key)
+            .map(|a| a.child(Rule::attribute_value).span.clone());
+        match existing {
+            Some(range) => self.replace(range, value),
+            None => {
+                //Insert after the line of the last attribute
+                let children = &self.root.children;
+                let at = match children.iter().rposition(|c| c.rule() == Some(Rule::attribute)) {
+                    Some(i) => children[i + 1].span.end,
+                    None => 0,
+                };
+                self.replace(at..at, &format!("{}:{};\n", key, value))
+            }
+        }
+    }
+    ///Replace a byte range of the source and rebuild the tree, leaving the tree unchanged if the
+    ///range is out of order or out of bounds, or if the result is not valid gabc. Returns the
+    ///byte range of the new text.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) -> Result<Range<usize>, String> {
+        if range.start > range.end
+            || range.end > self.source.len()
+            || !self.source.is_char_boundary(range.start)
+            || !self.source.is_char_boundary(range.end)
+        {
+            return Err(format!("invalid range: {:?}", range));
+        }
+        let mut source = self.source.clone();
+        source.replace_range(range.clone(), text);
+        *self = Cst::parse(&source)?;
+        Ok(range.start..range.start + text.len())
+    }
+    ///Nodes of the given rule directly under the root.
+    fn nodes(&self, rule: Rule) -> Vec<&Node> {
+        self.root.children.iter().filter(|c| c.rule() == Some(rule)).collect()
+    }
+}
+
+///Write the tree back out by concatenating its leaves, which reproduces the source exactly.
+impl fmt::Display for Cst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for leaf in self.root.leaves() {
+            f.write_str(leaf.text(&self.source))?;
+        }
+        Ok(())
+    }
+}
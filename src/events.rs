@@ -0,0 +1,115 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! The music of a `GabcFile` as a flat sequence of sung notes and barlines, with clefs and
+//! accidentals resolved, for the exporters to other formats.
+
+use {GabcFile, NoteElem};
+
+///A note as it is sung.
+pub(crate) struct SungNote {
+    ///Index of the syllable the note belongs to
+    pub syllable: usize,
+    ///Diatonic pitch, as returned by `Note::pitch_index()`
+    pub pitch: usize,
+    ///Chromatic alteration from the accidental in force: -1 for flat, 1 for sharp
+    pub alter: i32,
+    ///Whether this note is the first one at its pitch after an accidental sign
+    pub accidental: bool,
+    ///Number of puncta morae (".") lengthening the note
+    pub morae: usize,
+    ///Whether the note has a horizontal episema ("_")
+    pub episema: bool,
+    ///Whether the note is liquescent ("~", "<" or ">")
+    pub liquescent: bool,
+    ///Whether the note continues the neume of the note before it, i.e. they are in the same
+    ///syllable with no spacer (other than "!" or "@"), clef, barline or sign between them
+    pub joined: bool,
+}
+
+///A note or barline of a file.
+pub(crate) enum Event<'a> {
+    Note(SungNote),
+    Barline(&'a str),
+}
+
+///Flatten the music of a file into sung notes and barlines. Flats ("x"), naturals ("y") and
+///sharps ("#") are signs rather than notes: each one alters the notes at its pitch until the
+///next barline or sign at that pitch. Notes with no recognized clef are skipped.
+pub(crate) fn events<'a>(file: &GabcFile<'a>) -> Vec<Event<'a>> {
+    let mut result = Vec::new();
+    //Accidentals in force: (pitch, alteration, not yet shown on a note)
+    let mut accidentals: Vec<(usize, i32, bool)> = Vec::new();
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let mut in_neume = false;
+        for elem in &syllable.music {
+            let note = match elem {
+                NoteElem::Note(n) => n,
+                NoteElem::Barline(bar) => {
+                    accidentals.clear();
+                    in_neume = false;
+                    result.push(Event::Barline(bar));
+                    continue;
+                }
+                NoteElem::Spacer("!") | NoteElem::Spacer("@") => continue,
+                NoteElem::Spacer(_) | NoteElem::Clef(_) => {
+                    in_neume = false;
+                    continue;
+                }
+            };
+            let pitch = match note.pitch_index() {
+                Some(p) => p,
+                None => continue,
+            };
+            let sign = if note.suffix.contains('x') {
+                Some(-1)
+            } else if note.suffix.contains('y') {
+                Some(0)
+            } else if note.suffix.contains('#') {
+                Some(1)
+            } else {
+                None
+            };
+            if let Some(alter) = sign {
+                accidentals.retain(|a| a.0 != pitch);
+                accidentals.push((pitch, alter, true));
+                in_neume = false;
+                continue;
+            }
+            let (alter, accidental) = match accidentals.iter_mut().find(|a| a.0 == pitch) {
+                Some(a) => {
+                    let shown = a.2;
+                    a.2 = false;
+                    (a.1, shown)
+                }
+                None => (0, false),
+            };
+            result.push(Event::Note(SungNote {
+                syllable: i,
+                pitch,
+                alter,
+                accidental,
+                morae: note.suffix.matches('.').count(),
+                episema: note.suffix.contains('_'),
+                liquescent: note.suffix.contains(['~', '<', '>']),
+                joined: in_neume,
+            }));
+            in_neume = true;
+        }
+    }
+    result
+}
+
+///Letter name (lower case) and octave number (middle C is in octave 4) of a pitch index.
+pub(crate) fn pitch_name(pitch: usize) -> (char, i32) {
+    let letter = b"abcdefg"[pitch % 7] as char;
+    (letter, (pitch as i32 + 5) / 7 + 2)
+}
+
+///MIDI note number of a pitch with an alteration, untransposed (c' is middle C, 60).
+pub(crate) fn midi_key(pitch: usize, alter: i32) -> i32 {
+    let (letter, octave) = pitch_name(pitch);
+    let semitone = [9, 11, 0, 2, 4, 5, 7][(letter as u8 - b'a') as usize];
+    12 * (octave + 1) + semitone + alter
+}
@@ -0,0 +1,87 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! The font used for text under the staff: DejaVu Serif. With the "png-pdf" feature it is built
+//! into the library, so that PNG and PDF output need no fonts installed (see
+//! fonts/LICENSE-DejaVu), and text is measured from it. Without it, text widths are estimated.
+
+#[cfg(feature = "png-pdf")]
+use std::sync::OnceLock;
+#[cfg(feature = "png-pdf")]
+use ttf_parser::{Face, GlyphId};
+
+///The font file.
+#[cfg(feature = "png-pdf")]
+pub(crate) static FONT: &[u8] = include_bytes!("../fonts/DejaVuSerif.ttf");
+
+///Family name of the font, for SVG.
+pub(crate) static FAMILY: &str = "DejaVu Serif";
+
+///The parsed font, parsed on first use.
+#[cfg(feature = "png-pdf")]
+pub(crate) fn face() -> &'static Face<'static> {
+    static FACE: OnceLock<Face<'static>> = OnceLock::new();
+    FACE.get_or_init(|| Face::parse(FONT, 0).unwrap())
+}
+
+///The glyph for a character, or the "missing character" glyph.
+#[cfg(feature = "png-pdf")]
+pub(crate) fn glyph(face: &Face, c: char) -> GlyphId {
+    face.glyph_index(c).unwrap_or(GlyphId(0))
+}
+
+///Advance width of a glyph, in ems.
+#[cfg(feature = "png-pdf")]
+pub(crate) fn advance(face: &Face, glyph: GlyphId) -> f64 {
+    f64::from(face.glyph_hor_advance(glyph).unwrap_or(0)) / f64::from(face.units_per_em())
+}
+
+///Width of text set at a size.
+#[cfg(feature = "png-pdf")]
+pub(crate) fn text_width(text: &str, size: f64) -> f64 {
+    let face = face();
+    text.chars().map(|c| advance(face, glyph(face, c))).sum::<f64>() * size
+}
+
+///Estimated width of text set at a size in a common serif font.
+#[cfg(not(feature = "png-pdf"))]
+pub(crate) fn text_width(text: &str, size: f64) -> f64 {
+    let ems: f64 = text
+        .chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | 'í' | 't' | 'f' | 'r' | ' ' => 0.35,
+            c if c.is_uppercase() || c == 'm' || c == 'w' || c == 'æ' || c == 'œ' => 0.8,
+            c if c.is_alphabetic() => 0.6,
+            _ => 0.4,
+        })
+        .sum();
+    ems * size
+}
+
+///Text with any signs the font lacks written out: "℣" and "℟" as "V/" and "R/", and "✠" as "+".
+#[cfg(feature = "png-pdf")]
+pub(crate) fn printable(text: &str) -> String {
+    let face = face();
+    let mut result = String::new();
+    for c in text.chars() {
+        let spelled = match c {
+            '℣' => "V/",
+            '℟' => "R/",
+            '✠' => "+",
+            _ => "",
+        };
+        if !spelled.is_empty() && face.glyph_index(c).is_none() {
+            result.push_str(spelled);
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+///Text as it is drawn: without the font, in the viewer's font as written.
+#[cfg(not(feature = "png-pdf"))]
+pub(crate) fn printable(text: &str) -> String {
+    text.to_string()
+}
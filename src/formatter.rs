@@ -0,0 +1,345 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Canonical formatting of gabc files. The formatter works on the `GabcFile` model: header lines
+//! are written as "key: value;" in a fixed order, runs of whitespace in the text become single
+//! spaces, and the body is laid out with a line break after chosen barlines and wherever a line
+//! would grow too long. Line breaks only ever fall between words, so the text and music are
+//! unchanged. Formatting is idempotent: formatting formatted gabc gives the same text back.
+//!
+//! `%` comments are not part of the model, so `format()` cannot keep them, but `format_gabc()`
+//! reads the source's syntax tree (see the `cst` module) and keeps every comment. A comment in
+//! the header moves with the attribute on its line, or with the next attribute if it has a line
+//! of its own. In the body, a comment after music stays at the end of its line, and a comment on
+//! a line of its own stays on a line of its own; either way the next word starts a new line. In
+//! gabc the line break after a comment belongs to the text of the next syllable unless spaces come
+//! before the "%", so the formatter writes a space before a comment exactly when the syllable
+//! after it continues a word, and the words of the file are unchanged.
+
+use cst::Cst;
+use {GabcFile, NoteElem, Rule, Syllable};
+
+///Header attributes in canonical order; others follow them in their original order.
+static HEADER_ORDER: [&str; 20] = [
+    "name",
+    "gabc-copyright",
+    "score-copyright",
+    "office-part",
+    "occasion",
+    "meter",
+    "commentary",
+    "arranger",
+    "author",
+    "date",
+    "manuscript",
+    "manuscript-reference",
+    "manuscript-storage-place",
+    "book",
+    "language",
+    "transcriber",
+    "transcription-date",
+    "mode",
+    "annotation",
+    "user-notes",
+];
+
+///Options for `format()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    ///Attribute keys in the order they should appear in the header; attributes not listed come
+    ///after these, in their original order
+    pub header_order: Vec<String>,
+    ///Barlines (e.g. "::" or ";") after which a new line begins
+    pub break_after: Vec<String>,
+    ///Lines longer than this many characters are wrapped between words (0 for no wrapping);
+    ///a single word longer than this is left on a line of its own
+    pub max_width: usize,
+}
+
+impl Default for FormatOptions {
+    ///The Gregorio header order, a line break after every ":" and "::", and 80-character lines.
+    fn default() -> FormatOptions {
+        FormatOptions {
+            header_order: HEADER_ORDER.iter().map(|k| k.to_string()).collect(),
+            break_after: vec![":".to_string(), "::".to_string()],
+            max_width: 80,
+        }
+    }
+}
+
+///A word of the formatted body: syllables written without spaces between them.
+struct FormattedWord {
+    text: String,
+    ///Whether the word ends with one of the barlines that end a line
+    line_end: bool,
+    ///Whether the word continues the word before a comment, its text having no leading space
+    continues: bool,
+}
+
+///A header attribute with its comments.
+struct HeaderLine<'a> {
+    key: &'a str,
+    value: &'a str,
+    ///Comments on lines of their own before the attribute
+    comments: Vec<&'a str>,
+    ///Comment at the end of the attribute's line
+    trailing: Option<&'a str>,
+}
+
+///A syllable or comment of the body, in source order.
+enum Piece<'a, 'b> {
+    Syllable(&'b Syllable<'a>),
+    ///A comment, and whether it has a line of its own
+    Comment(&'a str, bool),
+}
+
+///A word or comment of the formatted body.
+enum Item<'a> {
+    Word(FormattedWord),
+    Comment(&'a str, bool),
+}
+
+///Format a `GabcFile` as canonical gabc.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::formatter::*;
+///let f = GabcFile::new("mode:8;
+///name:Test;
+///%%
+///(c4)  Al(gh)le(j)lu(jh)ia.(g.)   (::)
+///Al(gh)le(j)lu(jh)ia.(g.) (:) Al(gh)le(j)lu(jh)ia.(g.) (::)
+///");
+///let formatted = format(&f, &FormatOptions::default());
+///assert_eq!(formatted, "name: Test;
+///mode: 8;
+///%%
+///(c4) Al(gh)le(j)lu(jh)ia.(g.) (::)
+///Al(gh)le(j)lu(jh)ia.(g.) (:)
+///Al(gh)le(j)lu(jh)ia.(g.) (::)
+///");
+///assert_eq!(format(&GabcFile::new(&formatted), &FormatOptions::default()), formatted);
+///```
+pub fn format(file: &GabcFile, options: &FormatOptions) -> String {
+    let header = file
+        .attributes
+        .iter()
+        .map(|&(key, value)| HeaderLine {
+            key,
+            value,
+            comments: Vec::new(),
+            trailing: None,
+        })
+        .collect();
+    let pieces: Vec<Piece> = file.syllables.iter().map(Piece::Syllable).collect();
+    layout(header, &[], &pieces, options)
+}
+
+///Parse and format gabc source, keeping its comments, failing with pest's error message if it is
+///not valid gabc.
+///# Examples
+///```
+///# use gabc_parser::formatter::*;
+///let gabc = "mode:8; % from the Liber
+///% checked
+///name:Test;
+///%%
+///(c4)  Al(gh)le(j) % a comment
+///lu(jh)ia.(g.) (::)
+///% the end
+///";
+///assert_eq!(format_gabc(gabc, &FormatOptions::default()).unwrap(), "% checked
+///name: Test;
+///mode: 8; % from the Liber
+///%%
+///(c4) Al(gh)le(j) % a comment
+///lu(jh)ia.(g.) (::)
+///% the end
+///");
+///```
+pub fn format_gabc(gabc_input: &str, options: &FormatOptions) -> Result<String, String> {
+    let cst = Cst::parse(gabc_input)?;
+    let file = cst.gabc_file();
+    let source = cst.source();
+    let mut header: Vec<HeaderLine> = Vec::new();
+    //Comments on lines of their own, waiting for the attribute they come before
+    let mut pending: Vec<&str> = Vec::new();
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut syllables = file.syllables.iter();
+    let mut in_body = false;
+    let mut after_attribute = false;
+    for node in &cst.root().children {
+        let comment = match node.rule() {
+            Some(Rule::line_comment) => Some(node),
+            Some(Rule::newline) => node.children.iter().find(|c| c.rule() == Some(Rule::line_comment)),
+            _ => None,
+        }
+        .map(|c| c.text(source));
+        match node.rule() {
+            Some(Rule::attribute) => {
+                let (key, value) = file.attributes[header.len()];
+                header.push(HeaderLine {
+                    key,
+                    value,
+                    comments: std::mem::take(&mut pending),
+                    trailing: None,
+                });
+            }
+            Some(Rule::syllable) => pieces.push(Piece::Syllable(syllables.next().unwrap())),
+            None if node.text(source) == "%%" => in_body = true,
+            _ => {}
+        }
+        if let Some(comment) = comment {
+            if in_body {
+                let start = comment.as_ptr() as usize - source.as_ptr() as usize;
+                let own_line = source[..start].rsplit('\n').next().unwrap().trim().is_empty();
+                pieces.push(Piece::Comment(comment, own_line));
+            } else if after_attribute {
+                header.last_mut().unwrap().trailing = Some(comment);
+            } else {
+                pending.push(comment);
+            }
+        }
+        after_attribute = node.rule() == Some(Rule::attribute);
+    }
+    Ok(layout(header, &pending, &pieces, options))
+}
+
+///Write a formatted file: the header in canonical order, comments at the end of the header,
+///and the body.
+fn layout(mut header: Vec<HeaderLine>, header_end: &[&str], pieces: &[Piece], options: &FormatOptions) -> String {
+    let mut result = String::new();
+    let rank = |key: &str| options.header_order.iter().position(|k| k == key).unwrap_or(options.header_order.len());
+    header.sort_by_key(|a| rank(a.key.trim()));
+    for attribute in header {
+        for comment in attribute.comments {
+            result.push_str(comment);
+            result.push('\n');
+        }
+        result.push_str(&format!("{}: {};", attribute.key.trim(), attribute.value.trim()));
+        if let Some(comment) = attribute.trailing {
+            result.push(' ');
+            result.push_str(comment);
+        }
+        result.push('\n');
+    }
+    for comment in header_end {
+        result.push_str(comment);
+        result.push('\n');
+    }
+    result.push_str("%%\n");
+    let mut line = String::new();
+    //Whether the last line written holds music, so that a comment can follow it
+    let mut music_line = false;
+    let items = words(pieces, options);
+    for (i, item) in items.iter().enumerate() {
+        let word = match item {
+            &Item::Comment(comment, own_line) => {
+                let next_continues = matches!(items.get(i + 1), Some(Item::Word(w)) if w.continues);
+                if !next_continues && (own_line || (line.is_empty() && !music_line)) {
+                    if !line.is_empty() {
+                        result.push_str(&line);
+                        result.push('\n');
+                        line.clear();
+                    }
+                } else {
+                    //End the line of music with the comment
+                    if !line.is_empty() {
+                        result.push_str(&line);
+                        line.clear();
+                    } else if music_line {
+                        result.pop();
+                    }
+                    if next_continues {
+                        result.push(' ');
+                    }
+                }
+                result.push_str(comment);
+                result.push('\n');
+                music_line = false;
+                continue;
+            }
+            Item::Word(word) => word,
+        };
+        if !line.is_empty() {
+            let width = line.chars().count() + 1 + word.text.chars().count();
+            if options.max_width > 0 && width > options.max_width {
+                result.push_str(&line);
+                result.push('\n');
+                line.clear();
+            } else {
+                line.push(' ');
+            }
+        }
+        line.push_str(&word.text);
+        if word.line_end {
+            result.push_str(&line);
+            result.push('\n');
+            line.clear();
+            music_line = true;
+        }
+    }
+    if !line.is_empty() {
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+///Whether gabc source is already formatted, e.g. for a pre-commit check.
+///# Examples
+///```
+///# use gabc_parser::formatter::*;
+///let options = FormatOptions::default();
+///assert!(is_formatted("name: Test;\n%%\n(c4) A(g)men.(hg) (::)\n", &options).unwrap());
+///assert!(!is_formatted("name:Test;\n%%\n(c4) A(g)men.(hg) (::)\n", &options).unwrap());
+///assert!(is_formatted("name:Test;\n(c4) A(g)", &options).is_err());
+///```
+pub fn is_formatted(gabc_input: &str, options: &FormatOptions) -> Result<bool, String> {
+    Ok(format_gabc(gabc_input, options)? == gabc_input)
+}
+
+///Group the syllables of a body into words with normalized spacing. A word only ends where the
+///text has whitespace (or at a comment, which ends its line), so that breaking lines between
+///words cannot join or split sung words.
+fn words<'a>(pieces: &[Piece<'a, '_>], options: &FormatOptions) -> Vec<Item<'a>> {
+    let mut words: Vec<Item> = Vec::new();
+    for part in pieces {
+        let syllable = match part {
+            Piece::Syllable(s) => s,
+            Piece::Comment(comment, own_line) => {
+                words.push(Item::Comment(comment, *own_line));
+                continue;
+            }
+        };
+        let text = syllable.text;
+        let mut normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.ends_with(char::is_whitespace) && !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        let music: String = syllable.music.iter().map(|e| e.to_gabc()).collect();
+        let piece = format!("{}({})", normalized, music);
+        let line_end = match syllable.music.last() {
+            Some(NoteElem::Barline(b)) => options.break_after.iter().any(|x| x == b),
+            _ => false,
+        };
+        match words.last_mut() {
+            Some(Item::Word(w)) if !text.starts_with(char::is_whitespace) => {
+                w.text.push_str(&piece);
+                w.line_end = line_end;
+            }
+            Some(Item::Comment(..)) => words.push(Item::Word(FormattedWord {
+                text: piece,
+                line_end,
+                continues: !text.starts_with(char::is_whitespace),
+            })),
+            _ => words.push(Item::Word(FormattedWord {
+                text: piece,
+                line_end,
+                continues: false,
+            })),
+        }
+    }
+    words
+}
@@ -0,0 +1,135 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! HTML output: a document with the gabc header as a definition list, the score rendered as SVG
+//! by the `render` module, the text with its words hyphenated into syllables, and a table of the
+//! syllables with their notes. Each syllable of the text links to its row of the table, and both
+//! carry `data-start` and `data-end` attributes with the byte range of the syllable in the gabc
+//! source, so that scripts can point back to it. The document is also well-formed XML.
+//!
+//! The elements have classes starting with "gabc-" for styling; `HtmlOptions::css` adds a
+//! stylesheet for them to the document, which then needs no other files.
+
+use render::RenderOptions;
+use svg::to_svg;
+use {escape_xml, text_tokens, GabcFile, Syllabic};
+
+///Options for `GabcFile::as_html()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlOptions {
+    ///Layout of the rendered score
+    pub render: RenderOptions,
+    ///Whether to include a stylesheet in the document
+    pub css: bool,
+}
+
+impl Default for HtmlOptions {
+    ///The default layout, with the stylesheet.
+    fn default() -> HtmlOptions {
+        HtmlOptions {
+            render: RenderOptions::default(),
+            css: true,
+        }
+    }
+}
+
+///The stylesheet included by `HtmlOptions::css`: hovered or linked syllables are highlighted.
+const CSS: &str = "\
+.gabc { font-family: \"DejaVu Serif\", serif; max-width: 50em; margin: auto; }
+.gabc-header { display: grid; grid-template-columns: max-content auto; gap: 0.2em 1em; }
+.gabc-header dt { font-weight: bold; }
+.gabc-header dd { margin: 0; }
+.gabc-score svg { max-width: 100%; height: auto; }
+.gabc-text a { color: inherit; text-decoration: none; }
+.gabc-rubric { color: #c00000; }
+.gabc-syllables { border-collapse: collapse; }
+.gabc-syllables th, .gabc-syllables td { padding: 0.1em 0.6em; text-align: left; }
+.gabc-text a:hover, .gabc-syllables tr:hover, .gabc-syllables tr:target { background: #fff3c4; }
+";
+
+///Translate a `GabcFile` into an HTML document.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::html::*;
+///let s = "name:Test;
+///%%
+///(c4) Al(gh)le(j) *(;)";
+///let html = GabcFile::new(s).as_html(&HtmlOptions::default());
+///assert!(html.contains("<dt>name</dt><dd>Test</dd>"));
+///assert!(html.contains("<a class=\"gabc-syllable\" href=\"#gabc-syllable-1\" data-start=\"18\" \
+///data-end=\"25\">Al-</a><a class=\"gabc-syllable\" href=\"#gabc-syllable-2\" data-start=\"25\" \
+///data-end=\"30\">le</a> <a"));
+///assert!(html.contains("<tr id=\"gabc-syllable-1\" data-start=\"18\" data-end=\"25\"><td>1</td>\
+///<td>Al</td><td><code>gh</code></td></tr>"));
+///assert_eq!(&s[18..25], " Al(gh)");
+///```
+pub fn to_html(file: &GabcFile, options: &HtmlOptions) -> String {
+    let name = file.attributes.iter().find(|(k, _)| k.trim() == "name").map_or("gabc", |(_, v)| v.trim());
+    let mut result = String::from("<!DOCTYPE html>\n<html lang=\"la\">\n<head>\n<meta charset=\"utf-8\"/>\n");
+    result.push_str(&format!("<title>{}</title>\n", escape_xml(name)));
+    if options.css {
+        result.push_str(&format!("<style>\n{}</style>\n", CSS));
+    }
+    result.push_str("</head>\n<body>\n<article class=\"gabc\">\n<dl class=\"gabc-header\">\n");
+    for (key, value) in &file.attributes {
+        result.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", escape_xml(key.trim()), escape_xml(value.trim())));
+    }
+    result.push_str("</dl>\n<figure class=\"gabc-score\">\n");
+    let svg = to_svg(file, &options.render);
+    result.push_str(svg.split_once('\n').map_or(svg.as_str(), |(_, svg)| svg));
+    result.push_str("</figure>\n<p class=\"gabc-text\">");
+    let lyrics = file.syllable_lyrics();
+    let mut space = false;
+    let mut first = true;
+    let mut rows = String::new();
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let (leading, tokens, trailing) = text_tokens(syllable.text);
+        let data = format!("data-start=\"{}\" data-end=\"{}\"", syllable.span.start, syllable.span.end);
+        let music: String = syllable.music.iter().map(|e| e.to_gabc()).collect();
+        let plain = tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>().join(" ");
+        rows.push_str(&format!(
+            "<tr id=\"gabc-syllable-{}\" {}><td>{}</td><td>{}</td><td><code>{}</code></td></tr>\n",
+            i,
+            data,
+            i,
+            escape_xml(&plain),
+            escape_xml(&music)
+        ));
+        if tokens.is_empty() {
+            space |= leading || trailing;
+            continue;
+        }
+        if !first && (space || leading) {
+            result.push(' ');
+        }
+        first = false;
+        let text: Vec<String> = tokens
+            .iter()
+            .map(|t| {
+                if t.rubric {
+                    format!("<span class=\"gabc-rubric\">{}</span>", escape_xml(&t.text))
+                } else {
+                    escape_xml(&t.text)
+                }
+            })
+            .collect();
+        let hyphen = match lyrics[i] {
+            Some((_, Syllabic::Begin)) | Some((_, Syllabic::Middle)) => "-",
+            _ => "",
+        };
+        result.push_str(&format!(
+            "<a class=\"gabc-syllable\" href=\"#gabc-syllable-{}\" {}>{}{}</a>",
+            i,
+            data,
+            text.join(" "),
+            hyphen
+        ));
+        space = trailing;
+    }
+    result.push_str("</p>\n<table class=\"gabc-syllables\">\n<thead><tr><th>#</th><th>Text</th><th>Notes</th></tr></thead>\n<tbody>\n");
+    result.push_str(&rows);
+    result.push_str("</tbody>\n</table>\n</article>\n</body>\n</html>\n");
+    result
+}
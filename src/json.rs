@@ -0,0 +1,216 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! The JSON format of `GabcFile::as_json()` and `GabcFile::from_json()`: an owned copy of a
+//! file's attributes and syllables, with a `version` field that changes only when the format
+//! does, so that programs written in other languages can read and edit chants. The JSON Schema
+//! of the format is in gabc.schema.json at the root of this repository; `schema()` generates it
+//! with the "schema" feature.
+//!
+//! A file looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "attributes": [{"key": "name", "value": "Test"}],
+//!   "syllables": [
+//!     {"text": "", "music": [{"type": "clef", "value": "c4"}]},
+//!     {"text": " Al", "music": [
+//!       {"type": "note", "prefix": "", "position": "g", "suffix": ""},
+//!       {"type": "spacer", "value": "/"},
+//!       {"type": "note", "prefix": "", "position": "h", "suffix": "."}
+//!     ]},
+//!     {"text": " ", "music": [{"type": "barline", "value": "::"}]}
+//!   ]
+//! }
+//! ```
+//!
+//! Text, keys and values are kept exactly as written in the gabc, spaces included.
+
+use pest::Parser;
+#[cfg(feature = "schema")]
+use schemars::{schema_for, JsonSchema};
+use serde_json;
+use {GABCParser, GabcFile, NoteElem, Rule};
+
+///Version of the JSON format written by this library.
+pub const VERSION: u32 = 1;
+
+///A gabc file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "schema", schemars(title = "gabc file"))]
+pub struct Document {
+    ///Version of the format; 1 for this one
+    #[cfg_attr(feature = "schema", schemars(range(min = 1, max = 1)))]
+    pub version: u32,
+    ///The header of the file, in order
+    pub attributes: Vec<Attribute>,
+    ///The body of the file, in order
+    pub syllables: Vec<Syllable>,
+}
+
+///A header attribute, e.g. "name: Populus Sion;".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Attribute {
+    ///The name of the attribute, before the colon
+    pub key: String,
+    ///The value of the attribute, between the colon and the semicolon
+    pub value: String,
+}
+
+///A syllable: its text and the music in the parentheses after it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Syllable {
+    ///The text, with any spaces before it and any markup (e.g. "<i>")
+    pub text: String,
+    ///The elements of the music, in order
+    pub music: Vec<Element>,
+}
+
+///An element of the music of a syllable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Element {
+    ///A note, e.g. "-gw"
+    Note {
+        ///"-" for an initio debilis, or ""
+        #[serde(default)]
+        #[cfg_attr(feature = "schema", schemars(regex(pattern = r"^-*$")))]
+        prefix: String,
+        ///The staff position, from "a" to "m", in upper case for a punctum inclinatum
+        #[cfg_attr(feature = "schema", schemars(regex(pattern = r"^[a-mA-M]$")))]
+        position: String,
+        ///The signs after the position, e.g. "." or "~", or "x" for a flat sign
+        #[serde(default)]
+        suffix: String,
+    },
+    ///A spacer, e.g. "/" or "!"
+    Spacer {
+        value: String,
+    },
+    ///A division, e.g. ";" or "::"
+    Barline {
+        value: String,
+    },
+    ///A clef, e.g. "c4"
+    Clef {
+        value: String,
+    },
+}
+
+impl<'a> From<&GabcFile<'a>> for Document {
+    fn from(file: &GabcFile<'a>) -> Document {
+        Document {
+            version: VERSION,
+            attributes: file
+                .attributes
+                .iter()
+                .map(|(k, v)| Attribute {
+                    key: k.to_string(),
+                    value: v.to_string(),
+                })
+                .collect(),
+            syllables: file
+                .syllables
+                .iter()
+                .map(|s| Syllable {
+                    text: s.text.to_string(),
+                    music: s.music.iter().map(element).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+///The owned copy of an element of the music.
+fn element(elem: &NoteElem) -> Element {
+    match elem {
+        NoteElem::Note(n) => Element::Note {
+            prefix: n.prefix.to_string(),
+            position: n.position.to_string(),
+            suffix: n.suffix.to_string(),
+        },
+        NoteElem::Spacer(s) => Element::Spacer { value: s.to_string() },
+        NoteElem::Barline(b) => Element::Barline { value: b.to_string() },
+        NoteElem::Clef(c) => Element::Clef { value: c.to_string() },
+    }
+}
+
+impl Document {
+    ///Write this document as gabc, in the form of `GabcFile::to_gabc()`.
+    pub fn to_gabc(&self) -> String {
+        let mut result = String::new();
+        for a in &self.attributes {
+            result.push_str(&format!("{}:{};\n", a.key, a.value));
+        }
+        result.push_str("%%\n");
+        for syllable in &self.syllables {
+            result.push_str(&syllable.text);
+            result.push('(');
+            for elem in &syllable.music {
+                match elem {
+                    Element::Note { prefix, position, suffix } => {
+                        result.push_str(prefix);
+                        result.push_str(position);
+                        result.push_str(suffix);
+                    }
+                    Element::Spacer { value } | Element::Barline { value } | Element::Clef { value } => {
+                        result.push_str(value)
+                    }
+                }
+            }
+            result.push(')');
+        }
+        result.push('\n');
+        result
+    }
+}
+
+///Read a document in the JSON format. It is an error if the JSON does not match the schema, if
+///its version is not one this library reads, or if it does not describe valid gabc: e.g. a note
+///must have a single staff position, and text cannot contain parentheses.
+pub fn from_json(json: &str) -> Result<Document, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    match value.get("version").and_then(|v| v.as_u64()) {
+        Some(v) if v != u64::from(VERSION) => return Err(format!("unsupported JSON format version: {}", v)),
+        Some(_) => {}
+        None => return Err("missing JSON format version".to_string()),
+    }
+    let document: Document = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    let gabc = document.to_gabc();
+    GABCParser::parse(Rule::file, &gabc).map_err(|e| format!("invalid gabc: {}", e))?;
+    //Reading the gabc must give the same elements, or the JSON did not describe them correctly
+    let read = Document::from(&GabcFile::new(&gabc));
+    if read.attributes != document.attributes {
+        return Err("invalid gabc header".to_string());
+    }
+    if let Some(i) = read.syllables.iter().zip(&document.syllables).position(|(a, b)| a != b) {
+        return Err(format!("invalid gabc in syllable {} ({:?})", i, document.syllables[i].text));
+    }
+    if read != document {
+        return Err("invalid gabc".to_string());
+    }
+    Ok(document)
+}
+
+///Read a document in the JSON format straight into gabc: `from_json()` followed by
+///`Document::to_gabc()`.
+pub fn gabc_from_json(json: &str) -> Result<String, String> {
+    from_json(json).map(|document| document.to_gabc())
+}
+
+///The JSON Schema of the format, as pretty-printed JSON. Needs the "schema" feature.
+#[cfg(feature = "schema")]
+pub fn schema() -> String {
+    let mut schema = serde_json::to_string_pretty(&schema_for!(Document)).unwrap();
+    schema.push('\n');
+    schema
+}
@@ -0,0 +1,134 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Humdrum **kern output, with the syllables in a **text spine beside the music. Chant is written
+//! without a meter in the treble clef, at the pitch of `GabcFile::as_abc()` (an octave above
+//! `GabcFile::as_lilypond()`): eighth notes, or quarter notes under a punctum mora, with a slur
+//! over each neume. Flats and sharps are written on every note they alter, and a natural on the
+//! first note after a natural sign. Divisions are barlines: `==` for a double bar, `=` for a full
+//! bar, a partial barline `='` for a half bar and a tick `` =` `` for quarter bars and virgulae.
+//! Words are hyphenated Humdrum-style ("Al-", "-le-", "-lu-", "-ia"), and spaces inside a
+//! syllable become no-break spaces.
+//!
+//! The gabc attributes "name", "office-part", "mode", "author", "book" and "transcriber" become
+//! the reference records `!!!OTL`, `!!!AGN`, `!!!AMD`, `!!!COM`, `!!!SMS` and `!!!ENC`; other
+//! attributes are kept as global comments.
+
+use events::{events, pitch_name, Event};
+use {GabcFile, Syllabic};
+
+///Reference records written from gabc attributes.
+static RECORDS: [(&str, &str); 6] = [
+    ("OTL", "name"),
+    ("AGN", "office-part"),
+    ("AMD", "mode"),
+    ("COM", "author"),
+    ("SMS", "book"),
+    ("ENC", "transcriber"),
+];
+
+///Translate a `GabcFile` into a Humdrum file with a **kern spine and a **text spine.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("name:Test;
+///office-part:Antiphona;
+///mode:8;
+///%%
+///(c4) Al(gh)le(j)lu(jh/ixi)ia.(g.) (::)");
+///let kern = f.as_kern();
+///assert!(kern.starts_with("!!!OTL: Test\n!!!AGN: Antiphona\n!!!AMD: 8\n"));
+///assert!(kern.ends_with("
+///**kern\t**text
+///*clefG2\t*
+///(8g\tAl-
+///8a)\t.
+///8cc\t-le-
+///(8cc\t-lu-
+///8a)\t.
+///8b-\t.
+///4g\t-ia.
+///==\t==
+///*-\t*-
+///"));
+///```
+pub fn to_kern(file: &GabcFile) -> String {
+    let mut result = String::new();
+    for (key, value) in &file.attributes {
+        let (key, value) = (key.trim(), value.trim());
+        match RECORDS.iter().find(|r| r.1 == key) {
+            Some((record, _)) => result.push_str(&format!("!!!{}: {}\n", record, value)),
+            None => result.push_str(&format!("!! {}: {}\n", key, value)),
+        }
+    }
+    result.push_str("**kern\t**text\n*clefG2\t*\n");
+    let lyrics = file.syllable_lyrics();
+    let events = events(file);
+    let mut previous_syllable = None;
+    let mut started = false;
+    for (k, event) in events.iter().enumerate() {
+        match event {
+            Event::Note(n) => {
+                let next_joined = matches!(events.get(k + 1), Some(Event::Note(next)) if next.joined);
+                let mut token = String::new();
+                if !n.joined && next_joined {
+                    token.push('(');
+                }
+                token.push_str(if n.morae > 0 { "4" } else { "8" });
+                token.push_str(&kern_pitch(n.pitch));
+                token.push_str(match (n.alter, n.accidental) {
+                    (-1, _) => "-",
+                    (1, _) => "#",
+                    (_, true) => "n",
+                    _ => "",
+                });
+                if n.joined && !next_joined {
+                    token.push(')');
+                }
+                let text = match &lyrics[n.syllable] {
+                    Some((text, syllabic)) if previous_syllable != Some(n.syllable) => {
+                        //Spaces separate subtokens in Humdrum
+                        let text = text.replace(' ', "\u{a0}");
+                        match syllabic {
+                            Syllabic::Single => text,
+                            Syllabic::Begin => format!("{}-", text),
+                            Syllabic::Middle => format!("-{}-", text),
+                            Syllabic::End => format!("-{}", text),
+                        }
+                    }
+                    _ => ".".to_string(),
+                };
+                previous_syllable = Some(n.syllable);
+                started = true;
+                result.push_str(&format!("{}\t{}\n", token, text));
+            }
+            Event::Barline(bar) => {
+                if !started {
+                    continue;
+                }
+                let bar = match *bar {
+                    "::" => "==",
+                    "," | ",_" | ",0" | "'" | "`" => "=`",
+                    b if b.starts_with(';') => "='",
+                    _ => "=",
+                };
+                result.push_str(&format!("{}\t{}\n", bar, bar));
+            }
+        }
+    }
+    result.push_str("*-\t*-\n");
+    result
+}
+
+///The **kern note name of a pitch, an octave above its Lilypond (untransposed) pitch: "c" is
+///middle C, with letters repeated for each octave above ("cc") or capitals below ("B", "BB").
+fn kern_pitch(pitch: usize) -> String {
+    let (letter, octave) = pitch_name(pitch);
+    let octave = octave + 1;
+    if octave >= 4 {
+        letter.to_string().repeat(octave as usize - 3)
+    } else {
+        letter.to_ascii_uppercase().to_string().repeat(4 - octave as usize)
+    }
+}
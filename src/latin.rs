@@ -0,0 +1,317 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Latin syllabification and accentuation following ecclesiastical usage, for preparing new text
+//! to pair with gabc music. Syllables divide before a single consonant and inside consonant
+//! clusters (keeping a mute followed by a liquid, and "gn", with the following vowel); "qu",
+//! "gu" after "n", consonantal "i", the diphthongs ae/oe/au (æ/œ) and "ui" in "cui" and "huic"
+//! are handled.
+//!
+//! The tonic accent is taken from an acute accent when the text has one. Otherwise disyllables
+//! are accented on the first syllable, and longer words on the penultimate syllable if it is long
+//! by position or diphthong, by a common ending (e.g. "-áre", "-órum") or by a small dictionary,
+//! and on the antepenultimate syllable if not. Vowel length by nature cannot be known in general,
+//! so unusual words may need an acute accent in the input.
+
+///A Latin word divided into syllables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    ///Syllables of the word in order; punctuation stays attached to the first or last syllable
+    pub syllables: Vec<String>,
+    ///Index of the syllable carrying the tonic accent (`None` for monosyllables and words
+    ///without vowels, such as "*" or "2.")
+    pub accent: Option<usize>,
+}
+
+impl Word {
+    ///Syllabify and accent a single word.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::latin::*;
+    ///let w = Word::new("Dominus,");
+    ///assert_eq!(w.syllables, vec!["Do", "mi", "nus,"]);
+    ///assert_eq!(w.accent, Some(0));
+    ///assert_eq!(Word::new("laudate").accent, Some(1));
+    ///assert_eq!(Word::new("sanguis").syllables, vec!["san", "guis"]);
+    ///assert_eq!(Word::new("quóniam").syllables, vec!["quó", "ni", "am"]);
+    ///assert_eq!(Word::new("eius").syllables, vec!["e", "ius"]);
+    ///assert_eq!(Word::new("cælum").syllables, vec!["cæ", "lum"]);
+    ///assert_eq!(Word::new("magnus").syllables, vec!["ma", "gnus"]);
+    ///assert_eq!(Word::new("Patris").syllables, vec!["Pa", "tris"]);
+    ///```
+    pub fn new(word: &str) -> Word {
+        let chars: Vec<char> = word.chars().collect();
+        let start = chars.iter().position(|c| c.is_alphabetic()).unwrap_or(chars.len());
+        let end = chars.iter().rposition(|c| c.is_alphabetic()).map_or(start, |e| e + 1);
+        let units = units(&chars[start..end]);
+        if !units.iter().any(|u| u.vowel) {
+            return Word {
+                syllables: vec![word.to_string()],
+                accent: None,
+            };
+        }
+        let mut syllables = divide(&units);
+        let accent = accent(&syllables, &chars[start..end]);
+        //Reattach surrounding punctuation
+        let leading: String = chars[..start].iter().collect();
+        let trailing: String = chars[end..].iter().collect();
+        syllables[0].insert_str(0, &leading);
+        syllables.last_mut().unwrap().push_str(&trailing);
+        Word { syllables, accent }
+    }
+    ///Syllables with an acute accent added to the tonic syllable of polysyllables that do not
+    ///already have one, as in pointed liturgical texts.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::latin::*;
+    ///assert_eq!(Word::new("Dominus").pointed(), vec!["Dó", "mi", "nus"]);
+    ///assert_eq!(Word::new("cæli").pointed(), vec!["cǽ", "li"]);
+    ///```
+    pub fn pointed(&self) -> Vec<String> {
+        let mut result = self.syllables.clone();
+        if let Some(a) = self.accent {
+            if !result[a].chars().any(is_acute) {
+                result[a] = add_acute(&result[a]);
+            }
+        }
+        result
+    }
+}
+
+///Divide text into words and syllabify them.
+///# Examples
+///```
+///# use gabc_parser::latin::*;
+///let w = words("Salve, Regina");
+///assert_eq!(w.len(), 2);
+///assert_eq!(w[1].syllables, vec!["Re", "gi", "na"]);
+///assert_eq!(w[1].accent, Some(1));
+///```
+pub fn words(text: &str) -> Vec<Word> {
+    text.split_whitespace().map(Word::new).collect()
+}
+
+///Divide text into syllables in the form used by `Syllable::text`: every syllable beginning a
+///word after the first carries a leading space.
+///# Examples
+///```
+///# use gabc_parser::latin::*;
+///assert_eq!(gabc_syllables("Populus Sion"), vec!["Po", "pu", "lus", " Si", "on"]);
+///```
+pub fn gabc_syllables(text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    for (i, word) in words(text).into_iter().enumerate() {
+        for (j, s) in word.syllables.into_iter().enumerate() {
+            if i > 0 && j == 0 {
+                result.push(format!(" {}", s));
+            } else {
+                result.push(s);
+            }
+        }
+    }
+    result
+}
+
+///A vowel nucleus or consonant of a word: one or more characters acting as a single sound.
+#[derive(Debug, Clone)]
+struct Unit {
+    text: String,
+    vowel: bool,
+}
+
+///Split the letters of a word into vowel and consonant units.
+fn units(chars: &[char]) -> Vec<Unit> {
+    let lower: Vec<char> = chars.iter().map(|c| base_letter(*c)).collect();
+    let is_vowel_at = |i: usize| i < lower.len() && is_vowel(lower[i]);
+    //"ui" is a diphthong only in these monosyllables
+    let ui_diphthong = matches!(lower.iter().collect::<String>().as_str(), "cui" | "huic");
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = lower[i];
+        let next = if i + 1 < lower.len() { lower[i + 1] } else { ' ' };
+        let consonant_pair = (c == 'q' && next == 'u')
+            || (c == 'g' && next == 'u' && i > 0 && lower[i - 1] == 'n' && is_vowel_at(i + 2))
+            || matches!((c, next), ('c', 'h') | ('p', 'h') | ('t', 'h') | ('r', 'h'));
+        let (len, vowel) = if consonant_pair {
+            (2, false)
+        } else if c == 'i' && !is_acute(chars[i]) && (i == 0 || is_vowel_at(i - 1)) && is_vowel_at(i + 1) {
+            (1, false)
+        } else if is_vowel(c) {
+            let diphthong = (matches!((c, next), ('a', 'e') | ('o', 'e') | ('a', 'u'))
+                || (ui_diphthong && (c, next) == ('u', 'i')))
+                && !is_acute(chars[i + 1])
+                && chars[i + 1] != 'ë';
+            (if diphthong { 2 } else { 1 }, true)
+        } else {
+            (1, false)
+        };
+        result.push(Unit {
+            text: chars[i..i + len].iter().collect(),
+            vowel,
+        });
+        i += len;
+    }
+    result
+}
+
+///Group units into syllables, dividing the consonants between vowels.
+fn divide(units: &[Unit]) -> Vec<String> {
+    let nuclei: Vec<usize> = (0..units.len()).filter(|&i| units[i].vowel).collect();
+    let mut syllables = Vec::new();
+    let mut start = 0;
+    for (n, &nucleus) in nuclei.iter().enumerate() {
+        let end = match nuclei.get(n + 1) {
+            None => units.len(),
+            Some(&next) => {
+                let cluster = &units[nucleus + 1..next];
+                let onset = match cluster.len() {
+                    0 => 0,
+                    1 => 1,
+                    _ if is_inseparable(&cluster[cluster.len() - 2], &cluster[cluster.len() - 1]) => 2,
+                    _ => 1,
+                };
+                next - onset
+            }
+        };
+        syllables.push(units[start..end].iter().map(|u| u.text.as_str()).collect());
+        start = end;
+    }
+    syllables
+}
+
+///Whether two consonants always begin a syllable together (a mute followed by a liquid, or "gn").
+fn is_inseparable(a: &Unit, b: &Unit) -> bool {
+    let a = a.text.to_lowercase();
+    let b = b.text.to_lowercase();
+    let mute = matches!(a.as_str(), "b" | "c" | "d" | "g" | "p" | "t" | "f" | "ch" | "ph" | "th");
+    (mute && (b == "l" || b == "r")) || (a == "g" && b == "n")
+}
+
+///Words of three or more syllables whose penultimate syllable is long by nature, given as
+///(unaccented lower-case word, accented syllable counted from the end).
+static DICTIONARY: &[(&str, usize)] = &[
+    ("alleluia", 2),
+    ("maria", 2),
+    ("mariae", 2),
+    ("mariam", 2),
+    ("regina", 2),
+    ("reginae", 2),
+    ("divina", 2),
+    ("divinum", 2),
+    ("ruina", 2),
+    ("virtute", 2),
+    ("virtutis", 2),
+    ("virtutem", 2),
+    ("salute", 2),
+    ("salutis", 2),
+    ("salutem", 2),
+    ("amore", 2),
+    ("honore", 2),
+    ("timore", 2),
+    ("dolore", 2),
+    ("odore", 2),
+    ("labore", 2),
+    ("iudicare", 2),
+    ("ierusalem", 3),
+];
+
+///Endings whose penultimate syllable is long.
+static LONG_PENULT_ENDINGS: &[&str] = &[
+    "are", "ari", "aris", "arum", "ate", "ati", "atis", "atus", "ata", "atum", "ator", "amus",
+    "ando", "andi", "orum", "osus", "osa", "osum", "ura", "urus", "ione", "ionis", "iones",
+    "ionem", "ire", "ibam",
+];
+
+///Find the tonic syllable of a syllabified word (without surrounding punctuation).
+fn accent(syllables: &[String], letters: &[char]) -> Option<usize> {
+    if let Some(i) = syllables.iter().position(|s| s.chars().any(is_acute)) {
+        return Some(i);
+    }
+    let count = syllables.len();
+    let word: String = letters.iter().map(|c| base_letter(*c)).collect();
+    match count {
+        1 => None,
+        2 => Some(0),
+        _ => {
+            if let Some(&(_, from_end)) = DICTIONARY.iter().find(|d| d.0 == word) {
+                return Some(count - from_end);
+            }
+            let penult = &syllables[count - 2];
+            let last = syllables[count - 1].to_lowercase();
+            let penult_lower = penult.to_lowercase();
+            let long = penult_lower.ends_with(|c: char| !is_vowel(base_letter(c)))
+                || penult_lower.contains("ae")
+                || penult_lower.contains("oe")
+                || penult_lower.contains("au")
+                || penult_lower.contains('æ')
+                || penult_lower.contains('œ')
+                || last.starts_with('x')
+                || last.starts_with('z')
+                || last.starts_with("gn")
+                || LONG_PENULT_ENDINGS.iter().any(|e| word.ends_with(e) && word.len() > e.len() + 1);
+            Some(if long { count - 2 } else { count - 3 })
+        }
+    }
+}
+
+///Lower-case letter with any acute accent or diaeresis removed.
+fn base_letter(c: char) -> char {
+    match c {
+        'á' | 'Á' | 'ä' => 'a',
+        'é' | 'É' | 'ë' | 'Ë' => 'e',
+        'í' | 'Í' | 'ï' => 'i',
+        'ó' | 'Ó' | 'ö' => 'o',
+        'ú' | 'Ú' | 'ü' => 'u',
+        'ý' | 'Ý' => 'y',
+        'ǽ' | 'Ǽ' | 'Æ' => 'æ',
+        'Œ' => 'œ',
+        'j' | 'J' => 'j',
+        _ => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+///Whether a (base) letter is a vowel.
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y' | 'æ' | 'œ')
+}
+
+///Whether a character carries an acute accent.
+pub(crate) fn is_acute(c: char) -> bool {
+    matches!(c, 'á' | 'é' | 'í' | 'ó' | 'ú' | 'ý' | 'ǽ' | 'Á' | 'É' | 'Í' | 'Ó' | 'Ú' | 'Ý' | 'Ǽ' | '\u{301}')
+}
+
+///Put an acute accent on the first vowel of a syllable.
+fn add_acute(syllable: &str) -> String {
+    let mut result = String::new();
+    let mut done = false;
+    for c in syllable.chars() {
+        if done || !is_vowel(base_letter(c)) {
+            result.push(c);
+            continue;
+        }
+        done = true;
+        match c {
+            'a' => result.push('á'),
+            'e' => result.push('é'),
+            'i' => result.push('í'),
+            'o' => result.push('ó'),
+            'u' => result.push('ú'),
+            'y' => result.push('ý'),
+            'æ' => result.push('ǽ'),
+            'A' => result.push('Á'),
+            'E' => result.push('É'),
+            'I' => result.push('Í'),
+            'O' => result.push('Ó'),
+            'U' => result.push('Ú'),
+            'Y' => result.push('Ý'),
+            'Æ' => result.push('Ǽ'),
+            _ => {
+                result.push(c);
+                result.push('\u{301}');
+            }
+        }
+    }
+    result
+}
@@ -9,16 +9,52 @@
 
 extern crate itertools;
 extern crate pest;
+extern crate roxmltree;
 #[macro_use]
 extern crate pest_derive;
 extern crate serde;
 extern crate serde_json;
+#[cfg(feature = "png-pdf")]
+extern crate tiny_skia;
+#[cfg(feature = "png-pdf")]
+extern crate ttf_parser;
+#[cfg(feature = "png-pdf")]
+extern crate miniz_oxide;
+#[cfg(feature = "schema")]
+extern crate schemars;
 #[macro_use]
 extern crate serde_derive;
 
 use itertools::Itertools;
 use pest::iterators::Pairs;
 use pest::Parser;
+use std::ops::Range;
+
+pub mod abc;
+pub mod cst;
+mod events;
+mod font;
+pub mod formatter;
+pub mod html;
+pub mod json;
+pub mod kern;
+pub mod latin;
+pub mod mei;
+pub mod midi;
+pub mod musicxml;
+#[cfg(feature = "png-pdf")]
+pub mod pdf;
+#[cfg(feature = "png-pdf")]
+pub mod png;
+pub mod psalm;
+pub mod render;
+pub mod search;
+pub mod similarity;
+pub mod svg;
+pub mod table;
+pub mod terminal;
+pub mod underlay;
+pub mod volpiano;
 
 //-----------------------------------------------------------------------
 //Pest boilerplate from the book (https://pest-parser.github.io/book/)
@@ -56,7 +92,7 @@ pub struct GABCParser;
 
 //-----------------------------------------------------------------------
 
-///Struct representing a gabc note.
+///Struct representing a gabc note. Notes are compared by content: their spans are ignored.
 #[derive(Debug, Serialize)]
 pub struct Note<'a> {
     ///Entire prefix of the note (usually empty)
@@ -67,6 +103,19 @@ pub struct Note<'a> {
     pub suffix: &'a str,
     ///Clef governing this note in its original context
     pub current_clef: &'a str,
+    ///Byte range of this note in the parsed gabc input
+    pub span: Range<usize>,
+}
+
+//The same note read from differently laid out gabc (e.g. with comments or CRLF line endings)
+//has a different span
+impl<'a> PartialEq for Note<'a> {
+    fn eq(&self, other: &Note) -> bool {
+        self.prefix == other.prefix
+            && self.position == other.position
+            && self.suffix == other.suffix
+            && self.current_clef == other.current_clef
+    }
 }
 
 impl<'a> Note<'a> {
@@ -82,7 +131,8 @@ impl<'a> Note<'a> {
     ///```
     pub fn new<'b>(gabc_input: &'b str, current_clef: &'b str) -> Note<'b> {
         let mut parse_result = parse_gabc(gabc_input, Rule::note);
-        parsed_note_to_struct(parse_result.next().unwrap(), current_clef)
+        let node = cst::Node::from_pair(parse_result.next().unwrap());
+        note_from_node(gabc_input, &node, current_clef)
     }
     ///Get the absolute pitch of this note in modern (Lilypond) notation, between a, and a'''.
     ///Assumes that the clef indicates middle C or the F above middle C.
@@ -97,25 +147,104 @@ impl<'a> Note<'a> {
             "a,", "b,", "c", "d", "e", "f", "g", "a", "b", "c'", "d'", "e'", "f'", "g'", "a'",
             "b'", "c''", "d''", "e''", "f''", "g''", "a'''",
         ];
-        let start_index = match self.current_clef {
-            "c1" => 6,
-            "c2" => 4,
-            "c3" => 2,
-            "c4" => 0,
-            "f1" => 9,
-            "f2" => 7,
-            "f3" => 5,
-            "f4" => 3,
-            x => panic!("invalid clef: {}", x),
-        };
+        match self.pitch_index() {
+            Some(i) => ly_notes.get(i).unwrap(),
+            None => panic!("invalid clef: {}", self.current_clef),
+        }
+    }
+    ///Get the pitch of this note as a number of diatonic steps above a, (the lowest pitch
+    ///returned by `absolute_pitch()`), or `None` if the current clef is not recognized.
+    ///The difference between two pitch indexes is the diatonic interval between the notes,
+    ///whatever their clefs.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///assert_eq!(Note::new("d", "c1").pitch_index(), Some(9));
+    ///assert_eq!(Note::new("j", "c4").pitch_index(), Some(9));
+    ///assert_eq!(Note::new("d", "no clef set").pitch_index(), None);
+    ///```
+    pub fn pitch_index(&self) -> Option<usize> {
+        let start_index = clef_offset(self.current_clef)?;
         let position = self.position.to_lowercase().next().unwrap() as usize - 'a' as usize;
-        assert!(position < ly_notes.len());
-        return ly_notes.get(position + start_index).unwrap();
+        Some(position + start_index)
+    }
+    ///Get the Lilypond representation of this note: its absolute pitch, with the gregorian.ly
+    ///commands for its shape and rhythmic signs. A virga ("v" or "V") becomes `\virga`, a
+    ///quilisma ("w") `\quilisma`, an oriscus ("o" or "O") `\oriscus`, a liquescent "~"
+    ///`\deminutum` and "<" or ">" `\auctum` with `\ascendens` or `\descendens`; each punctum
+    ///mora is an `\augmentum` dot, and a vertical episema ("'") is an `\ictus`. Horizontal
+    ///episemata span notes, so they are written by `Syllable::ly_notes()`.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///assert_eq!(Note::new("h", "c1").to_ly(), "g'");
+    ///assert_eq!(Note::new("h..", "c1").to_ly(), "\\augmentum \\augmentum g'");
+    ///assert_eq!(Note::new("gv'", "c4").to_ly(), "\\virga g\\ictus");
+    ///assert_eq!(Note::new("fw", "c4").to_ly(), "\\quilisma f");
+    ///assert_eq!(Note::new("h<", "c4").to_ly(), "\\auctum \\ascendens a");
+    ///```
+    pub fn to_ly(&self) -> String {
+        let mut result = String::new();
+        let shapes = [
+            ("vV", "\\virga "),
+            ("w", "\\quilisma "),
+            ("oO", "\\oriscus "),
+            ("~", "\\deminutum "),
+            ("<", "\\auctum \\ascendens "),
+            (">", "\\auctum \\descendens "),
+        ];
+        for (signs, command) in shapes.iter() {
+            if self.suffix.contains(|c| signs.contains(c)) {
+                result.push_str(command);
+            }
+        }
+        //`\augmentum` is a music function, so it comes after the overrides for the shape
+        for _ in self.suffix.matches('.') {
+            result.push_str("\\augmentum ");
+        }
+        result.push_str(self.absolute_pitch());
+        if self.suffix.contains('\'') {
+            result.push_str("\\ictus");
+        }
+        result
+    }
+}
+
+///The pitch index (see `Note::pitch_index()`) of the lowest staff position, "a", under a clef.
+pub(crate) fn clef_offset(clef: &str) -> Option<usize> {
+    match clef {
+        "c1" => Some(6),
+        "c2" => Some(4),
+        "c3" => Some(2),
+        "c4" => Some(0),
+        "f1" => Some(9),
+        "f2" => Some(7),
+        "f3" => Some(5),
+        "f4" => Some(3),
+        _ => None,
     }
 }
 
+///The gabc clef that places a melody best on the staff: all its notes must fit (from "a" to
+///"m"), with the notes furthest from the middle of the staff as close to it as possible.
+pub(crate) fn choose_clef(pitches: &[usize]) -> Option<&'static str> {
+    let (low, high) = (*pitches.iter().min().unwrap_or(&9), *pitches.iter().max().unwrap_or(&9));
+    ["c4", "c3", "f3", "c2", "f4", "c1", "f2", "f1"]
+        .iter()
+        .filter(|c| {
+            let offset = clef_offset(c).unwrap();
+            low >= offset && high <= offset + 12
+        })
+        .min_by_key(|c| {
+            //Distance from "g", the middle of the staff
+            let middle = clef_offset(c).unwrap() + 6;
+            middle.abs_diff(low).max(high.abs_diff(middle))
+        })
+        .copied()
+}
+
 ///Any element that can appear in a gabc music string.
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum NoteElem<'a> {
     ///A gabc spacer, e.g. "/"
     Spacer(&'a str),
@@ -123,11 +252,14 @@ pub enum NoteElem<'a> {
     Barline(&'a str),
     ///A `Note` struct
     Note(Note<'a>),
+    ///A clef, e.g. "c4"; it governs the `Note`s after it
+    Clef(&'a str),
 }
 
 impl<'a> NoteElem<'a> {
-    ///Get the Lilypond representation of this note element. gabc spacers (e.g. "/") are ignored;
-    ///`Note` suffixes (e.g. ".") that have Lilypond equivalents are not yet implemented.
+    ///Get the Lilypond representation of this note element: the absolute pitch of a note, or
+    ///the divisio of a barline. gabc spacers (e.g. "/") and clefs are ignored. Rhythmic signs
+    ///and note shapes are left out; see `to_ly_articulated()` for them.
     ///# Examples
     ///```
     ///# use gabc_parser::*;
@@ -148,18 +280,57 @@ impl<'a> NoteElem<'a> {
                 _ => "\\divisioMinima",
             },
             NoteElem::Note(n) => n.absolute_pitch(),
-            NoteElem::Spacer(_) => "",
+            NoteElem::Spacer(_) | NoteElem::Clef(_) => "",
+        }
+    }
+    ///Get the Lilypond representation of this note element like `to_ly()`, but with the
+    ///gregorian.ly commands for a note's shape and rhythmic signs (see `Note::to_ly()`).
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let n = NoteElem::Note(Note::new("h..", "c1"));
+    ///assert_eq!(n.to_ly_articulated(), "\\augmentum \\augmentum g'");
+    ///let b = NoteElem::Barline("::");
+    ///assert_eq!(b.to_ly_articulated(), "\\finalis");
+    ///```
+    pub fn to_ly_articulated(&self) -> String {
+        match self {
+            NoteElem::Note(n) => n.to_ly(),
+            _ => self.to_ly().to_string(),
+        }
+    }
+    ///Get the gabc representation of this note element.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let n = NoteElem::Note(Note::new("-h..", "c1"));
+    ///assert_eq!(n.to_gabc(), "-h..");
+    ///assert_eq!(NoteElem::Clef("f3").to_gabc(), "f3");
+    ///```
+    pub fn to_gabc(&self) -> String {
+        match self {
+            NoteElem::Note(n) => format!("{}{}{}", n.prefix, n.position, n.suffix),
+            NoteElem::Spacer(s) | NoteElem::Barline(s) | NoteElem::Clef(s) => s.to_string(),
         }
     }
 }
 
-///Struct representing a gabc syllable with text and music, e.g. "Po(eh/hi)"
+///Struct representing a gabc syllable with text and music, e.g. "Po(eh/hi)". Syllables are
+///compared by content: their spans are ignored.
 #[derive(Debug, Serialize)]
 pub struct Syllable<'a> {
     ///Text part of the syllable
     pub text: &'a str,
     ///Music part of the syllable
     pub music: Vec<NoteElem<'a>>,
+    ///Byte range of this syllable (text and music) in the parsed gabc input
+    pub span: Range<usize>,
+}
+
+impl<'a> PartialEq for Syllable<'a> {
+    fn eq(&self, other: &Syllable) -> bool {
+        self.text == other.text && self.music == other.music
+    }
 }
 
 impl<'a> Syllable<'a> {
@@ -173,38 +344,76 @@ impl<'a> Syllable<'a> {
     ///```
     pub fn new<'b>(gabc_input: &'b str, current_clef: &'b str) -> Syllable<'b> {
         let mut parse_result = parse_gabc(gabc_input, Rule::syllable);
-        parsed_syllable_to_struct(parse_result.next().unwrap(), current_clef)
+        let node = cst::Node::from_pair(parse_result.next().unwrap());
+        let mut current_clef = current_clef;
+        syllable_from_node(gabc_input, &node, &mut current_clef)
     }
-    ///Translate this syllable's music string into a tied sequence of Lilypond notes.
+    ///Translate this syllable's music string into a tied sequence of Lilypond notes. A horizontal
+    ///episema ("_") over a run of notes starts with `\episemInitium` on its first note and ends
+    ///with `\episemFinis` on its last; a single marked note gets both.
     ///# Examples
     ///```
     ///# use gabc_parser::*;
     ///let s = Syllable::new("Po(eh/hi)", "c3");
     ///assert_eq!(s.ly_notes(), "g(c' c' d')");
+    ///let s = Syllable::new("Po(e_h_/hi.)", "c3");
+    ///assert_eq!(s.ly_notes(), "g\\episemInitium(c'\\episemFinis c' \\augmentum d')");
+    ///let s = Syllable::new("Po(e_)", "c3");
+    ///assert_eq!(s.ly_notes(), "g\\episemInitium\\episemFinis");
     ///```
     pub fn ly_notes(&self) -> String {
         let mut result = String::new();
-        let mut notes_iter = self.music.iter();
+        let elems: Vec<&NoteElem> = self.music.iter().filter(|e| !matches!(e, NoteElem::Clef(_))).collect();
+        let marked = |e: &NoteElem| matches!(e, NoteElem::Note(n) if n.suffix.contains('_'));
+        //Whether the nearest element before or after a note, other than a spacer, is a marked note
+        let run_continues = |side: &[&NoteElem], before: bool| {
+            let mut others = side.iter().filter(|e| !matches!(e, NoteElem::Spacer(_)));
+            let nearest = if before { others.next_back() } else { others.next() };
+            nearest.is_some_and(|e| marked(e))
+        };
+        let mut notes_iter = elems.iter().enumerate().map(|(i, e)| {
+            let mut t = e.to_ly_articulated();
+            if marked(e) {
+                if !run_continues(&elems[..i], true) {
+                    t.push_str("\\episemInitium");
+                }
+                if !run_continues(&elems[i + 1..], false) {
+                    t.push_str("\\episemFinis");
+                }
+            }
+            t
+        });
         match notes_iter.next() {
-            None => return result,
-            Some(s) => result.push_str(s.to_ly()),
+            None => {}
+            Some(s) => result.push_str(&s),
         }
         match notes_iter.next() {
-            None => return result,
+            None => {}
             Some(s) => {
-                result.push_str("(");
-                result.push_str(s.to_ly());
+                result.push('(');
+                result.push_str(&s);
+                for t in notes_iter.by_ref() {
+                    if t.trim() != "" { result.push(' '); };
+                    result.push_str(&t);
+                }
+                result.push(')');
             }
         }
-        while let Some(s) = notes_iter.next() {
-            let t = s.to_ly();
-            if t.trim() != "" { result.push_str(" "); };
-            result.push_str(t);
-        }
-        result.push_str(")");
         result
     }
-    ///Translate this syllable's text into valid Lilypond lyrics. If there are no Notes in this
+    ///Get the gabc representation of this syllable.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = Syllable::new(" Po(c3 eh/hi)", "no clef set");
+    ///assert_eq!(s.to_gabc(), " Po(c3 eh/hi)");
+    ///```
+    pub fn to_gabc(&self) -> String {
+        let music: String = self.music.iter().map(|e| e.to_gabc()).collect();
+        format!("{}({})", self.text, music)
+    }
+    ///Translate this syllable's text into valid Lilypond lyrics. gabc markup is stripped, and
+    ///text above the staff (`<alt>`) left out. If there are no Notes in this
     ///syllable's music string, add "\set stanza = " to prevent Lilypond matching this text
     ///to a note.
     ///# Examples
@@ -212,10 +421,19 @@ impl<'a> Syllable<'a> {
     ///# use gabc_parser::*;
     ///let s = Syllable::new("*()", "c3");
     ///assert_eq!(s.ly_text(), " \\set stanza = \"*\" ");
+    ///let s = Syllable::new(" <alt>Gm</alt><i>Al</i>(g)", "c3");
+    ///assert_eq!(s.ly_text(), " Al");
     ///```
     pub fn ly_text(&self) -> String {
-        //Filter out Lilypond control characters
-        let text = sanitize_ly_syllable(self.text);
+        //Strip markup, then filter out Lilypond control characters
+        let (leading, tokens, trailing) = text_tokens(self.text);
+        let plain = format!(
+            "{}{}{}",
+            if leading { " " } else { "" },
+            tokens.iter().map(|t| &t.text).join(" "),
+            if trailing { " " } else { "" }
+        );
+        let text = sanitize_ly_syllable(&plain);
         //If there are no notes, use "set stanza"
         let mut flag = false;
         for ne in &self.music {
@@ -223,26 +441,35 @@ impl<'a> Syllable<'a> {
                 flag = true;
             }
         }
-        if !flag && !(text.trim() == "") {
-            return format!(" \\set stanza = \"{}\" ", text);
+        if !flag && !text.trim().is_empty() {
+            format!(" \\set stanza = \"{}\" ", text)
         } else {
-            return text.to_string();
+            text.to_string()
         }
     }
+    ///Get the text above the staff at this syllable (`<alt>` in gabc), e.g. an accompaniment chord
+    ///added by `table::merge_chords()`.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = Syllable::new(" <alt>Dm</alt>Po(eh/hi)", "c3");
+    ///assert_eq!(s.annotation(), Some("Dm"));
+    ///assert_eq!(Syllable::new("Po(eh/hi)", "c3").annotation(), None);
+    ///```
+    pub fn annotation(&self) -> Option<&'a str> {
+        let start = self.text.find("<alt>")? + "<alt>".len();
+        let end = self.text[start..].find("</alt>").map_or(self.text.len(), |e| start + e);
+        Some(&self.text[start..end])
+    }
 }
 
 ///Sanitize a syllable for Lilypond by removing control characters, replacing interior spaces with
 ///underscores, and surrounding anything starting with a number with "double quotes" (this is
 ///a pretty hacky way to prevent Lilypond errors)
 fn sanitize_ly_syllable(text: &str) -> String {
-    let start = text.trim_left() != text;
-    let end = text.trim_right() != text;
-    let mut t = text.trim().chars().filter(|c| {
-        match c {
-            '{' | '}' => false,
-            _ => true,
-        }
-    }).map(|c| match c {
+    let start = text.trim_start() != text;
+    let end = text.trim_end() != text;
+    let mut t = text.trim().chars().filter(|c| !matches!(c, '{' | '}')).map(|c| match c {
         ' ' => '_',
         x => x,
     }).collect::<String>();
@@ -258,8 +485,125 @@ fn sanitize_ly_syllable(text: &str) -> String {
     result
 }
 
+///Escape text for use in XML (or HTML) content and attribute values.
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+///A word of a gabc file's text, made of one or more consecutive syllables (or part of a syllable,
+///when a syllable's text contains spaces).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Word {
+    ///Text of the word with gabc markup (tags, braces) stripped
+    pub text: String,
+    ///Whether this word is a rubric (e.g. "*", "<i>Ps.</i>", "iij.") rather than sung text
+    pub rubric: bool,
+    ///Indexes of the syllables this word's text comes from
+    pub syllables: Range<usize>,
+    ///Byte range of those syllables in the parsed gabc input
+    pub span: Range<usize>,
+}
+
+///A piece of syllable text between spaces, with markup stripped.
+struct TextToken {
+    text: String,
+    rubric: bool,
+}
+
+///Split the text of a syllable into tokens, reporting whether it starts or ends with a space.
+///Italic text (`<i>`), special characters (`<sp>`) and markup are handled; text above the staff
+///(`<alt>`) and verbatim TeX (`<v>`) are dropped.
+fn text_tokens(text: &str) -> (bool, Vec<TextToken>, bool) {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut italic = false;
+    let mut current_italic = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let tag = &rest[1..end];
+                rest = &rest[end + 1..];
+                match tag {
+                    "i" => italic = true,
+                    "/i" => italic = false,
+                    "alt" | "v" | "sp" => {
+                        let close = format!("</{}>", tag);
+                        let content_end = rest.find(&close).unwrap_or(rest.len());
+                        if tag == "sp" {
+                            current.push_str(special_character(&rest[..content_end]));
+                            current_italic |= italic;
+                        }
+                        rest = &rest[(content_end + close.len()).min(rest.len())..];
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        }
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '{' | '}' => {}
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    let rubric = is_rubric(&current, current_italic);
+                    tokens.push(TextToken { text: current, rubric });
+                    current = String::new();
+                    current_italic = false;
+                }
+            }
+            c => {
+                current.push(c);
+                current_italic |= italic;
+            }
+        }
+    }
+    if !current.is_empty() {
+        let rubric = is_rubric(&current, current_italic);
+        tokens.push(TextToken { text: current, rubric });
+    }
+    let leading = text.starts_with(char::is_whitespace);
+    let trailing = text.ends_with(char::is_whitespace);
+    (leading, tokens, trailing)
+}
+
+///Translate the content of a gabc `<sp>` tag into the character it stands for.
+fn special_character(code: &str) -> &str {
+    match code {
+        "V/" => "℣",
+        "R/" => "℟",
+        "+" => "✠",
+        "ae" => "æ",
+        "'ae" | "'æ" => "ǽ",
+        "oe" => "œ",
+        "'oe" | "'œ" => "œ́",
+        x => x,
+    }
+}
+
+///Whether a (markup-stripped) token of text is a rubric rather than sung text: italic text,
+///asterisks and other signs, verse numbers ("2.") and repeat counts ("ij.", "iij.").
+fn is_rubric(token: &str, italic: bool) -> bool {
+    let numeral = token.ends_with('.')
+        && token.len() > 1
+        && (token[..token.len() - 1].chars().all(|c| c.is_ascii_digit())
+            || (token.ends_with("j.") && token.chars().all(|c| matches!(c, 'i' | 'j' | 'v' | 'x' | '.'))));
+    italic || numeral || token.contains(['*', '†', '℣', '℟', '✠'])
+}
+
 ///Struct representing an entire gabc file.
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct GabcFile<'a> {
     ///This file's attributes, e.g. "name: Populus Sion", as key/value tuples
     pub attributes: Vec<(&'a str, &'a str)>,
@@ -277,15 +621,70 @@ impl<'a> GabcFile<'a> {
     ///(c1) Hel(e.)lo(hi~) (::)";
     ///let f = GabcFile::new(s);
     ///assert_eq!(f.attributes[0], ("name", "Test"));
-    ///assert_eq!(f.syllables.len(), 4); //clefs currently produce an empty syllable
+    ///assert_eq!(f.syllables.len(), 4); //the clef is a syllable with no text
     ///```
-    pub fn new(gabc_input: &str) -> GabcFile {
+    pub fn new(gabc_input: &str) -> GabcFile<'_> {
         let parse_result = parse_gabc(gabc_input, Rule::file);
         parsed_file_to_struct(parse_result)
     }
-    ///Translate this `GabcFile` into JSON.
+    ///Write this `GabcFile` back to gabc: its attributes, one per line, then its syllables.
+    ///Parsing the result gives back an identical `GabcFile`.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = "name:Test;
+    ///%%
+    ///(c1) Hel(e.)lo(hi~) (::)";
+    ///let f = GabcFile::new(s);
+    ///assert_eq!(f.to_gabc(), format!("{}\n", s));
+    ///assert_eq!(GabcFile::new(&f.to_gabc()), f);
+    ///```
+    pub fn to_gabc(&self) -> String {
+        let mut result = String::new();
+        for (key, value) in &self.attributes {
+            result.push_str(&format!("{}:{};\n", key, value));
+        }
+        result.push_str("%%\n");
+        for syllable in &self.syllables {
+            result.push_str(&syllable.to_gabc());
+        }
+        result.push('\n');
+        result
+    }
+    ///Translate this `GabcFile` into JSON; see the `json` module for the format.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = "name:Test;
+    ///%%
+    ///(c4) Al(gh/h.) (::)";
+    ///let json = GabcFile::new(s).as_json();
+    ///assert!(json.starts_with("{\"version\":1,\"attributes\":[{\"key\":\"name\",\"value\":\"Test\"}]"));
+    ///assert!(json.contains("{\"type\":\"note\",\"prefix\":\"\",\"position\":\"h\",\"suffix\":\".\"}"));
+    ///```
     pub fn as_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
+        serde_json::to_string(&json::Document::from(self)).unwrap()
+    }
+    ///Read a chant in the JSON format of `as_json()`, e.g. after it has been edited by another
+    ///program, into an owned `json::Document`. Write it out with `Document::to_gabc()`, and parse
+    ///that with `GabcFile::new()` for a `GabcFile`. See `json::from_json()` for the errors.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let f = GabcFile::new("name:Test;
+    ///%%
+    ///(c4) Al(gh/h.) (::)");
+    ///let json = f.as_json().replace("\"value\":\"Test\"", "\"value\":\"Edited\"");
+    ///let document = GabcFile::from_json(&json).unwrap();
+    ///assert_eq!(document.attributes[0].value, "Edited");
+    ///let gabc = document.to_gabc();
+    ///assert_eq!(gabc, "name:Edited;\n%%\n(c4) Al(gh/h.) (::)\n");
+    ///assert_eq!(GabcFile::new(&gabc).syllables.len(), f.syllables.len());
+    ///assert!(GabcFile::from_json(&json.replace("\"h\"", "\"hh\"")).is_err());
+    ///assert!(GabcFile::from_json(&json.replace("\"version\":1", "\"version\":2")).is_err());
+    ///```
+    pub fn from_json(json: &str) -> Result<json::Document, String> {
+        json::from_json(json)
     }
     ///Translate this `GabcFile` into a well-formed Lilypond file, by translating its text and music
     ///and inserting them into a template derived from
@@ -293,6 +692,92 @@ impl<'a> GabcFile<'a> {
     pub fn as_lilypond(&self) -> String {
         format!("{}{}{}{}{}", LY_1, &self.ly_notes(), LY_2, &self.ly_lyrics(), LY_3)
     }
+    ///Translate this `GabcFile` into a well-formed Lilypond file in square notation, like
+    ///`as_lilypond()` but with a template derived from
+    ///<http://lilypond.org/doc/v2.18/Documentation/notation/typesetting-gregorian-chant>: the
+    ///music is set in a `VaticanaVoice` on a `VaticanaStaff`.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let f = GabcFile::new("name:Test;
+    ///%%
+    ///(c1) Hel(e.)lo(hi~) (::)");
+    ///let ly = f.as_lilypond_vaticana();
+    ///assert!(ly.starts_with("\\include \"gregorian.ly\""));
+    ///assert!(ly.contains("g'(\\deminutum a')"));
+    ///assert!(ly.contains("\\new VaticanaVoice = \"melody\" \\chant"));
+    ///```
+    pub fn as_lilypond_vaticana(&self) -> String {
+        format!("{}{}{}{}{}", LY_1, &self.ly_notes(), LY_2, &self.ly_lyrics(), LY_VATICANA_3)
+    }
+    ///Translate this `GabcFile` into a MusicXML 4.0 score-partwise document; see the `musicxml`
+    ///module for details.
+    pub fn as_musicxml(&self) -> String {
+        musicxml::to_musicxml(self)
+    }
+    ///Translate this `GabcFile` into an ABC tune; see the `abc` module for details.
+    pub fn as_abc(&self) -> String {
+        abc::to_abc(self)
+    }
+    ///Translate this `GabcFile` into a Standard MIDI File; see the `midi` module for details.
+    pub fn as_midi(&self, options: &midi::MidiOptions) -> Vec<u8> {
+        midi::to_midi(self, options)
+    }
+    ///Write the syllables of this `GabcFile` as CSV, one row each; see the `table` module for
+    ///details.
+    pub fn as_csv(&self) -> String {
+        table::to_table(self, table::Format::Csv)
+    }
+    ///Write the syllables of this `GabcFile` as TSV, one row each; see the `table` module for
+    ///details.
+    pub fn as_tsv(&self) -> String {
+        table::to_table(self, table::Format::Tsv)
+    }
+    ///Translate this `GabcFile` into Humdrum **kern with a **text spine; see the `kern` module for
+    ///details.
+    pub fn as_kern(&self) -> String {
+        kern::to_kern(self)
+    }
+    ///Translate this `GabcFile` into Volpiano; see the `volpiano` module for details.
+    pub fn as_volpiano(&self) -> String {
+        volpiano::to_volpiano(self, None)
+    }
+    ///The Volpiano incipit of this `GabcFile`: its first `syllables` sung syllables.
+    pub fn volpiano_incipit(&self, syllables: usize) -> String {
+        volpiano::to_volpiano(self, Some(syllables))
+    }
+    ///Render this `GabcFile` in square notation as SVG; see the `render` and `svg` modules for
+    ///details.
+    pub fn as_svg(&self, options: &render::RenderOptions) -> String {
+        svg::to_svg(self, options)
+    }
+    ///Render this `GabcFile` in square notation as a PNG image with `scale` pixels to each unit
+    ///of the layout; see the `render` and `png` modules for details. Needs the "png-pdf"
+    ///feature.
+    #[cfg(feature = "png-pdf")]
+    pub fn as_png(&self, options: &render::RenderOptions, scale: f64) -> Result<Vec<u8>, String> {
+        png::to_png(self, options, scale)
+    }
+    ///Render this `GabcFile` in square notation as a PDF document; see the `render` and `pdf`
+    ///modules for details. Needs the "png-pdf" feature.
+    #[cfg(feature = "png-pdf")]
+    pub fn as_pdf(&self, options: &pdf::PdfOptions) -> Vec<u8> {
+        pdf::to_pdf(self, options)
+    }
+    ///Translate this `GabcFile` into an HTML document; see the `html` module for details.
+    pub fn as_html(&self, options: &html::HtmlOptions) -> String {
+        html::to_html(self, options)
+    }
+    ///Draw this `GabcFile` as text for a terminal `columns` wide; see the `terminal` module for
+    ///details.
+    pub fn as_terminal(&self, columns: usize) -> String {
+        terminal::to_terminal(self, columns)
+    }
+    ///Translate this `GabcFile` into an MEI 5.0 document in neume notation; see the `mei` module
+    ///for details.
+    pub fn as_mei(&self) -> String {
+        mei::to_mei(self)
+    }
     ///Extract the notes of this file into well-formed Lilypond music, with a newline between each
     ///syllable
     ///# Examples
@@ -303,8 +788,8 @@ impl<'a> GabcFile<'a> {
     ///(c1) Hel(e.)lo(hi~) (::)";
     ///let f = GabcFile::new(s);
     ///assert_eq!(f.ly_notes(), r#"
-    ///d'
-    ///g'(a')
+    ///\augmentum d'
+    ///g'(\deminutum a')
     ///\finalis
     ///"#);
     ///```
@@ -312,7 +797,7 @@ impl<'a> GabcFile<'a> {
         let mut notes = String::new();
         for syllable in &self.syllables {
             notes.push_str(&syllable.ly_notes());
-            notes.push_str("\n");
+            notes.push('\n');
         }
         notes
     }
@@ -331,29 +816,161 @@ impl<'a> GabcFile<'a> {
         let syllable_iter = &mut self.syllables.iter().peekable();
         while let Some(syll) = syllable_iter.next() {
             let s = &syll.ly_text();
-            result.push_str(&s);
+            result.push_str(s);
             if let Some(next_syll) = syllable_iter.peek() {
                 let next_s = next_syll.ly_text();
-                if s.trim_right() == s && next_s.trim_left() == next_s {
+                if s.trim_end() == s && next_s.trim_start() == next_s {
                     result.push_str(" -- ");
                 }
             }
         }
         result
     }
+    ///Group this file's syllables into words, with gabc markup stripped and rubrics (e.g. "*",
+    ///"<i>Ps.</i>", "iij.") marked. Punctuation separated from a word by a space (e.g. "æ :") is
+    ///attached to the preceding word.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = "%%
+    ///(c4) Hel(e.)lo(hi~) *(;) <sp>V/</sp>. {W}orld(h) <i>ij.</i>(::)";
+    ///let f = GabcFile::new(s);
+    ///let words = f.words();
+    ///assert_eq!(words.len(), 5);
+    ///assert_eq!(words[0].text, "Hello");
+    ///assert_eq!(words[0].syllables, 1..3);
+    ///assert_eq!(&s[words[0].span.clone()], " Hel(e.)lo(hi~)");
+    ///assert!(words[1].rubric && words[2].rubric);
+    ///assert_eq!(words[3].text, "World");
+    ///assert!(!words[3].rubric);
+    ///assert_eq!(words[4].text, "ij.");
+    ///assert!(words[4].rubric);
+    ///```
+    pub fn words(&self) -> Vec<Word> {
+        let mut words: Vec<Word> = Vec::new();
+        //Whether the last word continues into the next piece of text
+        let mut open = false;
+        for (i, syllable) in self.syllables.iter().enumerate() {
+            let (leading, tokens, trailing) = text_tokens(syllable.text);
+            if leading {
+                open = false;
+            }
+            let has_text = !tokens.is_empty();
+            for token in tokens {
+                let punctuation = !token.rubric && !token.text.chars().any(char::is_alphanumeric);
+                match words.last_mut() {
+                    Some(w) if open || (punctuation && !w.rubric) => {
+                        w.text.push_str(&token.text);
+                        w.rubric &= token.rubric;
+                        w.syllables.end = i + 1;
+                        w.span.end = syllable.span.end;
+                    }
+                    _ => words.push(Word {
+                        text: token.text,
+                        rubric: token.rubric,
+                        syllables: i..i + 1,
+                        span: syllable.span.clone(),
+                    }),
+                }
+                open = false;
+            }
+            if has_text {
+                open = !trailing;
+            }
+        }
+        words
+    }
+    ///Get the sung text of this file: its words, excluding rubrics, separated by single spaces.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = "%%
+    ///(c4) Hel(e.)lo(hi~) *(;) <sp>V/</sp>. {W}orld(h) <i>ij.</i>(::)";
+    ///assert_eq!(GabcFile::new(s).text(), "Hello World");
+    ///```
+    pub fn text(&self) -> String {
+        self.words()
+            .into_iter()
+            .filter(|w| !w.rubric)
+            .map(|w| w.text)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    ///Get the sung text of each syllable, with markup and rubrics stripped, and its position in
+    ///its word. Syllables without notes or without sung text give `None`.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///let s = "%%
+    ///(c4) Hel(e.)lo(hi~) *(;) {W}orld(h) (::)";
+    ///let lyrics = GabcFile::new(s).syllable_lyrics();
+    ///assert_eq!(lyrics[0], None);
+    ///assert_eq!(lyrics[1], Some(("Hel".to_string(), Syllabic::Begin)));
+    ///assert_eq!(lyrics[2], Some(("lo".to_string(), Syllabic::End)));
+    ///assert_eq!(lyrics[3], None);
+    ///assert_eq!(lyrics[4], Some(("World".to_string(), Syllabic::Single)));
+    ///```
+    pub fn syllable_lyrics(&self) -> Vec<Option<(String, Syllabic)>> {
+        let mut result: Vec<Option<(String, Syllabic)>> = self
+            .syllables
+            .iter()
+            .map(|s| {
+                if !s.music.iter().any(|e| matches!(e, NoteElem::Note(_))) {
+                    return None;
+                }
+                let (_, tokens, _) = text_tokens(s.text);
+                let text = tokens.into_iter().filter(|t| !t.rubric).map(|t| t.text).join(" ");
+                if text.is_empty() {
+                    None
+                } else {
+                    Some((text, Syllabic::Single))
+                }
+            })
+            .collect();
+        for word in self.words().into_iter().filter(|w| !w.rubric) {
+            let sung: Vec<usize> = word.syllables.filter(|&i| result[i].is_some()).collect();
+            if sung.len() < 2 {
+                continue;
+            }
+            for (k, &i) in sung.iter().enumerate() {
+                let syllabic = match k {
+                    0 => Syllabic::Begin,
+                    k if k + 1 == sung.len() => Syllabic::End,
+                    _ => Syllabic::Middle,
+                };
+                if let Some((_, s)) = result[i].as_mut() {
+                    *s = syllabic;
+                }
+            }
+        }
+        result
+    }
+}
+
+///Position of a sung syllable in its word.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Syllabic {
+    ///A word of one syllable
+    Single,
+    ///The first syllable of a longer word
+    Begin,
+    ///A syllable inside a word
+    Middle,
+    ///The last syllable of a longer word
+    End,
 }
 
 ///Wrapper for GABCParser::parse() that prints a helpful error and exits the process if parsing
 ///fails (a friendly alternative to panicking).
-pub fn parse_gabc(text: &str, rule: Rule) -> Pairs<Rule> {
-    let parse_result = GABCParser::parse(rule, &text);
+pub fn parse_gabc(text: &str, rule: Rule) -> Pairs<'_, Rule> {
+    let parse_result = GABCParser::parse(rule, text);
     match parse_result {
         Err(e) => {
             println!("Parse error: {}", e);
             std::process::exit(1);
         }
         Ok(pairs) => {
-            return pairs;
+            pairs
         }
     }
 }
@@ -369,7 +986,7 @@ fn print_rule_tree(rules: Pairs<Rule>, tabs: usize) -> String {
     let mut output = String::new();
     for rule in rules {
         for _ in 0..tabs {
-            output.push_str("\t");
+            output.push('\t');
         }
         output.push_str(format!("{:?}: {}\n", rule.as_rule(), rule.as_str()).as_ref());
         output.push_str(print_rule_tree(rule.into_inner(), tabs + 1).as_ref());
@@ -377,41 +994,34 @@ fn print_rule_tree(rules: Pairs<Rule>, tabs: usize) -> String {
     output
 }
 
-///Turns a file parse result into a `GabcFile`. This relies on unchecked unwrap() calls that should not
-///fail because of the characteristics of the pest PEG.
+///Turns a file parse result into a `GabcFile`, by way of its concrete syntax tree.
 fn parsed_file_to_struct<'b>(mut parsed_file: pest::iterators::Pairs<'b, Rule>) -> GabcFile<'b> {
+    let pair = parsed_file.next().unwrap();
+    //The file rule spans the whole input, from soi to eoi
+    let source = pair.as_str();
+    file_from_node(source, &cst::Node::from_pair(pair))
+}
+
+///Turns the syntax tree of a file into a `GabcFile`. This relies on unchecked unwrap() calls that should not
+///fail because of the characteristics of the pest PEG.
+fn file_from_node<'b>(source: &'b str, file: &cst::Node) -> GabcFile<'b> {
     let mut syllables: Vec<Syllable> = Vec::new();
     let mut attributes: Vec<(&str, &str)> = Vec::new();
     let mut current_clef = "no clef set";
-    for pair in parsed_file.next().unwrap().into_inner() {
-        match pair.as_rule() {
-            Rule::attribute => {
-                let attribute: (&str, &str) =
-                    pair.into_inner().map(|x| x.as_str()).next_tuple().unwrap();
+    for node in &file.children {
+        match node.rule() {
+            Some(Rule::attribute) => {
+                let attribute: (&str, &str) = node
+                    .children
+                    .iter()
+                    .filter(|x| x.rule().is_some())
+                    .map(|x| x.text(source))
+                    .next_tuple()
+                    .unwrap();
                 attributes.push(attribute);
             }
-            Rule::syllable => {
-                let mut syllable_components = pair.into_inner();
-                let text = syllable_components.next().unwrap().as_str();
-                let mut music: Vec<NoteElem> = Vec::new();
-                while let Some(pair) = syllable_components.next() {
-                    match pair.as_rule() {
-                        Rule::note => {
-                            music.push(NoteElem::Note(parsed_note_to_struct(pair, current_clef)));
-                        }
-                        Rule::barline => {
-                            music.push(NoteElem::Barline(pair.as_str()));
-                        }
-                        Rule::spacer => {
-                            music.push(NoteElem::Spacer(pair.as_str()));
-                        }
-                        Rule::clef => {
-                            current_clef = pair.as_str();
-                        }
-                        _ => unreachable!("impossible syllable sub-rule"),
-                    }
-                }
-                syllables.push(Syllable { text, music }); //strings[1..].to_vec() } );
+            Some(Rule::syllable) => {
+                syllables.push(syllable_from_node(source, node, &mut current_clef));
             }
             _ => {}
         }
@@ -422,57 +1032,58 @@ fn parsed_file_to_struct<'b>(mut parsed_file: pest::iterators::Pairs<'b, Rule>)
     }
 }
 
-///Turns a syllable parse result into a `Syllable`. This relies on unchecked unwrap() calls that should not
-///fail because of the characteristics of the pest PEG.
-///This isn't used in the main parse pipeline because it can't update the current_clef tracker,
-///but it is used in `Syllable::new()`.
-fn parsed_syllable_to_struct<'a>(parsed_syllable: pest::iterators::Pair<'a, Rule>, current_clef: &'a str) -> Syllable<'a> {
-    let mut syllable_components = parsed_syllable.into_inner();
-    let text = syllable_components.next().unwrap().as_str();
+///Turns the syntax tree of a syllable into a `Syllable`. This relies on unchecked unwrap() calls that should not
+///fail because of the characteristics of the pest PEG. Clefs in the syllable update `current_clef`.
+fn syllable_from_node<'a>(source: &'a str, syllable: &cst::Node, current_clef: &mut &'a str) -> Syllable<'a> {
+    let mut syllable_components = syllable.children.iter().filter(|x| x.rule().is_some());
+    let text = syllable_components.next().unwrap().text(source);
     let mut music: Vec<NoteElem> = Vec::new();
-    while let Some(pair) = syllable_components.next() {
-        match pair.as_rule() {
+    for node in syllable_components {
+        match node.rule().unwrap() {
             Rule::note => {
-                music.push(NoteElem::Note(parsed_note_to_struct(pair, current_clef)));
+                music.push(NoteElem::Note(note_from_node(source, node, current_clef)));
             }
             Rule::barline => {
-                music.push(NoteElem::Barline(pair.as_str()));
+                music.push(NoteElem::Barline(node.text(source)));
             }
             Rule::spacer => {
-                music.push(NoteElem::Spacer(pair.as_str()));
+                music.push(NoteElem::Spacer(node.text(source)));
+            }
+            Rule::clef => {
+                *current_clef = node.text(source);
+                music.push(NoteElem::Clef(node.text(source)));
             }
             _ => unreachable!("impossible syllable sub-rule"),
         }
     }
-    Syllable { text, music }
+    Syllable {
+        text,
+        music,
+        span: syllable.span.clone(),
+    }
 }
 
-///Turns a note parse result into a `Note`. This relies on unchecked unwrap() calls that should not
+///Turns the syntax tree of a note into a `Note`. This relies on unchecked unwrap() calls that should not
 ///fail because of the characteristics of the pest PEG.
-fn parsed_note_to_struct<'b>(parsed_note: pest::iterators::Pair<'b, Rule>, current_clef: &'b str) -> Note<'b> {
-        let mut prefix = "";
-        let mut position = 'z';
-        let mut suffix = "";
-        for p in parsed_note.into_inner() {
-            match &p.as_rule() {
-                Rule::prefix => prefix = p.as_str(),
-                Rule::position => position = p.as_str().chars().next().unwrap(),
-                Rule::suffix => suffix = p.as_str(),
-                _ => unreachable!("impossible note sub-rule"),
-            }
-        }
-        assert!(position != 'z'); //note rule MUST have a position sub-rule
-        Note {
-            prefix,
-            position,
-            suffix,
-            current_clef,
-        }
+fn note_from_node<'b>(source: &'b str, note: &cst::Node, current_clef: &'b str) -> Note<'b> {
+    //A note may have several prefixes and suffixes, which are contiguous around the position
+    let position = note
+        .children
+        .iter()
+        .find(|x| x.rule() == Some(Rule::position))
+        .expect("note rule MUST have a position sub-rule");
+    Note {
+        prefix: &source[note.span.start..position.span.start],
+        position: position.text(source).chars().next().unwrap(),
+        suffix: &source[position.span.end..note.span.end],
+        current_clef,
+        span: note.span.clone(),
+    }
 }
 
 //Lilypond template below derived from
 //<http://lilypond.org/doc/v2.18/Documentation/snippets/templates#templates-ancient-notation-template-_002d-modern-transcription-of-gregorian-music>
-static LY_1: &'static str = r#"\include "gregorian.ly"
+static LY_1: &str = r#"\include "gregorian.ly"
 
 chant = \absolute { \transpose c c' {
   \set Score.timing = ##f
@@ -480,13 +1091,13 @@ chant = \absolute { \transpose c c' {
 // f4 a2 \divisioMinima
 // g4 b a2 f2 \divisioMaior
 // g4( f) f( g) a2 \finalis
-static LY_2: &'static str = r#"
+static LY_2: &str = r#"
 }}
 
 verba = \lyricmode {
   "#;
 // Lo -- rem ip -- sum do -- lor sit a -- met
-static LY_3: &'static str = r#"
+static LY_3: &str = r#"
 }
 
 \score {
@@ -511,3 +1122,21 @@ static LY_3: &'static str = r#"
     }
   }
 }"#;
+
+//Vaticana score below derived from
+//<http://lilypond.org/doc/v2.18/Documentation/notation/typesetting-gregorian-chant>
+static LY_VATICANA_3: &str = r#"
+}
+
+\score {
+  \new VaticanaStaff <<
+    \new VaticanaVoice = "melody" \chant
+    \new Lyrics = "one" \lyricsto melody \verba
+  >>
+  \layout {
+    \context {
+      \Score
+      barAlways = ##t
+    }
+  }
+}"#;
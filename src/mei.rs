@@ -0,0 +1,663 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! MEI output with the Neumes module (MEI 5.0). The music is written in neume notation on a
+//! single staff: each syllable becomes a `<syllable>` with its text (`<syl>`) and its neumes,
+//! and each neume a `<neume>` of neume components (`<nc>`) at their written pitch. Notes joined
+//! in gabc (e.g. "hgh", or "e!f") make one neume; spacers, clefs and divisions separate neumes.
+//! Liquescent ("~", "<", ">"), quilisma ("w"), oriscus ("o") and stropha ("s") notes get the
+//! matching MEI child elements, with the ascending ("<") and descending (">") liquescents curving
+//! anticlockwise and clockwise (`@curve`), and virgae and punctua inclinata a `@tilt`; bivirgae and
+//! distrophae are written as repeated components. Flat, natural and
+//! sharp signs become `<accid>`s and clefs and divisions are written where they occur. Rhythmic
+//! signs (episemata, morae) are not encoded.
+//!
+//! The header is built from the gabc attributes "name", "transcriber", "book" and "mode".
+//!
+//! `from_mei()` reads such documents, e.g. transcriptions made in Neon, back into gabc.
+
+use formatter::{format_gabc, FormatOptions};
+use roxmltree;
+use {clef_offset, escape_xml, GabcFile, Note, NoteElem, Syllabic};
+
+///Translate a `GabcFile` into an MEI 5.0 document using the Neumes module.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("name:Test;
+///mode:8;
+///%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(gwh) (::)");
+///let mei = f.as_mei();
+///assert!(mei.contains("<title>Test</title>"));
+///assert!(mei.contains("<clef shape=\"C\" line=\"4\"/>"));
+///assert_eq!(mei.matches("<neume").count(), 4);
+///assert!(mei.contains("<syl wordpos=\"i\" con=\"d\">Al</syl>"));
+///assert!(mei.contains("<liquescent/>"));
+///assert!(mei.contains("<divLine form=\"finalis\"/>"));
+///```
+pub fn to_mei(file: &GabcFile) -> String {
+    let mut result = String::new();
+    result.push_str(HEADER);
+    result.push_str(&mei_head(file));
+    result.push_str(MUSIC_START);
+    let lyrics = file.syllable_lyrics();
+    for (syllable, lyric) in file.syllables.iter().zip(lyrics) {
+        let mut writer = SyllableWriter {
+            result: &mut result,
+            lyric,
+            open: false,
+            neume: Vec::new(),
+        };
+        for elem in &syllable.music {
+            match elem {
+                NoteElem::Note(n) if accidental(n).is_some() => writer.accid(n),
+                NoteElem::Note(n) => writer.neume.push(n),
+                NoteElem::Spacer("!") | NoteElem::Spacer("@") => {}
+                NoteElem::Spacer(_) => writer.end_neume(),
+                NoteElem::Clef(c) => writer.layer_element(&clef(c)),
+                NoteElem::Barline(b) => writer.layer_element(&format!("<divLine form=\"{}\"/>", division(b))),
+            }
+        }
+        writer.end_syllable();
+    }
+    result.push_str(MUSIC_END);
+    result
+}
+
+///Writes the music of one gabc syllable: its notes go into an MEI `<syllable>`, opened at the
+///first neume, while clefs and divisions are written between syllables.
+struct SyllableWriter<'r, 's, 'a: 's> {
+    result: &'r mut String,
+    ///Text of the syllable, written with its first neume
+    lyric: Option<(String, Syllabic)>,
+    ///Whether a `<syllable>` element is open
+    open: bool,
+    ///Notes of the neume being read
+    neume: Vec<&'s Note<'a>>,
+}
+
+impl<'r, 's, 'a> SyllableWriter<'r, 's, 'a> {
+    ///Open a `<syllable>` element, if none is open.
+    fn start_syllable(&mut self) {
+        if self.open {
+            return;
+        }
+        self.open = true;
+        self.result.push_str("                <syllable>\n");
+        if let Some((text, syllabic)) = self.lyric.take() {
+            let wordpos = match syllabic {
+                Syllabic::Single => "",
+                Syllabic::Begin => " wordpos=\"i\" con=\"d\"",
+                Syllabic::Middle => " wordpos=\"m\" con=\"d\"",
+                Syllabic::End => " wordpos=\"t\"",
+            };
+            self.result.push_str(&format!("                  <syl{}>{}</syl>\n", wordpos, escape_xml(&text)));
+        }
+    }
+    ///Write the neume being read, if it has any notes with a known pitch.
+    fn end_neume(&mut self) {
+        //A bivirga ("vv") or distropha ("ss") is written on one staff position, but is two notes
+        let neume: Vec<(&Note, (char, i32))> = self
+            .neume
+            .drain(..)
+            .filter_map(|n| Some((n, pitch(n)?)))
+            .flat_map(|(n, p)| {
+                let repeats = n.suffix.matches('v').count().max(n.suffix.matches('s').count()).max(1);
+                std::iter::repeat_n((n, p), repeats)
+            })
+            .collect();
+        if neume.is_empty() {
+            return;
+        }
+        self.start_syllable();
+        let pitches: Vec<usize> = neume.iter().map(|(n, _)| n.pitch_index().unwrap()).collect();
+        match neume_type(&pitches, neume[0].0) {
+            Some(kind) => self.result.push_str(&format!("                  <neume type=\"{}\">\n", kind)),
+            None => self.result.push_str("                  <neume>\n"),
+        }
+        for (note, (pname, oct)) in neume {
+            self.result.push_str(&nc(note, pname, oct));
+        }
+        self.result.push_str("                  </neume>\n");
+    }
+    ///Write an accidental sign, which ends the neume before it.
+    fn accid(&mut self, note: &Note) {
+        self.end_neume();
+        if let Some((ploc, oloc)) = pitch(note) {
+            self.start_syllable();
+            self.result.push_str(&format!(
+                "                  <accid accid=\"{}\" ploc=\"{}\" oloc=\"{}\"/>\n",
+                accidental(note).unwrap(),
+                ploc,
+                oloc
+            ));
+        }
+    }
+    ///Close the open `<syllable>` element, if any.
+    fn end_syllable(&mut self) {
+        self.end_neume();
+        if self.open {
+            self.result.push_str("                </syllable>\n");
+            self.open = false;
+        }
+    }
+    ///Write an element (a clef or division) directly in the layer, outside any syllable.
+    fn layer_element(&mut self, element: &str) {
+        self.end_syllable();
+        self.result.push_str(&format!("                {}\n", element));
+    }
+}
+
+///The `<nc>` element of a note.
+fn nc(note: &Note, pname: char, oct: i32) -> String {
+    let tilt = if note.position.is_uppercase() {
+        " tilt=\"se\""
+    } else if note.suffix.contains(['v', 'V']) {
+        " tilt=\"n\""
+    } else {
+        ""
+    };
+    let mut children = String::new();
+    if note.suffix.contains('<') {
+        children.push_str("<liquescent curve=\"a\"/>");
+    } else if note.suffix.contains('>') {
+        children.push_str("<liquescent curve=\"c\"/>");
+    } else if note.suffix.contains('~') {
+        children.push_str("<liquescent/>");
+    }
+    if note.suffix.contains('w') {
+        children.push_str("<quilisma/>");
+    }
+    if note.suffix.contains('o') {
+        children.push_str("<oriscus/>");
+    }
+    if note.suffix.contains('s') {
+        children.push_str("<strophicus/>");
+    }
+    let start = format!("                    <nc pname=\"{}\" oct=\"{}\"{}", pname, oct, tilt);
+    if children.is_empty() {
+        format!("{}/>\n", start)
+    } else {
+        format!("{}>{}</nc>\n", start, children)
+    }
+}
+
+///Classify a neume by its melodic contour, for its `@type`: the traditional names of the neumes
+///of one to three notes. Other neumes, e.g. repeated notes, are not classified.
+fn neume_type(pitches: &[usize], first: &Note) -> Option<&'static str> {
+    use std::cmp::Ordering::*;
+    let steps: Vec<_> = pitches.windows(2).map(|w| w[1].cmp(&w[0])).collect();
+    let kind = match steps[..] {
+        [] if first.suffix.contains(['v', 'V']) => "virga",
+        [] => "punctum",
+        [Greater] => "pes",
+        [Less] => "clivis",
+        [Greater, Less] => "torculus",
+        [Less, Greater] => "porrectus",
+        [Less, Less] => "climacus",
+        [Greater, Greater] => "scandicus",
+        _ => return None,
+    };
+    Some(kind)
+}
+
+///Pitch name and octave of a note as written, or `None` if it has no clef.
+fn pitch(note: &Note) -> Option<(char, i32)> {
+    note.pitch_index().map(::events::pitch_name)
+}
+
+///The MEI `@accid` value of a flat, natural or sharp sign.
+fn accidental(note: &Note) -> Option<&'static str> {
+    if note.suffix.contains('x') {
+        Some("f")
+    } else if note.suffix.contains('y') {
+        Some("n")
+    } else if note.suffix.contains('#') {
+        Some("s")
+    } else {
+        None
+    }
+}
+
+///The `<clef>` element of a gabc clef, e.g. "c4".
+fn clef(clef: &str) -> String {
+    let mut chars = clef.chars();
+    let shape = chars.next().unwrap().to_ascii_uppercase();
+    format!("<clef shape=\"{}\" line=\"{}\"/>", shape, chars.as_str())
+}
+
+///The `@form` of the `<divLine>` of a gabc barline.
+fn division(barline: &str) -> &'static str {
+    match barline {
+        "'" | "`" => "virgula",
+        "," | ",_" | ",0" => "minima",
+        "::" => "finalis",
+        b if b.starts_with(';') => "minor",
+        _ => "maior",
+    }
+}
+
+///The `<meiHead>` of a file: its title, transcriber and source in the file description, and its
+///mode in the work list.
+fn mei_head(file: &GabcFile) -> String {
+    let attribute = |key: &str| {
+        file.attributes
+            .iter()
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| escape_xml(v.trim()))
+    };
+    let title = attribute("name").unwrap_or_default();
+    let mut result = String::from("  <meiHead>\n    <fileDesc>\n      <titleStmt>\n");
+    result.push_str(&format!("        <title>{}</title>\n", title));
+    if let Some(transcriber) = attribute("transcriber") {
+        result.push_str("        <respStmt>\n");
+        result.push_str(&format!("          <persName role=\"transcriber\">{}</persName>\n", transcriber));
+        result.push_str("        </respStmt>\n");
+    }
+    result.push_str("      </titleStmt>\n      <pubStmt/>\n");
+    if let Some(book) = attribute("book") {
+        result.push_str("      <sourceDesc>\n        <source>\n");
+        result.push_str(&format!("          <bibl>\n            <title>{}</title>\n          </bibl>\n", book));
+        result.push_str("        </source>\n      </sourceDesc>\n");
+    }
+    result.push_str("    </fileDesc>\n");
+    result.push_str("    <encodingDesc>\n      <appInfo>\n        <application>\n");
+    result.push_str("          <name>gabc-parser</name>\n");
+    result.push_str("        </application>\n      </appInfo>\n    </encodingDesc>\n");
+    result.push_str("    <workList>\n      <work>\n");
+    result.push_str(&format!("        <title>{}</title>\n", title));
+    if let Some(mode) = attribute("mode") {
+        result.push_str(&format!("        <mode>{}</mode>\n", mode));
+    }
+    result.push_str("      </work>\n    </workList>\n  </meiHead>\n");
+    result
+}
+
+///The result of reading an MEI document with `from_mei()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeiImport {
+    ///The document as gabc, ready for `GabcFile::new()`
+    pub gabc: String,
+    ///MEI constructs that have no gabc equivalent and were left out, e.g. `"<custos>"`, with the
+    ///number of times each one occurs, in order of first occurrence
+    pub unsupported: Vec<(String, usize)>,
+}
+
+///Read an MEI document in neume notation (MEI 4 or 5 with the Neumes module, e.g. from Neon) into
+///gabc. The initial clef is read from the first `<staffDef>` that has one, as attributes or as a
+///`<clef>` element. The layers of the document are then read in order: clefs, syllables with
+///their text, neumes and accidentals, and divisions (`<divLine>`, or `<division>` in MEI 4). Note
+///shapes are read from `@tilt` and the `<liquescent>` (with its `@curve`), `<quilisma>`,
+///`<oriscus>` and `<strophicus>` elements, and staff positions from `@pname` and `@oct` under the
+///clef in force. Anything else in the music, such as custodes, system breaks, notes beyond the
+///gabc staff or `<nc>` attributes like `@con`, is left out and reported. The
+///header attributes "name", "transcriber", "book" and "mode" are read from the `<meiHead>`, as
+///written by `to_mei()`. The gabc is written in canonical form (see the `formatter` module).
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::mei::*;
+///let mei = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(gwh) (::)").as_mei();
+///let import = from_mei(&mei).unwrap();
+///assert_eq!(import.gabc, "name: Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(gwh) (::)
+///");
+///assert!(import.unsupported.is_empty());
+///let f = GabcFile::new(&import.gabc);
+///assert_eq!(f.syllables.len(), 6);
+///```
+pub fn from_mei(mei: &str) -> Result<MeiImport, String> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let document = roxmltree::Document::parse_with_options(mei, options).map_err(|e| format!("{}", e))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "mei" {
+        return Err(format!("not an MEI document: <{}>", root.tag_name().name()));
+    }
+    let mut reader = Reader {
+        body: String::new(),
+        clef: None,
+        joined: false,
+        accidentals: String::new(),
+        unsupported: Vec::new(),
+    };
+    let staff_clef = root.descendants().filter(|n| n.has_tag_name("staffDef")).find_map(|def| {
+        if def.has_attribute("clef.shape") {
+            Some((def.attribute("clef.shape"), def.attribute("clef.line")))
+        } else {
+            def.children().find(|n| n.has_tag_name("clef")).map(|c| (c.attribute("shape"), c.attribute("line")))
+        }
+    });
+    if let Some((shape, line)) = staff_clef {
+        reader.clef(shape, line);
+    }
+    for layer in root.descendants().filter(|n| n.has_tag_name("layer")) {
+        for child in layer.children().filter(|n| n.is_element()) {
+            match child.tag_name().name() {
+                "syllable" | "neume" => reader.syllable(child),
+                "clef" => reader.clef(child.attribute("shape"), child.attribute("line")),
+                "divLine" | "division" => {
+                    let barline = reader.barline(child);
+                    reader.write_syllable("", &barline, false);
+                }
+                "accid" => {
+                    let accid = reader.accid(child);
+                    reader.accidentals.push_str(&accid);
+                }
+                other => reader.report(format!("<{}>", other)),
+            }
+        }
+    }
+    let mut gabc = String::new();
+    let head = root.children().find(|n| n.has_tag_name("meiHead"));
+    for (key, value) in head.map(header).unwrap_or_default() {
+        gabc.push_str(&format!("{}:{};\n", key, value));
+    }
+    gabc.push_str("%%\n");
+    gabc.push_str(&reader.body);
+    gabc.push('\n');
+    Ok(MeiImport {
+        gabc: format_gabc(&gabc, &FormatOptions::default())?,
+        unsupported: reader.unsupported,
+    })
+}
+
+///State of `from_mei()` as it reads the music.
+struct Reader {
+    ///The gabc body written so far
+    body: String,
+    ///The gabc clef in force, e.g. "c4"
+    clef: Option<String>,
+    ///Whether the last syllable written continues its word in the next one
+    joined: bool,
+    ///Accidentals read outside a syllable, for the music of the next one
+    accidentals: String,
+    unsupported: Vec<(String, usize)>,
+}
+
+impl Reader {
+    ///Count an unsupported construct.
+    fn report(&mut self, what: String) {
+        match self.unsupported.iter_mut().find(|(w, _)| *w == what) {
+            Some(entry) => entry.1 += 1,
+            None => self.unsupported.push((what, 1)),
+        }
+    }
+    ///Append a gabc syllable to the body, after a space unless it continues the previous word.
+    ///Music with no text in the middle of a word, e.g. the rest of a syllable interrupted by a
+    ///division, is added to the syllable before it.
+    fn write_syllable(&mut self, text: &str, music: &str, joined: bool) {
+        let music = format!("{}{}", std::mem::take(&mut self.accidentals), music);
+        if text.is_empty() && self.joined && self.body.ends_with(')') {
+            self.body.pop();
+            let after_barline = self.body.ends_with([',', ';', ':', '\'', '`']) || self.body.ends_with('(');
+            if !after_barline && music.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                self.body.push('/');
+            }
+            self.body.push_str(&music);
+            self.body.push(')');
+            return;
+        }
+        if !self.body.is_empty() && !self.joined {
+            self.body.push(' ');
+        }
+        self.body.push_str(&format!("{}({})", text, music));
+        self.joined = joined;
+    }
+    ///Change the clef, writing it as a syllable of its own if it differs from the clef in force.
+    fn clef(&mut self, shape: Option<&str>, line: Option<&str>) {
+        if let Some(clef) = self.read_clef(shape, line) {
+            if self.clef.as_ref() != Some(&clef) {
+                self.write_syllable("", &clef, false);
+                self.clef = Some(clef);
+            }
+        }
+    }
+    ///The gabc clef of a clef shape and line, if there is one.
+    fn read_clef(&mut self, shape: Option<&str>, line: Option<&str>) -> Option<String> {
+        let clef = format!("{}{}", shape.unwrap_or("").to_lowercase(), line.unwrap_or(""));
+        if clef_offset(&clef).is_none() {
+            self.report(format!("<clef shape=\"{}\" line=\"{}\">", shape.unwrap_or(""), line.unwrap_or("")));
+            return None;
+        }
+        Some(clef)
+    }
+    ///Read a `<syllable>` (or a `<neume>` outside any syllable) into a gabc syllable.
+    fn syllable(&mut self, node: roxmltree::Node) {
+        let mut text = String::new();
+        let mut wordpos = None;
+        let mut music = String::new();
+        //Whether the last thing in the music is a neume, which a following neume is separated from
+        let mut after_neume = false;
+        let children: Vec<_> = if node.has_tag_name("neume") {
+            vec![node]
+        } else {
+            node.children().filter(|n| n.is_element()).collect()
+        };
+        for child in children {
+            match child.tag_name().name() {
+                "syl" => {
+                    wordpos = wordpos.or(child.attribute("wordpos"));
+                    let syl: String = child.descendants().filter(|n| n.is_text()).filter_map(|n| n.text()).collect();
+                    text.push_str(&syl.split_whitespace().collect::<Vec<_>>().join(" "));
+                }
+                "neume" => {
+                    let neume = self.neume(child);
+                    if !neume.is_empty() {
+                        if after_neume {
+                            music.push('/');
+                        }
+                        music.push_str(&neume);
+                        after_neume = true;
+                    }
+                }
+                "accid" => {
+                    music.push_str(&self.accid(child));
+                    after_neume = false;
+                }
+                "clef" => {
+                    if let Some(clef) = self.read_clef(child.attribute("shape"), child.attribute("line")) {
+                        music.push_str(&clef);
+                        self.clef = Some(clef);
+                        after_neume = false;
+                    }
+                }
+                "divLine" | "division" => {
+                    music.push_str(&self.barline(child));
+                    after_neume = false;
+                }
+                other => self.report(format!("<{}>", other)),
+            }
+        }
+        if text.contains(['(', '%']) {
+            self.report("\"(\" or \"%\" in syllable text".to_string());
+            text.retain(|c| !matches!(c, '(' | '%'));
+        }
+        if !text.is_empty() || !music.is_empty() {
+            self.write_syllable(&text, &music, matches!(wordpos, Some("i") | Some("m")));
+        }
+    }
+    ///The gabc notes of a `<neume>`.
+    fn neume(&mut self, node: roxmltree::Node) -> String {
+        let mut result = String::new();
+        for child in node.children().filter(|n| n.is_element()) {
+            if !child.has_tag_name("nc") {
+                self.report(format!("<{}>", child.tag_name().name()));
+                continue;
+            }
+            let position = match self.position(child) {
+                Some(p) => p,
+                None => continue,
+            };
+            for attribute in child.attributes() {
+                //Identifiers and facsimile zones are layout, not music
+                if !["pname", "oct", "ploc", "oloc", "tilt", "id", "facs"].contains(&attribute.name()) {
+                    self.report(format!("<nc {}>", attribute.name()));
+                }
+            }
+            let mut suffix = String::new();
+            match child.attribute("tilt") {
+                None => result.push(position),
+                Some("se") => result.push(position.to_ascii_uppercase()),
+                Some("n") => {
+                    result.push(position);
+                    suffix.push('v');
+                }
+                Some(tilt) => {
+                    self.report(format!("tilt=\"{}\"", tilt));
+                    result.push(position);
+                }
+            }
+            let mut liquescent = None;
+            for shape in child.children().filter(|n| n.is_element()) {
+                match shape.tag_name().name() {
+                    "oriscus" => suffix.push('o'),
+                    "quilisma" => suffix.push('w'),
+                    "strophicus" => suffix.push('s'),
+                    "liquescent" => {
+                        liquescent = match shape.attribute("curve") {
+                            None => Some('~'),
+                            Some("a") => Some('<'),
+                            Some("c") => Some('>'),
+                            Some(curve) => {
+                                self.report(format!("<liquescent curve=\"{}\">", curve));
+                                Some('~')
+                            }
+                        }
+                    }
+                    other => self.report(format!("<{}>", other)),
+                }
+            }
+            suffix.extend(liquescent);
+            result.push_str(&suffix);
+        }
+        result
+    }
+    ///The gabc staff position of an element with `@pname` and `@oct` (or `@ploc` and `@oloc`)
+    ///under the clef in force, if it has one.
+    fn position(&mut self, node: roxmltree::Node) -> Option<char> {
+        let pname = node.attribute("pname").or(node.attribute("ploc"));
+        let oct = node.attribute("oct").or(node.attribute("oloc"));
+        let (letter, octave) = match (pname, oct.and_then(|o| o.parse::<i32>().ok())) {
+            (Some(p), Some(o)) if p.len() == 1 && ("a"..="g").contains(&p) => (p.as_bytes()[0] - b'a', o),
+            _ => {
+                self.report(format!("<{}> without a pitch", node.tag_name().name()));
+                return None;
+            }
+        };
+        let offset = match self.clef.as_ref() {
+            Some(clef) => clef_offset(clef).unwrap() as i32,
+            None => {
+                self.report(format!("<{}> before any clef", node.tag_name().name()));
+                return None;
+            }
+        };
+        //Inverse of `events::pitch_name()`
+        let letter = letter as i32;
+        let pitch = 7 * (octave - if letter >= 2 { 3 } else { 2 }) + letter;
+        match pitch - offset {
+            p @ 0..=12 => Some((b'a' + p as u8) as char),
+            _ => {
+                self.report(format!("<{}> outside the staff", node.tag_name().name()));
+                None
+            }
+        }
+    }
+    ///The gabc sign of an `<accid>`, e.g. "ix".
+    fn accid(&mut self, node: roxmltree::Node) -> String {
+        let sign = match node.attribute("accid") {
+            Some("f") => 'x',
+            Some("n") => 'y',
+            Some("s") => '#',
+            other => {
+                self.report(format!("<accid accid=\"{}\">", other.unwrap_or("")));
+                return String::new();
+            }
+        };
+        match self.position(node) {
+            Some(position) => format!("{}{}", position, sign),
+            None => String::new(),
+        }
+    }
+    ///The gabc barline of a `<divLine>` or `<division>`: the inverse of `division()`, with
+    ///"maxima" read as a full bar and "caesura" as a virgula.
+    fn barline(&mut self, node: roxmltree::Node) -> String {
+        let barline = match node.attribute("form") {
+            Some("virgula") | Some("caesura") => "`",
+            Some("minima") => ",",
+            Some("minor") => ";",
+            Some("maior") | Some("maxima") => ":",
+            Some("finalis") => "::",
+            other => {
+                self.report(format!("<{} form=\"{}\">", node.tag_name().name(), other.unwrap_or("")));
+                ","
+            }
+        };
+        barline.to_string()
+    }
+}
+
+///The gabc header attributes found in an `<meiHead>`. Characters that cannot appear in gabc
+///attribute values (":" and ";") become commas.
+fn header(head: roxmltree::Node) -> Vec<(&'static str, String)> {
+    let text = |node: roxmltree::Node| {
+        let text: String = node.descendants().filter(|n| n.is_text()).filter_map(|n| n.text()).collect();
+        text.split_whitespace().collect::<Vec<_>>().join(" ").replace([':', ';'], ",")
+    };
+    let find = |parent: &str, name: &str| {
+        head.descendants()
+            .filter(|n| n.has_tag_name(name))
+            .find(|n| n.ancestors().any(|a| a.has_tag_name(parent)))
+            .map(text)
+    };
+    let transcriber = head
+        .descendants()
+        .find(|n| n.has_tag_name("persName") && n.attribute("role") == Some("transcriber"))
+        .map(text);
+    let fields = vec![
+        ("name", find("titleStmt", "title").or_else(|| find("work", "title"))),
+        ("transcriber", transcriber),
+        ("book", find("sourceDesc", "title")),
+        ("mode", find("work", "mode")),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.filter(|v| !v.is_empty())?)))
+        .collect()
+}
+
+static HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?xml-model href="https://music-encoding.org/schema/5.0/mei-Neumes.rng" type="application/xml" schematypens="http://relaxng.org/ns/structure/1.0"?>
+<mei xmlns="http://www.music-encoding.org/ns/mei" meiversion="5.0+Neumes">
+"#;
+
+static MUSIC_START: &str = r#"  <music>
+    <body>
+      <mdiv>
+        <score>
+          <scoreDef>
+            <staffGrp>
+              <staffDef n="1" lines="4" notationtype="neume"/>
+            </staffGrp>
+          </scoreDef>
+          <section>
+            <staff n="1">
+              <layer n="1">
+"#;
+
+static MUSIC_END: &str = r#"              </layer>
+            </staff>
+          </section>
+        </score>
+      </mdiv>
+    </body>
+  </music>
+</mei>
+"#;
@@ -0,0 +1,149 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Standard MIDI File output, for rehearsal tracks. A file is written as a single track (SMF
+//! type 0) with the file's name as the track name, one note for each sung note, a lyric
+//! meta-event at the first note of each syllable, and a rest at each division of the chant.
+//! Pitches are those of `GabcFile::as_lilypond()` (c' is middle C, MIDI note 60) before
+//! transposition. The rhythm is that of the Solesmes editions: every note has the same basic
+//! length unless a punctum mora or an episema lengthens it.
+
+use events::{events, midi_key, Event};
+use {GabcFile, Syllabic};
+
+///Ticks per quarter note; a plain note is an eighth note.
+const DIVISION: u16 = 480;
+///Ticks per plain note.
+const PULSE: f64 = DIVISION as f64 / 2.0;
+
+///Options for `GabcFile::as_midi()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiOptions {
+    ///Plain notes per minute
+    pub tempo: u32,
+    ///Semitones to transpose by
+    pub transposition: i32,
+    ///General MIDI program (0-based), e.g. 52 for a choir
+    pub program: u8,
+    ///Length of a note with a punctum mora ("."), in plain notes. Each further mora, as in a
+    ///double mora (".."), lengthens the note by as much again.
+    pub mora_length: f64,
+    ///Length of a note with a horizontal episema ("_"), in plain notes
+    pub episema_length: f64,
+    ///Length of the rest at a quarter bar or virgula, in plain notes. Half bars have twice this
+    ///rest, full bars three times and double bars four times.
+    pub pause_length: f64,
+}
+
+impl Default for MidiOptions {
+    ///144 notes per minute at written pitch on a choir, with doubled morae, episemata half as
+    ///long again as a plain note and a rest of half a plain note per division level.
+    fn default() -> MidiOptions {
+        MidiOptions {
+            tempo: 144,
+            transposition: 0,
+            program: 52,
+            mora_length: 2.0,
+            episema_length: 1.5,
+            pause_length: 0.5,
+        }
+    }
+}
+
+///Translate a `GabcFile` into a type-0 Standard MIDI File.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::midi::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh)ia.(g.) (::)");
+///let smf = f.as_midi(&MidiOptions::default());
+///assert_eq!(&smf[0..4], b"MThd");
+///assert_eq!(smf.windows(3).filter(|w| w[0] == 0x90 && w[2] > 0).count(), 6);
+///assert_eq!(smf.windows(2).filter(|w| w == &[0xFF, 0x05]).count(), 4);
+///```
+pub fn to_midi(file: &GabcFile, options: &MidiOptions) -> Vec<u8> {
+    let mut track = Vec::new();
+    if let Some((_, name)) = file.attributes.iter().find(|(k, _)| k.trim() == "name") {
+        meta(&mut track, 0, 0x03, name.trim().as_bytes());
+    }
+    let quarter = 120_000_000 / u64::from(options.tempo.max(1));
+    meta(&mut track, 0, 0x51, &quarter.min(0xFF_FFFF).to_be_bytes()[5..]);
+    delta(&mut track, 0);
+    track.extend([0xC0, options.program & 0x7F]);
+    let lyrics = file.syllable_lyrics();
+    let mut previous_syllable = None;
+    //Ticks since the last event
+    let mut wait = 0.0;
+    for event in events(file) {
+        match event {
+            Event::Note(n) => {
+                if previous_syllable != Some(n.syllable) {
+                    if let Some((text, syllabic)) = &lyrics[n.syllable] {
+                        let hyphen = if matches!(syllabic, Syllabic::Begin | Syllabic::Middle) { "-" } else { "" };
+                        meta(&mut track, ticks(&mut wait), 0x05, format!("{}{}", text, hyphen).as_bytes());
+                    }
+                }
+                previous_syllable = Some(n.syllable);
+                let key = (midi_key(n.pitch, n.alter) + options.transposition).clamp(0, 127) as u8;
+                let mut length = 1.0 + n.morae as f64 * (options.mora_length - 1.0);
+                if n.episema {
+                    length *= options.episema_length;
+                }
+                delta(&mut track, ticks(&mut wait));
+                track.extend([0x90, key, 80]);
+                wait += length * PULSE;
+                delta(&mut track, ticks(&mut wait));
+                track.extend([0x80, key, 0]);
+            }
+            Event::Barline(bar) => {
+                let level = match bar {
+                    "," | ",_" | ",0" | "'" | "`" => 1.0,
+                    "::" => 4.0,
+                    b if b.starts_with(';') => 2.0,
+                    _ => 3.0,
+                };
+                wait += level * options.pause_length * PULSE;
+            }
+        }
+    }
+    meta(&mut track, ticks(&mut wait), 0x2F, &[]);
+    let mut result = Vec::new();
+    result.extend(b"MThd");
+    result.extend(6u32.to_be_bytes());
+    result.extend(0u16.to_be_bytes());
+    result.extend(1u16.to_be_bytes());
+    result.extend(DIVISION.to_be_bytes());
+    result.extend(b"MTrk");
+    result.extend((track.len() as u32).to_be_bytes());
+    result.extend(track);
+    result
+}
+
+///Take the whole ticks out of a wait, leaving the fraction for the next event.
+fn ticks(wait: &mut f64) -> u32 {
+    let whole = wait.floor();
+    *wait -= whole;
+    whole as u32
+}
+
+///Write a delta time as a variable-length quantity.
+fn delta(track: &mut Vec<u8>, ticks: u32) {
+    let mut bytes = vec![(ticks & 0x7F) as u8];
+    let mut rest = ticks >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    track.extend(bytes.iter().rev());
+}
+
+///Write a meta-event.
+fn meta(track: &mut Vec<u8>, ticks: u32, kind: u8, data: &[u8]) {
+    delta(track, ticks);
+    track.extend([0xFF, kind]);
+    delta(track, data.len() as u32);
+    track.extend(data);
+}
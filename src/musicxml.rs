@@ -0,0 +1,543 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! MusicXML output. Chant is written as a single unmetered part (`<senza-misura/>`) in the
+//! treble clef, at the same pitch as `GabcFile::as_lilypond()`: stemless eighth notes (quarter
+//! notes under a punctum mora), a slur over the notes of each syllable, and a measure for each
+//! division of the chant. Quarter bars and virgulae become breath marks.
+//!
+//! `from_musicxml()` reads single-voice MusicXML, such as this or chant entered in MuseScore,
+//! back into gabc.
+
+use events::{events, pitch_name, Event, SungNote};
+use formatter::{format_gabc, FormatOptions};
+use roxmltree::Node;
+use {choose_clef, clef_offset, escape_xml, GabcFile, Syllabic};
+
+///Number of divisions of a quarter note: plain notes are eighth notes.
+const DIVISIONS: u32 = 2;
+
+///A note ready to be written.
+struct XmlNote {
+    step: char,
+    octave: i32,
+    alter: i32,
+    accidental: bool,
+    mora: bool,
+    slur: Option<&'static str>,
+    breath: bool,
+    lyric: Option<(String, Syllabic)>,
+}
+
+///A measure: its notes and the style of the barline that ends it, if any.
+struct Measure {
+    notes: Vec<XmlNote>,
+    bar: Option<&'static str>,
+}
+
+///Translate a `GabcFile` into a MusicXML 4.0 score-partwise document.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh)ia.(g.) (::)");
+///let xml = f.as_musicxml();
+///assert!(xml.contains("<work-title>Test</work-title>"));
+///assert_eq!(xml.matches("<note>").count(), 6);
+///assert!(xml.contains("<syllabic>begin</syllabic>"));
+///assert!(xml.contains("<bar-style>light-light</bar-style>"));
+///```
+pub fn to_musicxml(file: &GabcFile) -> String {
+    let mut result = String::new();
+    result.push_str(HEADER);
+    result.push_str("<score-partwise version=\"4.0\">\n");
+    result.push_str(&metadata(file));
+    result.push_str(PART_LIST);
+    result.push_str("  <part id=\"P1\">\n");
+    for (i, measure) in measures(file).iter().enumerate() {
+        result.push_str(&format!("    <measure number=\"{}\">\n", i + 1));
+        if i == 0 {
+            result.push_str(ATTRIBUTES);
+        }
+        for note in &measure.notes {
+            result.push_str(&note_xml(note));
+        }
+        if let Some(style) = measure.bar {
+            result.push_str("      <barline location=\"right\">\n");
+            result.push_str(&format!("        <bar-style>{}</bar-style>\n", style));
+            result.push_str("      </barline>\n");
+        }
+        result.push_str("    </measure>\n");
+    }
+    result.push_str("  </part>\n</score-partwise>\n");
+    result
+}
+
+///Work title and identification elements from the gabc header. "name" gives the title,
+///"author" and "transcriber" the creators and "book" the source; other attributes are kept as
+///miscellaneous fields.
+fn metadata(file: &GabcFile) -> String {
+    let mut result = String::new();
+    let mut creators = String::new();
+    let mut source = String::new();
+    let mut miscellaneous = String::new();
+    for (key, value) in &file.attributes {
+        let (key, value) = (key.trim(), escape_xml(value.trim()));
+        match key {
+            "name" => {
+                result.push_str(&format!("  <work>\n    <work-title>{}</work-title>\n  </work>\n", value));
+                result.push_str(&format!("  <movement-title>{}</movement-title>\n", value));
+            }
+            "author" => creators.push_str(&format!("    <creator type=\"composer\">{}</creator>\n", value)),
+            "transcriber" => creators.push_str(&format!("    <creator type=\"transcriber\">{}</creator>\n", value)),
+            "book" if source.is_empty() => source = format!("    <source>{}</source>\n", value),
+            _ => miscellaneous.push_str(&format!(
+                "      <miscellaneous-field name=\"{}\">{}</miscellaneous-field>\n",
+                escape_xml(key),
+                value
+            )),
+        }
+    }
+    result.push_str("  <identification>\n");
+    result.push_str(&creators);
+    result.push_str("    <encoding>\n      <software>gabc-parser</software>\n    </encoding>\n");
+    result.push_str(&source);
+    if !miscellaneous.is_empty() {
+        result.push_str("    <miscellaneous>\n");
+        result.push_str(&miscellaneous);
+        result.push_str("    </miscellaneous>\n");
+    }
+    result.push_str("  </identification>\n");
+    result
+}
+
+///Divide the notes of a file into measures at its barlines, with slurs, breath marks and lyrics.
+fn measures(file: &GabcFile) -> Vec<Measure> {
+    let lyrics = file.syllable_lyrics();
+    let events = events(file);
+    let mut measures = vec![Measure {
+        notes: Vec::new(),
+        bar: None,
+    }];
+    let mut previous_syllable = None;
+    for (k, event) in events.iter().enumerate() {
+        match event {
+            Event::Note(n) => {
+                let first = previous_syllable != Some(n.syllable);
+                previous_syllable = Some(n.syllable);
+                let last = !matches!(events[k + 1..].iter().find(|e| matches!(e, Event::Note(_))),
+                    Some(Event::Note(next)) if next.syllable == n.syllable);
+                let slur = match (first, last) {
+                    (true, false) => Some("start"),
+                    (false, true) => Some("stop"),
+                    _ => None,
+                };
+                let lyric = if first { lyrics[n.syllable].clone() } else { None };
+                measures.last_mut().unwrap().notes.push(xml_note(n, slur, lyric));
+            }
+            Event::Barline(bar) => {
+                let style = match *bar {
+                    "," | ",_" | ",0" | "'" | "`" => None,
+                    "::" => Some("light-light"),
+                    b if b.starts_with(';') => Some("short"),
+                    _ => Some("regular"),
+                };
+                let current = measures.last_mut().unwrap();
+                match style {
+                    None => {
+                        if let Some(note) = current.notes.last_mut() {
+                            note.breath = true;
+                        }
+                    }
+                    //A division with no notes before it has nothing to end
+                    Some(_) if current.notes.is_empty() => {}
+                    Some(style) => {
+                        current.bar = Some(style);
+                        measures.push(Measure {
+                            notes: Vec::new(),
+                            bar: None,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    if measures.len() > 1 && measures.last().unwrap().notes.is_empty() {
+        measures.pop();
+    }
+    measures
+}
+
+///Prepare a sung note for writing, an octave above its Lilypond (untransposed) pitch.
+fn xml_note(n: &SungNote, slur: Option<&'static str>, lyric: Option<(String, Syllabic)>) -> XmlNote {
+    let (letter, octave) = pitch_name(n.pitch);
+    XmlNote {
+        step: letter.to_ascii_uppercase(),
+        octave: octave + 1,
+        alter: n.alter,
+        accidental: n.accidental,
+        mora: n.morae > 0,
+        slur,
+        breath: false,
+        lyric,
+    }
+}
+
+///Write a `<note>` element, with its children in the order required by the MusicXML schema.
+fn note_xml(note: &XmlNote) -> String {
+    let mut result = String::from("      <note>\n        <pitch>\n");
+    result.push_str(&format!("          <step>{}</step>\n", note.step));
+    if note.alter != 0 {
+        result.push_str(&format!("          <alter>{}</alter>\n", note.alter));
+    }
+    result.push_str(&format!("          <octave>{}</octave>\n        </pitch>\n", note.octave));
+    let (duration, kind) = if note.mora { (DIVISIONS, "quarter") } else { (DIVISIONS / 2, "eighth") };
+    result.push_str(&format!("        <duration>{}</duration>\n", duration));
+    result.push_str("        <voice>1</voice>\n");
+    result.push_str(&format!("        <type>{}</type>\n", kind));
+    if note.accidental {
+        let accidental = match note.alter {
+            -1 => "flat",
+            1 => "sharp",
+            _ => "natural",
+        };
+        result.push_str(&format!("        <accidental>{}</accidental>\n", accidental));
+    }
+    result.push_str("        <stem>none</stem>\n");
+    if note.slur.is_some() || note.breath {
+        result.push_str("        <notations>\n");
+        if let Some(slur) = note.slur {
+            result.push_str(&format!("          <slur type=\"{}\" number=\"1\"/>\n", slur));
+        }
+        if note.breath {
+            result.push_str("          <articulations>\n            <breath-mark/>\n          </articulations>\n");
+        }
+        result.push_str("        </notations>\n");
+    }
+    if let Some((text, syllabic)) = &note.lyric {
+        let syllabic = match syllabic {
+            Syllabic::Single => "single",
+            Syllabic::Begin => "begin",
+            Syllabic::Middle => "middle",
+            Syllabic::End => "end",
+        };
+        result.push_str("        <lyric number=\"1\">\n");
+        result.push_str(&format!("          <syllabic>{}</syllabic>\n", syllabic));
+        result.push_str(&format!("          <text>{}</text>\n", escape_xml(text)));
+        result.push_str("        </lyric>\n");
+    }
+    result.push_str("      </note>\n");
+    result
+}
+
+static HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+"#;
+
+static PART_LIST: &str = r#"  <part-list>
+    <score-part id="P1">
+      <part-name print-object="no">Chant</part-name>
+    </score-part>
+  </part-list>
+"#;
+
+static ATTRIBUTES: &str = r#"      <attributes>
+        <divisions>2</divisions>
+        <key print-object="no">
+          <fifths>0</fifths>
+        </key>
+        <time print-object="no">
+          <senza-misura/>
+        </time>
+        <clef>
+          <sign>G</sign>
+          <line>2</line>
+        </clef>
+      </attributes>
+"#;
+
+///A note or division read from MusicXML.
+enum Item {
+    Note {
+        ///Pitch index, as returned by `Note::pitch_index()`
+        pitch: usize,
+        ///Chromatic alteration: -1 for flat, 1 for sharp
+        alter: i32,
+        ///Length in quarter notes
+        length: f64,
+        ///Whether the note is under the same slur as the note before it
+        joined: bool,
+        ///Text and `<syllabic>` of its lyric
+        lyric: Option<(String, String)>,
+    },
+    Bar(&'static str),
+}
+
+///Read the first part of a MusicXML score-partwise document into gabc. The part must have a
+///single voice with no chords; rests are skipped, and tied notes are read as one. Each note with
+///a lyric (the first, if there are several) starts a syllable, and the notes after it up to the
+///next lyric are its melisma; `<syllabic>` gives the word boundaries. Within a syllable, notes
+///under one slur form a neume. Notes longer than the most common length get a punctum mora.
+///Barlines become divisions ("::" for double and final bars, ";" for short bars, "," for dashed,
+///dotted and tick bars and ":" for others), and breath marks and caesuras become virgulae
+///(","). The clef is chosen to centre the melody on the staff. The title, composer
+///("author"), transcriber, source ("book") and miscellaneous fields become gabc attributes. The
+///gabc is written in canonical form (see the `formatter` module).
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::musicxml::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh/ixi)ia.(g.) (::)");
+/////The same melody, a step lower on the staff under a C clef on the third line
+///assert_eq!(from_musicxml(&f.as_musicxml()).unwrap(), "name: Test;
+///%%
+///(c3) Al(ef)le(h)lu(hf/gxg)ia.(e.) (::)
+///");
+///assert!(from_musicxml("<score-timewise/>").is_err());
+///```
+pub fn from_musicxml(xml: &str) -> Result<String, String> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let document = roxmltree::Document::parse_with_options(xml, options).map_err(|e| format!("{}", e))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "score-partwise" {
+        return Err(format!("not a partwise MusicXML score: <{}>", root.tag_name().name()));
+    }
+    let part = root.children().find(|n| n.has_tag_name("part")).ok_or("no part in the score")?;
+    let mut items: Vec<Item> = Vec::new();
+    //Divisions of a quarter note
+    let mut divisions = 1.0;
+    let mut voice = None;
+    let mut in_slur = false;
+    let mut tied = false;
+    for measure in part.children().filter(|n| n.has_tag_name("measure")) {
+        for child in measure.children().filter(|n| n.is_element()) {
+            match child.tag_name().name() {
+                "attributes" => {
+                    if let Some(d) = child_text(child, "divisions") {
+                        divisions = d.parse().map_err(|_| format!("invalid divisions: {}", d))?;
+                    }
+                }
+                "backup" | "forward" => return Err("more than one voice".to_string()),
+                "barline" => {
+                    let bar = match child_text(child, "bar-style").unwrap_or("regular") {
+                        "none" => continue,
+                        "light-light" | "light-heavy" | "heavy-heavy" => "::",
+                        "short" => ";",
+                        "dashed" | "dotted" | "tick" => ",",
+                        _ => ":",
+                    };
+                    items.push(Item::Bar(bar));
+                }
+                "note" => {
+                    if child.children().any(|n| n.has_tag_name("chord")) {
+                        return Err("chords are not supported".to_string());
+                    }
+                    let this_voice = child_text(child, "voice").unwrap_or("1");
+                    if *voice.get_or_insert(this_voice) != this_voice {
+                        return Err("more than one voice".to_string());
+                    }
+                    if child.children().any(|n| n.has_tag_name("rest")) {
+                        continue;
+                    }
+                    let pitch = child.children().find(|n| n.has_tag_name("pitch")).ok_or("unpitched note")?;
+                    let step = child_text(pitch, "step").unwrap_or("");
+                    let letter = match step {
+                        "A" | "B" | "C" | "D" | "E" | "F" | "G" => (step.as_bytes()[0] - b'A') as i32,
+                        _ => return Err(format!("invalid step: {}", step)),
+                    };
+                    let octave: i32 = child_text(pitch, "octave").and_then(|o| o.parse().ok()).ok_or("invalid octave")?;
+                    let alter: f64 = child_text(pitch, "alter").map_or(Ok(0.0), |a| a.parse()).map_err(|_| "invalid alter")?;
+                    if alter.fract() != 0.0 || alter.abs() > 1.0 {
+                        return Err(format!("unsupported alteration: {}", alter));
+                    }
+                    //Inverse of `xml_note()`
+                    let pitch = 7 * (octave - 1 - if letter >= 2 { 3 } else { 2 }) + letter;
+                    if pitch < 0 {
+                        return Err(format!("note too low: {}{}", step, octave));
+                    }
+                    let length = child_text(child, "duration").and_then(|d| d.parse::<f64>().ok()).unwrap_or(divisions) / divisions;
+                    let notations: Vec<Node> = child.children().filter(|n| n.has_tag_name("notations")).flat_map(|n| n.descendants()).collect();
+                    let lyric = child.children().find(|n| n.has_tag_name("lyric")).and_then(|l| {
+                        let text: Vec<&str> = l.children().filter(|n| n.has_tag_name("text")).filter_map(|n| n.text()).collect();
+                        let syllabic = child_text(l, "syllabic").unwrap_or("single");
+                        Some((text.join(" "), syllabic.to_string())).filter(|_| !text.is_empty())
+                    });
+                    let was_tied = tied;
+                    tied = notations.iter().any(|n| n.has_tag_name("tied") && n.attribute("type") == Some("start"));
+                    match items.last_mut() {
+                        //The end of a tie lengthens the note it is tied to
+                        Some(Item::Note { length: l, .. }) if was_tied && lyric.is_none() => *l += length,
+                        _ => items.push(Item::Note {
+                            pitch: pitch as usize,
+                            alter: alter as i32,
+                            length,
+                            joined: in_slur,
+                            lyric,
+                        }),
+                    }
+                    for slur in notations.iter().filter(|n| n.has_tag_name("slur")) {
+                        match slur.attribute("type") {
+                            Some("start") => in_slur = true,
+                            Some("stop") => in_slur = false,
+                            _ => {}
+                        }
+                    }
+                    if notations.iter().any(|n| n.has_tag_name("breath-mark") || n.has_tag_name("caesura")) {
+                        items.push(Item::Bar(","));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut gabc = String::new();
+    for (key, value) in read_metadata(root) {
+        gabc.push_str(&format!("{}:{};\n", key.replace([':', ';'], ","), value.replace([':', ';'], ",")));
+    }
+    gabc.push_str("%%\n");
+    gabc.push_str(&write_gabc(&items)?);
+    format_gabc(&gabc, &FormatOptions::default())
+}
+
+///The text of the first child of a node with a tag name.
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|n| n.has_tag_name(name)).and_then(|n| n.text()).map(|t| t.trim())
+}
+
+///Gabc attributes from the work title, creators, source and miscellaneous fields of a score.
+fn read_metadata(root: Node) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let title = root.children().find(|n| n.has_tag_name("work")).and_then(|w| child_text(w, "work-title"));
+    if let Some(title) = title.or_else(|| child_text(root, "movement-title")) {
+        result.push(("name".to_string(), title.to_string()));
+    }
+    let identification = match root.children().find(|n| n.has_tag_name("identification")) {
+        Some(i) => i,
+        None => return result,
+    };
+    for node in identification.descendants().filter(|n| n.is_element()) {
+        let text = node.text().unwrap_or("").trim().to_string();
+        match (node.tag_name().name(), node.attribute("type"), node.attribute("name")) {
+            ("creator", Some("composer"), _) => result.push(("author".to_string(), text)),
+            ("creator", Some("transcriber"), _) => result.push(("transcriber".to_string(), text)),
+            ("source", _, _) => result.push(("book".to_string(), text)),
+            ("miscellaneous-field", _, Some(name)) => result.push((name.to_string(), text)),
+            _ => {}
+        }
+    }
+    result
+}
+
+///A gabc syllable being written: its text and music.
+struct GabcSyllable {
+    text: String,
+    music: String,
+}
+
+///Write notes and divisions read from MusicXML as a gabc body.
+fn write_gabc(items: &[Item]) -> Result<String, String> {
+    let pitches: Vec<usize> = items
+        .iter()
+        .filter_map(|i| match i {
+            Item::Note { pitch, .. } => Some(*pitch),
+            _ => None,
+        })
+        .collect();
+    let clef = choose_clef(&pitches).ok_or("the range of the melody is too wide for a clef")?;
+    let offset = clef_offset(clef).unwrap();
+    //The most common length of a note is a plain note
+    let lengths: Vec<f64> = items
+        .iter()
+        .filter_map(|i| match i {
+            Item::Note { length, .. } => Some(*length),
+            _ => None,
+        })
+        .collect();
+    let plain = lengths
+        .iter()
+        .max_by_key(|l| lengths.iter().filter(|m| m == l).count())
+        .copied()
+        .unwrap_or(0.0);
+    let mut body = format!("({})", clef);
+    let mut open: Option<GabcSyllable> = None;
+    //Whether the open syllable continues its word in the next one
+    let mut continues = false;
+    //Alterations shown in the gabc since the last division
+    let mut shown: Vec<(usize, i32)> = Vec::new();
+    let close = |body: &mut String, open: &mut Option<GabcSyllable>| {
+        if let Some(s) = open.take() {
+            body.push_str(&format!("{}({})", s.text, s.music));
+        }
+    };
+    for item in items {
+        match item {
+            //A virgula after a note stays in its syllable, as in "gén(jh,ji)"
+            Item::Bar(",") if open.is_some() => {
+                open.as_mut().unwrap().music.push(',');
+                shown.clear();
+            }
+            Item::Bar(bar) => {
+                close(&mut body, &mut open);
+                body.push_str(&format!(" ({})", bar));
+                shown.clear();
+            }
+            Item::Note {
+                pitch,
+                alter,
+                length,
+                joined,
+                lyric,
+            } => {
+                if lyric.is_some() || open.is_none() {
+                    close(&mut body, &mut open);
+                    let text = match lyric {
+                        Some((text, syllabic)) => {
+                            let space = if continues { "" } else { " " };
+                            continues = matches!(syllabic.as_str(), "begin" | "middle");
+                            format!("{}{}", space, text.replace(['(', ')', '%'], ""))
+                        }
+                        None => {
+                            continues = false;
+                            " ".to_string()
+                        }
+                    };
+                    open = Some(GabcSyllable {
+                        text,
+                        music: String::new(),
+                    });
+                }
+                let syllable = open.as_mut().unwrap();
+                let position = (b'a' + (pitch - offset) as u8) as char;
+                let current = shown.iter().find(|a| a.0 == *pitch).map_or(0, |a| a.1);
+                //As in gabc, a sign starts a new neume; a virgula already separates neumes
+                if (!*joined || *alter != current) && !syllable.music.is_empty() && !syllable.music.ends_with(',') {
+                    syllable.music.push('/');
+                }
+                if *alter != current {
+                    syllable.music.push(position);
+                    syllable.music.push(match alter {
+                        -1 => 'x',
+                        1 => '#',
+                        _ => 'y',
+                    });
+                    shown.retain(|a| a.0 != *pitch);
+                    shown.push((*pitch, *alter));
+                }
+                syllable.music.push(position);
+                if *length > plain {
+                    syllable.music.push('.');
+                }
+            }
+        }
+    }
+    close(&mut body, &mut open);
+    body.push('\n');
+    Ok(body)
+}
@@ -0,0 +1,281 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! PDF output of the square-notation layout of the `render` module, in points. The music is
+//! either set on one page as tall as it needs, or divided between pages of a fixed height at the
+//! ends of its lines. Notes and staves are drawn as vector shapes, and the text is set in the
+//! built-in DejaVu Serif, embedded in the file, so it can be printed anywhere and searched. This
+//! module needs the "png-pdf" feature.
+
+use font;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use render::{layout, number, RenderOptions, Shape, System};
+use std::collections::BTreeMap;
+use ttf_parser::Face;
+use GabcFile;
+
+///Options for `GabcFile::as_pdf()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfOptions {
+    ///Layout of the music, in points; its line width and the margins give the page width
+    pub render: RenderOptions,
+    ///Height of each page in points, or `None` for a single page holding all the music
+    pub page_height: Option<f64>,
+    ///Margin on each side of the page, in points
+    pub margin: f64,
+}
+
+impl Default for PdfOptions {
+    ///A4 pages with 15 mm margins and staff lines 6 points apart.
+    fn default() -> PdfOptions {
+        PdfOptions {
+            render: RenderOptions {
+                staff_size: 6.0,
+                line_width: 595.28 - 2.0 * 42.52,
+            },
+            page_height: Some(841.89),
+            margin: 42.52,
+        }
+    }
+}
+
+///Objects written before the pages.
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+const INFO: usize = 3;
+const FONT: usize = 4;
+const CID_FONT: usize = 5;
+const DESCRIPTOR: usize = 6;
+const FONT_FILE: usize = 7;
+const TO_UNICODE: usize = 8;
+
+///Render a `GabcFile` in square notation as a PDF document.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::pdf::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(g.) (::)");
+///let pdf = f.as_pdf(&PdfOptions::default());
+///assert!(pdf.starts_with(b"%PDF-1.7"));
+///assert!(pdf.ends_with(b"%%EOF\n"));
+///let text = String::from_utf8_lossy(&pdf);
+///assert_eq!(text.matches("/Type /Page ").count(), 1);
+///assert!(text.contains("/MediaBox [0 0 595.28 841.89]"));
+///```
+pub fn to_pdf(file: &GabcFile, options: &PdfOptions) -> Vec<u8> {
+    let layout = layout(file, &options.render);
+    let width = layout.width + 2.0 * options.margin;
+    //Divide the systems between pages
+    let mut pages: Vec<Vec<System>> = vec![Vec::new()];
+    let mut used = 0.0;
+    for system in layout.systems {
+        if let Some(height) = options.page_height {
+            let page = pages.last().unwrap();
+            if !page.is_empty() && used + system.height > height - 2.0 * options.margin {
+                pages.push(Vec::new());
+                used = 0.0;
+            }
+        }
+        used += system.height;
+        pages.last_mut().unwrap().push(system);
+    }
+    let height = options.page_height.unwrap_or(used + 2.0 * options.margin);
+    let face = font::face();
+    //Glyphs used by the text, with the characters they stand for
+    let mut glyphs: BTreeMap<u16, char> = BTreeMap::new();
+    let mut writer = Writer {
+        data: b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n".to_vec(),
+        offsets: BTreeMap::new(),
+    };
+    let mut kids = Vec::new();
+    for (i, systems) in pages.iter().enumerate() {
+        let (page, contents) = (TO_UNICODE + 1 + 2 * i, TO_UNICODE + 2 + 2 * i);
+        kids.push(format!("{} 0 R", page));
+        writer.object(
+            page,
+            &format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 {} 0 R >> >> /Contents {} 0 R >>",
+                PAGES,
+                number(width),
+                number(height),
+                FONT,
+                contents
+            ),
+        );
+        let mut content = format!("1 0 0 -1 0 {} cm\n", number(height));
+        let mut top = options.margin;
+        for system in systems {
+            content.push_str(&format!("q 1 0 0 1 {} {} cm\n", number(options.margin), number(top)));
+            for shape in &system.shapes {
+                content.push_str(&operators(shape, face, &mut glyphs));
+            }
+            content.push_str("Q\n");
+            top += system.height;
+        }
+        writer.stream(contents, "", content.as_bytes());
+    }
+    writer.object(CATALOG, &format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES));
+    writer.object(PAGES, &format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), kids.len()));
+    let title = file.attributes.iter().find(|(k, _)| k.trim() == "name").map_or("", |(_, v)| v.trim());
+    writer.object(INFO, &format!("<< /Title {} /Producer (gabc-parser) >>", text_string(title)));
+    write_font(&mut writer, face, &glyphs);
+    writer.finish()
+}
+
+///Content stream operators drawing a shape, noting the glyphs of any text.
+fn operators(shape: &Shape, face: &Face, glyphs: &mut BTreeMap<u16, char>) -> String {
+    let path = |points: &[(f64, f64)]| {
+        let mut result = String::new();
+        for (i, (x, y)) in points.iter().enumerate() {
+            result.push_str(&format!("{} {} {} ", number(*x), number(*y), if i == 0 { "m" } else { "l" }));
+        }
+        result
+    };
+    match shape {
+        Shape::Polygon(points) => format!("{}h f\n", path(points)),
+        Shape::Line(points, width) => format!("{} w {}S\n", number(*width), path(points)),
+        Shape::Circle((x, y), r) => {
+            //Four Bézier quarters, anticlockwise from the right
+            let k = 0.5523 * r;
+            let points = [
+                (x + r, y + k), (x + k, y + r), (*x, y + r),
+                (x - k, y + r), (x - r, y + k), (x - r, *y),
+                (x - r, y - k), (x - k, y - r), (*x, y - r),
+                (x + k, y - r), (x + r, y - k), (x + r, *y),
+            ];
+            let mut result = format!("{} {} m", number(x + r), number(*y));
+            for (i, (px, py)) in points.iter().enumerate() {
+                result.push_str(&format!(" {} {}", number(*px), number(*py)));
+                if i % 3 == 2 {
+                    result.push_str(" c");
+                }
+            }
+            result.push_str(" f\n");
+            result
+        }
+        Shape::Text { x, y, size, text, rubric } => {
+            let mut hex = String::new();
+            for c in text.chars() {
+                let glyph = font::glyph(face, c);
+                glyphs.insert(glyph.0, c);
+                hex.push_str(&format!("{:04X}", glyph.0));
+            }
+            let (color, reset) = if *rubric { ("0.753 0 0 rg ", " 0 g") } else { ("", "") };
+            format!(
+                "{}BT /F1 {} Tf 1 0 0 -1 {} {} Tm <{}> Tj ET{}\n",
+                color,
+                number(*size),
+                number(*x),
+                number(*y),
+                hex,
+                reset
+            )
+        }
+    }
+}
+
+///Write the font objects: a Type 0 font over the embedded TrueType font, addressed by glyph
+///number, with the widths of the glyphs used and their characters for text extraction.
+fn write_font(writer: &mut Writer, face: &Face, glyphs: &BTreeMap<u16, char>) {
+    let em = |units: i16| number(f64::from(units) * 1000.0 / f64::from(face.units_per_em()));
+    writer.object(
+        FONT,
+        &format!(
+            "<< /Type /Font /Subtype /Type0 /BaseFont /DejaVuSerif /Encoding /Identity-H /DescendantFonts [{} 0 R] /ToUnicode {} 0 R >>",
+            CID_FONT, TO_UNICODE
+        ),
+    );
+    let widths: Vec<String> = glyphs
+        .keys()
+        .map(|&g| format!("{} [{}]", g, number(font::advance(face, ttf_parser::GlyphId(g)) * 1000.0)))
+        .collect();
+    writer.object(
+        CID_FONT,
+        &format!(
+            "<< /Type /Font /Subtype /CIDFontType2 /BaseFont /DejaVuSerif /CIDSystemInfo << /Registry (Adobe) /Ordering (Identity) /Supplement 0 >> /FontDescriptor {} 0 R /CIDToGIDMap /Identity /W [{}] >>",
+            DESCRIPTOR,
+            widths.join(" ")
+        ),
+    );
+    let bbox = face.global_bounding_box();
+    writer.object(
+        DESCRIPTOR,
+        &format!(
+            "<< /Type /FontDescriptor /FontName /DejaVuSerif /Flags 34 /FontBBox [{} {} {} {}] /ItalicAngle 0 /Ascent {} /Descent {} /CapHeight {} /StemV 80 /FontFile2 {} 0 R >>",
+            em(bbox.x_min),
+            em(bbox.y_min),
+            em(bbox.x_max),
+            em(bbox.y_max),
+            em(face.ascender()),
+            em(face.descender()),
+            em(face.capital_height().unwrap_or(face.ascender())),
+            FONT_FILE
+        ),
+    );
+    writer.stream(
+        FONT_FILE,
+        &format!("/Length1 {} /Filter /FlateDecode ", font::FONT.len()),
+        &compress_to_vec_zlib(font::FONT, 6),
+    );
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    let entries: Vec<(&u16, &char)> = glyphs.iter().collect();
+    for chunk in entries.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", chunk.len()));
+        for (glyph, c) in chunk {
+            let utf16: String = c.encode_utf16(&mut [0; 2]).iter().map(|u| format!("{:04X}", u)).collect();
+            cmap.push_str(&format!("<{:04X}> <{}>\n", glyph, utf16));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    writer.stream(TO_UNICODE, "", cmap.as_bytes());
+}
+
+///Writes numbered objects, keeping their offsets for the cross-reference table.
+struct Writer {
+    data: Vec<u8>,
+    offsets: BTreeMap<usize, usize>,
+}
+
+impl Writer {
+    fn object(&mut self, number: usize, body: &str) {
+        self.offsets.insert(number, self.data.len());
+        self.data.extend(format!("{} 0 obj\n{}\nendobj\n", number, body).as_bytes());
+    }
+    ///Write a stream with its length and any other dictionary entries.
+    fn stream(&mut self, number: usize, entries: &str, content: &[u8]) {
+        self.offsets.insert(number, self.data.len());
+        self.data.extend(format!("{} 0 obj\n<< {}/Length {} >>\nstream\n", number, entries, content.len()).as_bytes());
+        self.data.extend(content);
+        self.data.extend(b"\nendstream\nendobj\n");
+    }
+    ///Write the cross-reference table and trailer.
+    fn finish(mut self) -> Vec<u8> {
+        let start = self.data.len();
+        let size = self.offsets.len() + 1;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", size);
+        for offset in self.offsets.values() {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            size, CATALOG, INFO, start
+        ));
+        self.data.extend(xref.as_bytes());
+        self.data
+    }
+}
+
+///A PDF text string: UTF-16 with a byte order mark, in hexadecimal.
+fn text_string(text: &str) -> String {
+    let hex: String = text.encode_utf16().map(|u| format!("{:04X}", u)).collect();
+    format!("<FEFF{}>", hex)
+}
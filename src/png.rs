@@ -0,0 +1,148 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! PNG output of the square-notation layout of the `render` module, drawn in black and red on
+//! white. The image has the layout's size (one line of music wide, with the lines one below the
+//! other) times a scale, so that e.g. a scale of 2 gives a sharper image for high-density
+//! screens. Text is drawn from the built-in DejaVu Serif, so no fonts need to be installed. This
+//! module needs the "png-pdf" feature.
+
+use font;
+use render::{layout, RenderOptions, Shape};
+use tiny_skia::{Color, FillRule, Paint, PathBuilder, Pixmap, Stroke, Transform};
+use ttf_parser::{Face, OutlineBuilder};
+use GabcFile;
+
+///Render a `GabcFile` in square notation as a PNG image, with `scale` pixels to each unit of the
+///layout. Fails if the image is too large to draw, e.g. for a long chant at a large scale.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::render::*;
+///let f = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(g.) (::)");
+///let png = f.as_png(&RenderOptions::default(), 2.0).unwrap();
+///assert_eq!(&png[1..4], b"PNG");
+/////The width is in the header, after the signature and the chunk's length and type
+///assert_eq!(&png[16..20], &1200u32.to_be_bytes());
+///assert!(f.as_png(&RenderOptions::default(), 1e6).is_err());
+///```
+pub fn to_png(file: &GabcFile, options: &RenderOptions, scale: f64) -> Result<Vec<u8>, String> {
+    let layout = layout(file, options);
+    let height: f64 = layout.systems.iter().map(|s| s.height).sum();
+    let size = |length: f64| ((length * scale).ceil() as u32).max(1);
+    let (width, height) = (size(layout.width), size(height));
+    let mut pixmap = Pixmap::new(width, height).ok_or_else(|| format!("image too large: {}x{} pixels", width, height))?;
+    pixmap.fill(Color::WHITE);
+    let face = font::face();
+    let mut top = 0.0;
+    for system in &layout.systems {
+        let transform = Transform::from_row(scale as f32, 0.0, 0.0, scale as f32, 0.0, (top * scale) as f32);
+        for shape in &system.shapes {
+            draw(&mut pixmap, face, shape, transform);
+        }
+        top += system.height;
+    }
+    pixmap.encode_png().map_err(|e| format!("{}", e))
+}
+
+///Draw a shape onto an image.
+fn draw(pixmap: &mut Pixmap, face: &Face, shape: &Shape, transform: Transform) {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(0, 0, 0, 255);
+    paint.anti_alias = true;
+    let polyline = |points: &[(f64, f64)], close: bool| {
+        let mut path = PathBuilder::new();
+        for (i, (x, y)) in points.iter().enumerate() {
+            if i == 0 {
+                path.move_to(*x as f32, *y as f32);
+            } else {
+                path.line_to(*x as f32, *y as f32);
+            }
+        }
+        if close {
+            path.close();
+        }
+        path.finish()
+    };
+    match shape {
+        Shape::Polygon(points) => {
+            if let Some(path) = polyline(points, true) {
+                pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+            }
+        }
+        Shape::Line(points, width) => {
+            if let Some(path) = polyline(points, false) {
+                let stroke = Stroke {
+                    width: *width as f32,
+                    ..Stroke::default()
+                };
+                pixmap.stroke_path(&path, &paint, &stroke, transform, None);
+            }
+        }
+        Shape::Circle((x, y), r) => {
+            if let Some(path) = PathBuilder::from_circle(*x as f32, *y as f32, *r as f32) {
+                pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+            }
+        }
+        Shape::Text { x, y, size, text, rubric } => {
+            if *rubric {
+                paint.set_color_rgba8(0xC0, 0, 0, 255);
+            }
+            let mut outline = Outline {
+                path: PathBuilder::new(),
+                x: *x,
+                y: *y,
+                scale: size / f64::from(face.units_per_em()),
+            };
+            for c in text.chars() {
+                let glyph = font::glyph(face, c);
+                face.outline_glyph(glyph, &mut outline);
+                outline.x += font::advance(face, glyph) * size;
+            }
+            if let Some(path) = outline.path.finish() {
+                pixmap.fill_path(&path, &paint, FillRule::Winding, transform, None);
+            }
+        }
+    }
+}
+
+///Collects glyph outlines into a path, placing them at a pen position.
+struct Outline {
+    path: PathBuilder,
+    ///Pen position on the baseline
+    x: f64,
+    y: f64,
+    ///Pixels per font unit
+    scale: f64,
+}
+
+impl Outline {
+    ///A point of a glyph, whose y axis points up, on the page.
+    fn point(&self, x: f32, y: f32) -> (f32, f32) {
+        ((self.x + f64::from(x) * self.scale) as f32, (self.y - f64::from(y) * self.scale) as f32)
+    }
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        self.path.move_to(x, y);
+    }
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        self.path.line_to(x, y);
+    }
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let ((x1, y1), (x, y)) = (self.point(x1, y1), self.point(x, y));
+        self.path.quad_to(x1, y1, x, y);
+    }
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let ((x1, y1), (x2, y2), (x, y)) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.path.cubic_to(x1, y1, x2, y2, x, y);
+    }
+    fn close(&mut self) {
+        self.path.close();
+    }
+}
@@ -0,0 +1,438 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Psalm tone engine: sets pointed psalm verses to a `PsalmTone` and produces gabc. Because
+//! `GabcFile` borrows from its source text, the generator returns gabc source; parse it with
+//! `GabcFile::new()` to get a `GabcFile`.
+//!
+//! A pointed verse separates syllables with hyphens and marks accented syllables with an acute
+//! accent, the mediant with "*" and an optional flex with "†" (or "+"), e.g.
+//! "Di-xit Dó-mi-nus Dó-mi-no mé-o: * se-de a dex-tris mé-is." Unpointed Latin can be
+//! syllabified and accented automatically with `Verse::from_latin()`.
+
+use latin::Word;
+
+///A syllable of a pointed psalm verse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointedSyllable {
+    ///Text of the syllable, including any attached punctuation
+    pub text: String,
+    ///Whether this syllable carries a word accent
+    pub accented: bool,
+    ///Whether this syllable begins a word
+    pub word_start: bool,
+}
+
+///A psalm verse divided at its flex (if any) and mediant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verse {
+    ///Syllables before the flex mark (empty if the verse has no flex)
+    pub flex: Vec<PointedSyllable>,
+    ///Syllables between the flex (or the start of the verse) and the mediant
+    pub mediant: Vec<PointedSyllable>,
+    ///Syllables after the mediant
+    pub termination: Vec<PointedSyllable>,
+}
+
+impl Verse {
+    ///Read a pointed verse (see the module documentation for the pointing format).
+    ///# Examples
+    ///```
+    ///# use gabc_parser::psalm::*;
+    ///let v = Verse::from_pointed("Dó-mi-nus † Dó-mi-no mé-o: * se-de.").unwrap();
+    ///assert_eq!(v.flex.len(), 3);
+    ///assert_eq!(v.mediant.len(), 5);
+    ///assert!(v.mediant[3].accented);
+    ///assert!(v.termination[0].word_start);
+    ///```
+    pub fn from_pointed(text: &str) -> Result<Verse, String> {
+        let mut parts: Vec<Vec<PointedSyllable>> = vec![Vec::new()];
+        let mut flex_seen = false;
+        let mut mediant_seen = false;
+        for word in text.split_whitespace() {
+            match word {
+                "†" | "+" => {
+                    if flex_seen || mediant_seen {
+                        return Err("flex must come once, before the mediant".to_string());
+                    }
+                    flex_seen = true;
+                    parts.push(Vec::new());
+                }
+                "*" => {
+                    if mediant_seen {
+                        return Err("verse has more than one mediant".to_string());
+                    }
+                    mediant_seen = true;
+                    parts.push(Vec::new());
+                }
+                _ => {
+                    let current = parts.last_mut().unwrap();
+                    for (i, s) in word.split('-').filter(|s| !s.is_empty()).enumerate() {
+                        current.push(PointedSyllable {
+                            text: s.to_string(),
+                            accented: s.chars().any(is_accented_vowel),
+                            word_start: i == 0,
+                        });
+                    }
+                }
+            }
+        }
+        if !mediant_seen {
+            return Err("verse has no mediant (*)".to_string());
+        }
+        let termination = parts.pop().unwrap();
+        let mediant = parts.pop().unwrap();
+        let flex = parts.pop().unwrap_or_default();
+        if mediant.is_empty() || termination.is_empty() || (flex_seen && flex.is_empty()) {
+            return Err("empty half-verse".to_string());
+        }
+        Ok(Verse {
+            flex,
+            mediant,
+            termination,
+        })
+    }
+    ///Read an unpointed Latin verse, syllabifying and accenting it with `latin::Word`. The
+    ///mediant ("*") and flex ("†" or "+") must still be marked.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::psalm::*;
+    ///let v = Verse::from_latin("Dixit Dominus Domino meo: * sede a dextris meis.").unwrap();
+    ///assert_eq!(v, Verse::from_pointed("Dí-xit Dó-mi-nus Dó-mi-no mé-o: * sé-de a déx-tris mé-is.").unwrap());
+    ///```
+    pub fn from_latin(text: &str) -> Result<Verse, String> {
+        let pointed: Vec<String> = text
+            .split_whitespace()
+            .map(|w| match w {
+                "*" | "†" | "+" => w.to_string(),
+                _ => Word::new(w).pointed().join("-"),
+            })
+            .collect();
+        Verse::from_pointed(&pointed.join(" "))
+    }
+}
+
+///One accent of a `Cadence` and the unaccented syllables following it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accent {
+    ///Note (or neume) sung on the accented syllable
+    pub accent: String,
+    ///Note sung on each unaccented syllable after the accent except the last (e.g. the middle
+    ///syllable of a dactyl)
+    pub extra: String,
+    ///Note sung on the last syllable before the next accent or the end of the half-verse
+    pub after: String,
+}
+
+///The melodic formula ending a half-verse: preparatory syllables followed by one or more
+///accents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cadence {
+    ///Notes of the syllables sung just before the first accent, whether accented or not
+    pub preparatory: Vec<String>,
+    ///The accents of the cadence, in order
+    pub accents: Vec<Accent>,
+}
+
+impl Cadence {
+    ///Create a cadence from preparatory notes and (accent, extra, after) note triples.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::psalm::*;
+    ///let c = Cadence::new(&["h", "j"], &[("h", "g", "g")]);
+    ///assert_eq!(c.accents[0].after, "g");
+    ///```
+    pub fn new(preparatory: &[&str], accents: &[(&str, &str, &str)]) -> Cadence {
+        Cadence {
+            preparatory: preparatory.iter().map(|s| s.to_string()).collect(),
+            accents: accents
+                .iter()
+                .map(|&(accent, extra, after)| Accent {
+                    accent: accent.to_string(),
+                    extra: extra.to_string(),
+                    after: after.to_string(),
+                })
+                .collect(),
+        }
+    }
+    ///Assign notes to the end of a half-verse, returning one note per syllable (`None` where the
+    ///cadence does not reach and the tenor is sung). A cadence without accents is an error.
+    fn apply(&self, syllables: &[PointedSyllable]) -> Result<Vec<Option<String>>, String> {
+        if self.accents.is_empty() {
+            return Err("a cadence needs at least one accent".to_string());
+        }
+        let mut notes = vec![None; syllables.len()];
+        //Find the last accents of the half-verse, counting backwards
+        let mut accent_positions = Vec::new();
+        let mut end = syllables.len();
+        for _ in &self.accents {
+            match syllables[..end].iter().rposition(|s| s.accented) {
+                Some(p) => {
+                    accent_positions.insert(0, p);
+                    end = p;
+                }
+                None => {
+                    let text: Vec<&str> = syllables.iter().map(|s| s.text.as_ref()).collect();
+                    return Err(format!("too few accents for the cadence in \"{}\"", text.join(" ")));
+                }
+            }
+        }
+        for (i, accent) in self.accents.iter().enumerate() {
+            let start = accent_positions[i];
+            let next = accent_positions.get(i + 1).cloned().unwrap_or(syllables.len());
+            if next == start + 1 {
+                //No unaccented syllable follows: sing the whole formula on the accent
+                let mut neume = accent.accent.clone();
+                if i + 1 == self.accents.len() {
+                    neume.push_str(&accent.after);
+                }
+                notes[start] = Some(neume);
+                continue;
+            }
+            notes[start] = Some(accent.accent.clone());
+            for note in notes.iter_mut().take(next - 1).skip(start + 1) {
+                *note = Some(accent.extra.clone());
+            }
+            notes[next - 1] = Some(accent.after.clone());
+        }
+        let first_accent = accent_positions[0];
+        let prep_start = first_accent.saturating_sub(self.preparatory.len());
+        let prep_notes = &self.preparatory[self.preparatory.len() - (first_accent - prep_start)..];
+        for (note, prep) in notes[prep_start..first_accent].iter_mut().zip(prep_notes) {
+            *note = Some(prep.clone());
+        }
+        Ok(notes)
+    }
+}
+
+///A psalm tone: intonation, reciting note (tenor), flex, mediant and terminations
+///(differentiae).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsalmTone {
+    ///Name of the tone, e.g. "8"; used for the "mode" header of generated files
+    pub name: String,
+    ///Clef the notes of this tone are written in, e.g. "c4"
+    pub clef: String,
+    ///Notes of the first syllables of an intoned verse
+    pub intonation: Vec<String>,
+    ///Reciting note
+    pub tenor: String,
+    ///Note the voice drops to after the last accent before a flex
+    pub flex: String,
+    ///Cadence ending the first half of each verse
+    pub mediant: Cadence,
+    ///Named cadences ending the second half of each verse, e.g. ("G", ...) for tone 8G
+    pub terminations: Vec<(String, Cadence)>,
+}
+
+impl PsalmTone {
+    ///Look up a termination by name.
+    pub fn termination(&self, name: &str) -> Option<&Cadence> {
+        self.terminations.iter().find(|t| t.0 == name).map(|t| &t.1)
+    }
+    ///Set one verse to this tone, returning its gabc syllables (without clef or header), ending
+    ///with a "(::)" barline.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::psalm::*;
+    ///let tone = tone(8).unwrap();
+    ///let v = Verse::from_pointed("Lau-dá-te Dó-mi-num óm-nes gén-tes: * lau-dá-te e-um óm-nes pó-pu-li.").unwrap();
+    ///assert_eq!(tone.verse_gabc(&v, "G", true).unwrap(),
+    ///    "Lau(g)dá(h)te(j) Dó(j)mi(j)num(j) óm(j)nes(j) gén(k)tes:(j) *(:) \
+    ///     lau(j)dá(j)te(j) e(j)um(j) óm(h)nes(j) pó(h)pu(g)li.(g) (::)");
+    ///```
+    pub fn verse_gabc(&self, verse: &Verse, termination: &str, intone: bool) -> Result<String, String> {
+        let ending = match self.termination(termination) {
+            Some(c) => c,
+            None => return Err(format!("tone {} has no termination {}", self.name, termination)),
+        };
+        let mut result = String::new();
+        //First half: intonation, then the flex (if any) and the mediant cadence
+        let mut first_notes = Vec::new();
+        if !verse.flex.is_empty() {
+            first_notes.extend(self.flex_notes(&verse.flex));
+        }
+        first_notes.extend(self.mediant.apply(&verse.mediant)?);
+        let first: Vec<&PointedSyllable> = verse.flex.iter().chain(&verse.mediant).collect();
+        if intone {
+            for (note, int) in first_notes.iter_mut().zip(&self.intonation) {
+                if note.is_none() {
+                    *note = Some(int.clone());
+                }
+            }
+        }
+        for (i, (s, note)) in first.iter().zip(&first_notes).enumerate() {
+            self.push_syllable(&mut result, s, note, i == 0);
+            if !verse.flex.is_empty() && i + 1 == verse.flex.len() {
+                result.push_str(" †(,)");
+            }
+        }
+        result.push_str(" *(:)");
+        let second_notes = ending.apply(&verse.termination)?;
+        for (s, note) in verse.termination.iter().zip(&second_notes) {
+            self.push_syllable(&mut result, s, note, false);
+        }
+        result.push_str(" (::)");
+        Ok(result)
+    }
+    ///Set a whole psalm to this tone, returning a complete gabc file. The first verse is always
+    ///intoned; later verses only if `intone_every_verse` is set (as for the Gospel canticles).
+    ///# Examples
+    ///```
+    ///# use gabc_parser::*;
+    ///# use gabc_parser::psalm::*;
+    ///let tone = tone(8).unwrap();
+    ///let verses = [Verse::from_pointed("Lau-dá-te Dó-mi-num óm-nes gén-tes: * lau-dá-te e-um óm-nes pó-pu-li.").unwrap(),
+    ///    Verse::from_latin("Quoniam confirmata est † super nos misericordia eius: * et veritas Domini manet in aeternum.").unwrap()];
+    ///let gabc = tone.psalm_gabc(&verses, "G", false).unwrap();
+    ///let f = GabcFile::new(&gabc);
+    ///assert_eq!(f.attributes[0], ("mode", "8"));
+    ///```
+    pub fn psalm_gabc(&self, verses: &[Verse], termination: &str, intone_every_verse: bool) -> Result<String, String> {
+        let mut result = format!("mode:{};\nannotation:{} {};\n%%\n({})", self.name, self.name, termination, self.clef);
+        for (i, verse) in verses.iter().enumerate() {
+            result.push(if i == 0 { ' ' } else { '\n' });
+            result.push_str(&self.verse_gabc(verse, termination, i == 0 || intone_every_verse)?);
+        }
+        result.push('\n');
+        Ok(result)
+    }
+    ///Notes for the part of a verse before the flex: tenor up to the last accent, then the flex
+    ///note on the following syllables (or on the accent itself if it is the last syllable).
+    fn flex_notes(&self, syllables: &[PointedSyllable]) -> Vec<Option<String>> {
+        let mut notes = vec![None; syllables.len()];
+        let last = syllables.len() - 1;
+        match syllables.iter().rposition(|s| s.accented) {
+            Some(p) if p < last => {
+                for note in notes.iter_mut().skip(p + 1) {
+                    *note = Some(self.flex.clone());
+                }
+            }
+            _ => notes[last] = Some(self.flex.clone()),
+        }
+        notes
+    }
+    ///Append one gabc syllable, defaulting to the tenor.
+    fn push_syllable(&self, result: &mut String, s: &PointedSyllable, note: &Option<String>, first: bool) {
+        if s.word_start && !first {
+            result.push(' ');
+        }
+        result.push_str(&s.text);
+        result.push('(');
+        result.push_str(note.as_ref().unwrap_or(&self.tenor));
+        result.push(')');
+    }
+}
+
+///Get one of the built-in psalm tones (1-8), a compact rendering of the common Solesmes tones
+///written in the c4 clef with their usual terminations. Check them against your own books;
+///tones can also be built by hand as `PsalmTone` values.
+///# Examples
+///```
+///# use gabc_parser::psalm::*;
+///let t = tone(1).unwrap();
+///assert_eq!(t.tenor, "h");
+///assert!(t.termination("D").is_some());
+///assert!(tone(9).is_none());
+///```
+pub fn tone(number: u8) -> Option<PsalmTone> {
+    let tone = match number {
+        1 => built_in(
+            &["f", "gh"],
+            "h",
+            "g",
+            Cadence::new(&["g", "h"], &[("i", "h", "h")]),
+            vec![
+                ("D", Cadence::new(&["g", "f"], &[("g", "f", "ed")])),
+                ("f", Cadence::new(&["g"], &[("h", "g", "f")])),
+                ("g", Cadence::new(&["g"], &[("h", "g", "g")])),
+            ],
+        ),
+        2 => built_in(
+            &["c", "d"],
+            "f",
+            "d",
+            Cadence::new(&[], &[("g", "f", "f")]),
+            vec![("D", Cadence::new(&["e"], &[("d", "c", "d")]))],
+        ),
+        3 => built_in(
+            &["g", "hj"],
+            "j",
+            "h",
+            Cadence::new(&[], &[("k", "j", "j")]),
+            vec![
+                ("a", Cadence::new(&["j"], &[("i", "h", "h")])),
+                ("b", Cadence::new(&[], &[("k", "j", "i")])),
+                ("g", Cadence::new(&[], &[("j", "i", "g")])),
+            ],
+        ),
+        4 => built_in(
+            &["h", "gh"],
+            "h",
+            "g",
+            Cadence::new(&["g", "h"], &[("i", "h", "h")]),
+            vec![
+                ("E", Cadence::new(&["g"], &[("h", "g", "e")])),
+                ("A", Cadence::new(&["g"], &[("h", "g", "h")])),
+            ],
+        ),
+        5 => built_in(
+            &["f", "h"],
+            "j",
+            "h",
+            Cadence::new(&[], &[("k", "j", "j")]),
+            vec![("a", Cadence::new(&["k"], &[("i", "j", "h")]))],
+        ),
+        6 => built_in(
+            &["f", "gh"],
+            "h",
+            "g",
+            Cadence::new(&["g"], &[("h", "h", "h")]),
+            vec![("F", Cadence::new(&["f", "g"], &[("h", "g", "f")]))],
+        ),
+        7 => built_in(
+            &["hg", "hi"],
+            "k",
+            "j",
+            Cadence::new(&[], &[("l", "k", "k")]),
+            vec![
+                ("a", Cadence::new(&["l"], &[("k", "j", "h")])),
+                ("c", Cadence::new(&["l"], &[("k", "j", "j")])),
+            ],
+        ),
+        8 => built_in(
+            &["g", "h"],
+            "j",
+            "h",
+            Cadence::new(&[], &[("k", "j", "j")]),
+            vec![
+                ("G", Cadence::new(&["h", "j"], &[("h", "g", "g")])),
+                ("c", Cadence::new(&[], &[("k", "j", "j")])),
+            ],
+        ),
+        _ => return None,
+    };
+    Some(PsalmTone {
+        name: number.to_string(),
+        ..tone
+    })
+}
+
+///Build a c4 tone from string slices (for the built-in tones).
+fn built_in(intonation: &[&str], tenor: &str, flex: &str, mediant: Cadence, terminations: Vec<(&str, Cadence)>) -> PsalmTone {
+    PsalmTone {
+        name: String::new(),
+        clef: "c4".to_string(),
+        intonation: intonation.iter().map(|s| s.to_string()).collect(),
+        tenor: tenor.to_string(),
+        flex: flex.to_string(),
+        mediant,
+        terminations: terminations.into_iter().map(|(n, c)| (n.to_string(), c)).collect(),
+    }
+}
+
+///Whether a character is a vowel marked with an acute accent.
+fn is_accented_vowel(c: char) -> bool {
+    matches!(c, 'á' | 'é' | 'í' | 'ó' | 'ú' | 'ý' | 'ǽ' | 'Á' | 'É' | 'Í' | 'Ó' | 'Ú' | 'Ý' | 'Ǽ')
+}
@@ -0,0 +1,614 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Layout of a `GabcFile` in square notation, as drawn by the `svg`, `png` and `pdf` modules. Syllables are set
+//! from left to right on four-line staves of a fixed width, with each syllable's text centred
+//! under its first note. A new line (system) is started between syllables when the next one does
+//! not fit: the old system ends with a custos showing the next note, and the new one starts with
+//! the clef in force.
+//!
+//! Notes joined in gabc are drawn as one neume: a rising pair not followed by a lower note is a
+//! podatus (one note above the other), high-low-high at the start of a neume a porrectus, and a
+//! neume starting with a falling pair gets the stem of a clivis. Virgae, puncta inclinata,
+//! strophae, oriscus, quilismata and liquescents have their own shapes. Horizontal and vertical
+//! episemata, morae, flats, naturals, sharps and the divisions are drawn as written. Text is
+//! set in DejaVu Serif, with rubrics (e.g. "*") in red. The font is built into the library with
+//! the "png-pdf" feature; without it, the widths of text are estimated.
+
+use font;
+use {text_tokens, GabcFile, Note, NoteElem, Syllabic};
+
+///Options for laying out square notation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    ///Distance between two lines of the staff, in pixels; everything else is scaled from it
+    pub staff_size: f64,
+    ///Width of each line of music, in pixels
+    pub line_width: f64,
+}
+
+impl Default for RenderOptions {
+    ///Staff lines 10 pixels apart, on lines 600 pixels wide.
+    fn default() -> RenderOptions {
+        RenderOptions {
+            staff_size: 10.0,
+            line_width: 600.0,
+        }
+    }
+}
+
+///A shape of a laid-out system, in pixels from the top left corner of the system.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Shape {
+    ///A filled polygon
+    Polygon(Vec<(f64, f64)>),
+    ///A line through some points, of a width
+    Line(Vec<(f64, f64)>, f64),
+    ///A filled circle around a point, of a radius
+    Circle((f64, f64), f64),
+    ///Text starting from a point on its baseline, red if it is a rubric
+    Text {
+        x: f64,
+        y: f64,
+        size: f64,
+        text: String,
+        rubric: bool,
+    },
+}
+
+impl Shape {
+    ///The same shape moved right by `dx` pixels.
+    fn shifted(self, dx: f64) -> Shape {
+        let shift = |points: Vec<(f64, f64)>| points.into_iter().map(|(x, y)| (x + dx, y)).collect();
+        match self {
+            Shape::Polygon(points) => Shape::Polygon(shift(points)),
+            Shape::Line(points, width) => Shape::Line(shift(points), width),
+            Shape::Circle((x, y), r) => Shape::Circle((x + dx, y), r),
+            Shape::Text { x, y, size, text, rubric } => Shape::Text {
+                x: x + dx,
+                y,
+                size,
+                text,
+                rubric,
+            },
+        }
+    }
+}
+
+///One line of music.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct System {
+    pub height: f64,
+    pub shapes: Vec<Shape>,
+}
+
+///A `GabcFile` laid out as systems of the same width, from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Layout {
+    pub width: f64,
+    pub systems: Vec<System>,
+}
+
+///Height of the top staff line below the top of a system, in staff spaces.
+const TOP: f64 = 2.5;
+
+///Lay out a `GabcFile` in square notation.
+pub(crate) fn layout(file: &GabcFile, options: &RenderOptions) -> Layout {
+    let s = options.staff_size;
+    let mut setter = Setter {
+        s,
+        width: options.line_width,
+        text_size: 1.6 * s,
+        systems: Vec::new(),
+        shapes: Vec::new(),
+        x: 0.0,
+        text_end: 0.0,
+        clef: None,
+        hyphen: None,
+    };
+    setter.start_system(false);
+    let lyrics = file.syllable_lyrics();
+    let mut word_end = false;
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let (leading, tokens, trailing) = text_tokens(syllable.text);
+        let rubric = !tokens.is_empty() && tokens.iter().all(|t| t.rubric);
+        let text = font::printable(&tokens.into_iter().map(|t| t.text).collect::<Vec<_>>().join(" "));
+        let new_word = leading || word_end || i == 0;
+        word_end = trailing;
+        let music = setter.music(&syllable.music);
+        if music.width == 0.0 && text.is_empty() {
+            setter.update_clef(&syllable.music);
+            continue;
+        }
+        let text_width = setter.text_width(&text);
+        let anchor = music.anchor.unwrap_or(music.width / 2.0);
+        let place = |setter: &Setter| {
+            let space = if new_word && setter.text_end > 0.0 { setter.text_size * 0.4 } else { 0.0 };
+            let start = if text.is_empty() {
+                setter.x
+            } else {
+                setter.x.max(setter.text_end + space + text_width / 2.0 - anchor)
+            };
+            (start, (start + music.width).max(start + anchor + text_width / 2.0))
+        };
+        let (mut start, end) = place(&setter);
+        let margin = 1.2 * s;
+        if end > setter.width - margin && setter.x > setter.start_x() {
+            let custos = next_note(file, i);
+            setter.end_system(custos);
+            let starts_with_clef = matches!(syllable.music.first(), Some(NoteElem::Clef(_)));
+            setter.start_system(!starts_with_clef);
+            start = place(&setter).0;
+        }
+        setter.shapes.extend(music.shapes.into_iter().map(|shape| shape.shifted(start)));
+        if music.width > 0.0 {
+            setter.x = start + music.width + 0.6 * s;
+        }
+        if !text.is_empty() {
+            let text_start = start + anchor - text_width / 2.0;
+            let continues = matches!(lyrics[i], Some((_, Syllabic::Begin)) | Some((_, Syllabic::Middle)));
+            if let (Some(from), Some(_)) = (setter.hyphen.take(), &lyrics[i]) {
+                setter.hyphen_between(from, text_start);
+            }
+            setter.text(text_start, text, rubric);
+            setter.text_end = text_start + text_width;
+            if continues {
+                setter.hyphen = Some(setter.text_end);
+            }
+        }
+        setter.update_clef(&syllable.music);
+    }
+    setter.end_system(None);
+    Layout {
+        width: options.line_width,
+        systems: setter.systems,
+    }
+}
+
+///The staff position (0 for "a" to 12 for "m") of the first sung note from a syllable on.
+fn next_note(file: &GabcFile, from: usize) -> Option<usize> {
+    file.syllables[from..]
+        .iter()
+        .flat_map(|s| s.music.iter())
+        .find_map(|e| match e {
+            NoteElem::Note(n) if !is_sign(n) => Some(position(n)),
+            _ => None,
+        })
+}
+
+///Whether a note is a flat, natural or sharp sign rather than a sung note.
+fn is_sign(note: &Note) -> bool {
+    note.suffix.contains(['x', 'y', '#'])
+}
+
+///The staff position of a note, from 0 for "a" to 12 for "m".
+fn position(note: &Note) -> usize {
+    (note.position.to_ascii_lowercase() as u8 - b'a') as usize
+}
+
+///The music of one syllable, with its shapes starting at x = 0.
+struct Music {
+    shapes: Vec<Shape>,
+    width: f64,
+    ///Centre of the first sung note, under which the text is centred
+    anchor: Option<f64>,
+}
+
+///State of the layout.
+struct Setter<'a> {
+    ///Staff size: the distance between staff lines
+    s: f64,
+    width: f64,
+    text_size: f64,
+    systems: Vec<System>,
+    ///Shapes of the system being set
+    shapes: Vec<Shape>,
+    ///Where the next music may start
+    x: f64,
+    ///Where the last text ended
+    text_end: f64,
+    ///Clef in force
+    clef: Option<&'a str>,
+    ///End of the text of the last syllable, if its word continues in the next one
+    hyphen: Option<f64>,
+}
+
+impl<'a> Setter<'a> {
+    ///The vertical centre of a staff position (0 for "a", 3 for the lowest line).
+    fn y(&self, position: f64) -> f64 {
+        (TOP + (9.0 - position) / 2.0) * self.s
+    }
+    ///Where music starts on a new system.
+    fn start_x(&self) -> f64 {
+        0.3 * self.s
+    }
+    ///Width of text under the staff.
+    fn text_width(&self, text: &str) -> f64 {
+        font::text_width(text, self.text_size)
+    }
+    ///Start a new system with its staff lines, and the clef in force if `clef` is set.
+    fn start_system(&mut self, clef: bool) {
+        self.shapes = (0..4)
+            .map(|line| {
+                let y = self.y(3.0 + 2.0 * line as f64);
+                Shape::Line(vec![(0.0, y), (self.width, y)], 0.08 * self.s)
+            })
+            .collect();
+        self.x = self.start_x();
+        self.text_end = 0.0;
+        if let (true, Some(c)) = (clef, self.clef) {
+            let mut shapes = Vec::new();
+            let width = self.clef_shapes(&mut shapes, 0.0, c);
+            let x = self.x;
+            self.shapes.extend(shapes.into_iter().map(|shape| shape.shifted(x)));
+            self.x += width + 0.6 * self.s;
+        }
+    }
+    ///Finish the system being set, ending it with a custos for the note at a staff position.
+    fn end_system(&mut self, custos: Option<usize>) {
+        let s = self.s;
+        if let Some(p) = custos {
+            let (x, y) = (self.width - 0.9 * s, self.y(p as f64));
+            self.shapes.push(rect(x, y - 0.3 * s, 0.5 * s, 0.6 * s));
+            let tail = if p < 7 { -1.5 * s } else { 1.5 * s };
+            self.shapes.push(Shape::Line(vec![(x + 0.45 * s, y), (x + 0.45 * s, y + tail)], 0.1 * s));
+            let mut ledger = Vec::new();
+            self.ledger_lines(&mut ledger, x - 0.15 * s, 0.8 * s, p);
+            self.shapes.extend(ledger);
+        }
+        if let Some(from) = self.hyphen.take() {
+            let hyphen = self.text_width("-");
+            self.text(from.min(self.width - hyphen), "-".to_string(), false);
+        }
+        let height = (TOP + 5.0) * s + self.text_size * 1.2 + s;
+        self.systems.push(System {
+            height,
+            shapes: std::mem::take(&mut self.shapes),
+        });
+    }
+    ///Set the clef in force after a syllable.
+    fn update_clef(&mut self, music: &[NoteElem<'a>]) {
+        for elem in music {
+            if let NoteElem::Clef(c) = elem {
+                self.clef = Some(*c);
+            }
+        }
+    }
+    ///Add text under the staff.
+    fn text(&mut self, x: f64, text: String, rubric: bool) {
+        let y = (TOP + 5.0) * self.s + self.text_size * 0.9;
+        self.shapes.push(Shape::Text {
+            x,
+            y,
+            size: self.text_size,
+            text,
+            rubric,
+        });
+    }
+    ///Add a hyphen between two syllables of a word if there is room for one.
+    fn hyphen_between(&mut self, from: f64, to: f64) {
+        let hyphen = self.text_width("-");
+        if to - from > 1.5 * hyphen {
+            self.text((from + to - hyphen) / 2.0, "-".to_string(), false);
+        }
+    }
+    ///Lay out the music of a syllable.
+    fn music(&self, music: &[NoteElem]) -> Music {
+        let s = self.s;
+        let mut result = Music {
+            shapes: Vec::new(),
+            width: 0.0,
+            anchor: None,
+        };
+        let mut x = 0.0;
+        let mut neume: Vec<&Note> = Vec::new();
+        for elem in music {
+            let gap = match elem {
+                NoteElem::Note(n) if is_sign(n) => {
+                    x = self.neume(&mut result, x, &neume);
+                    neume.clear();
+                    x = self.sign(&mut result.shapes, x, n);
+                    0.3 * s
+                }
+                NoteElem::Note(n) => {
+                    neume.push(n);
+                    continue;
+                }
+                NoteElem::Spacer(spacer) => {
+                    x = self.neume(&mut result, x, &neume);
+                    neume.clear();
+                    match *spacer {
+                        "!" => 0.15 * s,
+                        "@" => 0.0,
+                        "/" => 0.5 * s,
+                        "//" => 0.9 * s,
+                        "/0" => 0.25 * s,
+                        " " => 1.2 * s,
+                        //"/[n]": n half spaces
+                        other => other[2..other.len() - 1].parse::<f64>().unwrap_or(1.0).max(0.0) * 0.5 * s,
+                    }
+                }
+                NoteElem::Clef(c) => {
+                    x = self.neume(&mut result, x, &neume);
+                    neume.clear();
+                    x = self.clef_shapes(&mut result.shapes, x, c);
+                    0.6 * s
+                }
+                NoteElem::Barline(bar) => {
+                    x = self.neume(&mut result, x, &neume);
+                    neume.clear();
+                    x = self.barline(&mut result.shapes, x + 0.5 * s, bar);
+                    0.5 * s
+                }
+            };
+            result.width = x;
+            x += gap;
+        }
+        result.width = self.neume(&mut result, x, &neume).max(result.width);
+        result
+    }
+    ///Draw the notes of a neume from `x`, returning where it ends.
+    fn neume(&self, music: &mut Music, x: f64, notes: &[&Note]) -> f64 {
+        if notes.is_empty() {
+            return x;
+        }
+        let (s, w) = (self.s, 1.1 * self.s);
+        let stem = 0.12 * s;
+        let shapes = &mut music.shapes;
+        let positions: Vec<usize> = notes.iter().map(|n| position(n)).collect();
+        let plain = |n: &Note| !n.suffix.contains(['v', 'V', 's', 'o', 'w', '~', '<', '>']) && !inclined(n);
+        let mut cursor = x;
+        //Left edge, vertical centre and whether it was drawn above the note before it
+        let mut previous: Option<(f64, f64, bool)> = None;
+        let mut i = 0;
+        if notes.len() >= 3
+            && notes[..3].iter().all(|n| plain(n))
+            && positions[0] > positions[1]
+            && positions[2] > positions[1]
+        {
+            //Porrectus: an oblique stroke for the first two notes
+            let (y0, y1) = (self.y(positions[0] as f64), self.y(positions[1] as f64));
+            let h = 0.9 * s;
+            shapes.push(Shape::Polygon(vec![
+                (x, y0 - h / 2.0),
+                (x + 2.0 * w, y1 - h / 2.0),
+                (x + 2.0 * w, y1 + h / 2.0),
+                (x, y0 + h / 2.0),
+            ]));
+            shapes.push(Shape::Line(vec![(x + stem / 2.0, y0 - h / 2.0), (x + stem / 2.0, y0 + 1.2 * s)], stem));
+            self.ledger_lines(shapes, x, w, positions[0]);
+            self.ledger_lines(shapes, x + w, w, positions[1]);
+            music.anchor.get_or_insert(x + w / 2.0);
+            previous = Some((x + w, y1, false));
+            cursor = x + 2.0 * w;
+            i = 2;
+        }
+        while i < notes.len() {
+            let note = notes[i];
+            let p = positions[i];
+            let y = self.y(p as f64);
+            let stacked = match previous {
+                Some((_, _, false)) => {
+                    p > positions[i - 1]
+                        && !inclined(note)
+                        && (plain(notes[i - 1]) || notes[i - 1].suffix.contains('w'))
+                        && positions.get(i + 1).is_none_or(|&next| next > p)
+                }
+                _ => false,
+            };
+            let left = match previous {
+                Some((left, previous_y, _)) if stacked => {
+                    shapes.push(Shape::Line(vec![(left + w - stem / 2.0, previous_y), (left + w - stem / 2.0, y)], stem));
+                    left
+                }
+                Some((_, previous_y, _)) => {
+                    //Repeated notes are drawn a little apart
+                    if p == positions[i - 1] {
+                        cursor += 0.25 * s;
+                    }
+                    if p.abs_diff(positions[i - 1]) >= 2 && !inclined(note) && !inclined(notes[i - 1]) {
+                        shapes.push(Shape::Line(vec![(cursor, previous_y), (cursor, y)], stem));
+                    }
+                    cursor
+                }
+                None => {
+                    //Clivis: a stem on the left of a first note followed by a lower one
+                    if positions.get(1).is_some_and(|&next| next < p) && plain(note) {
+                        shapes.push(Shape::Line(vec![(x + stem / 2.0, y - 0.45 * s), (x + stem / 2.0, y + 1.2 * s)], stem));
+                    }
+                    cursor
+                }
+            };
+            music.anchor.get_or_insert(left + w / 2.0);
+            let width = self.note(shapes, left, note);
+            if !stacked {
+                cursor = left + width;
+            }
+            previous = Some((left, y, stacked));
+            cursor = self.rhythmic_signs(shapes, left, cursor, note);
+            i += 1;
+        }
+        cursor
+    }
+    ///Draw the head of a note with its left edge at `x`, returning its width.
+    fn note(&self, shapes: &mut Vec<Shape>, x: f64, note: &Note) -> f64 {
+        let (s, w, h) = (self.s, 1.1 * self.s, 0.9 * self.s);
+        let p = position(note);
+        let y = self.y(p as f64);
+        let suffix = note.suffix;
+        let stem = 0.12 * s;
+        //Bivirgae and distrophae are written on one position but drawn as two or three notes
+        let repeats = suffix.matches('v').count().max(suffix.matches('s').count()).max(1);
+        let mut left = x;
+        for _ in 0..repeats {
+            if inclined(note) {
+                shapes.push(Shape::Polygon(vec![
+                    (left + w / 2.0, y - 0.6 * h),
+                    (left + w, y),
+                    (left + w / 2.0, y + 0.6 * h),
+                    (left, y),
+                ]));
+            } else if suffix.contains('w') {
+                let mut points = vec![(left, y + h / 2.0)];
+                for k in 0..=6 {
+                    let dy = if k % 2 == 0 { -h / 4.0 } else { -h / 2.0 };
+                    points.push((left + w * k as f64 / 6.0, y + dy));
+                }
+                points.push((left + w, y + h / 2.0));
+                shapes.push(Shape::Polygon(points));
+            } else if suffix.contains('o') {
+                shapes.push(Shape::Polygon(vec![
+                    (left, y - 0.1 * h),
+                    (left + 0.3 * w, y - h / 2.0),
+                    (left + w, y - 0.2 * h),
+                    (left + w, y + 0.1 * h),
+                    (left + 0.7 * w, y + h / 2.0),
+                    (left, y + 0.2 * h),
+                ]));
+            } else if suffix.contains('s') {
+                shapes.push(rect(left + 0.15 * w, y - 0.35 * h, 0.7 * w, 0.7 * h));
+            } else if suffix.contains('~') {
+                shapes.push(rect(left + 0.1 * w, y - h / 4.0, 0.8 * w, h / 2.0));
+            } else {
+                shapes.push(rect(left, y - h / 2.0, w, h));
+            }
+            if suffix.contains('v') {
+                shapes.push(Shape::Line(vec![(left + w - stem / 2.0, y), (left + w - stem / 2.0, y + 1.6 * s)], stem));
+            } else if suffix.contains('V') {
+                shapes.push(Shape::Line(vec![(left + stem / 2.0, y), (left + stem / 2.0, y + 1.6 * s)], stem));
+            }
+            if suffix.contains('<') {
+                shapes.push(Shape::Line(vec![(left + w - stem / 2.0, y), (left + w - stem / 2.0, y - 0.9 * s)], stem));
+            } else if suffix.contains('>') {
+                shapes.push(Shape::Line(vec![(left + w - stem / 2.0, y), (left + w - stem / 2.0, y + 0.9 * s)], stem));
+            }
+            self.ledger_lines(shapes, left, w, p);
+            left += w + 0.25 * s;
+        }
+        left - x - 0.25 * s
+    }
+    ///Draw the episemata and morae of a note drawn at `x`, with the neume so far ending at
+    ///`end`; returns the new end of the neume.
+    fn rhythmic_signs(&self, shapes: &mut Vec<Shape>, x: f64, end: f64, note: &Note) -> f64 {
+        let (s, w) = (self.s, 1.1 * self.s);
+        let p = position(note);
+        let y = self.y(p as f64);
+        if note.suffix.contains('_') {
+            shapes.push(rect(x, y - 0.9 * s, w, 0.15 * s));
+        }
+        if note.suffix.contains('\'') {
+            shapes.push(Shape::Line(vec![(x + w / 2.0, y + 0.65 * s), (x + w / 2.0, y + 1.2 * s)], 0.12 * s));
+        }
+        let dots = if note.suffix.contains("..") { 2 } else { note.suffix.matches('.').count() };
+        //Dots on a line go in the space above it
+        let dot_y = if p % 2 == 1 { self.y(p as f64 + 1.0) } else { y };
+        let mut end = end;
+        for _ in 0..dots {
+            end += 0.45 * s;
+            shapes.push(Shape::Circle((end, dot_y), 0.18 * s));
+            end += 0.2 * s;
+        }
+        end
+    }
+    ///Draw ledger lines for a note of a width at a staff position outside the staff.
+    fn ledger_lines(&self, shapes: &mut Vec<Shape>, x: f64, width: f64, position: usize) {
+        let ledger = match position {
+            0 | 1 => 1.0,
+            11 | 12 => 11.0,
+            _ => return,
+        };
+        let y = self.y(ledger);
+        shapes.push(Shape::Line(vec![(x - 0.25 * self.s, y), (x + width + 0.25 * self.s, y)], 0.08 * self.s));
+    }
+    ///Draw a flat, natural or sharp sign at `x`, returning where it ends.
+    fn sign(&self, shapes: &mut Vec<Shape>, x: f64, note: &Note) -> f64 {
+        let s = self.s;
+        let y = self.y(position(note) as f64);
+        let line = 0.12 * s;
+        if note.suffix.contains('x') {
+            shapes.push(Shape::Line(vec![(x + 0.1 * s, y - 1.3 * s), (x + 0.1 * s, y + 0.5 * s)], line));
+            shapes.push(Shape::Line(
+                vec![(x + 0.1 * s, y - 0.05 * s), (x + 0.45 * s, y - 0.3 * s), (x + 0.6 * s, y - 0.05 * s), (x + 0.1 * s, y + 0.5 * s)],
+                line,
+            ));
+            x + 0.7 * s
+        } else if note.suffix.contains('y') {
+            shapes.push(Shape::Line(vec![(x + 0.1 * s, y - 1.1 * s), (x + 0.1 * s, y + 0.35 * s), (x + 0.55 * s, y + 0.2 * s)], line));
+            shapes.push(Shape::Line(vec![(x + 0.55 * s, y + 1.1 * s), (x + 0.55 * s, y - 0.35 * s), (x + 0.1 * s, y - 0.2 * s)], line));
+            x + 0.65 * s
+        } else {
+            for dx in [0.2, 0.5].iter() {
+                shapes.push(Shape::Line(vec![(x + dx * s, y - s), (x + dx * s, y + s)], line));
+            }
+            for dy in [-0.35, 0.35].iter() {
+                shapes.push(Shape::Line(vec![(x, y + (dy + 0.1) * s), (x + 0.7 * s, y + (dy - 0.1) * s)], 0.2 * s));
+            }
+            x + 0.7 * s
+        }
+    }
+    ///Draw a clef at `x`, returning where it ends.
+    fn clef_shapes(&self, shapes: &mut Vec<Shape>, x: f64, clef: &str) -> f64 {
+        let s = self.s;
+        let line = clef[1..].parse::<f64>().unwrap_or(3.0);
+        let y = self.y(1.0 + 2.0 * line);
+        shapes.push(rect(x, y - 1.05 * s, 0.18 * s, 2.1 * s));
+        shapes.push(rect(x + 0.3 * s, y - 1.05 * s, 0.8 * s, 0.9 * s));
+        shapes.push(rect(x + 0.3 * s, y + 0.15 * s, 0.8 * s, 0.9 * s));
+        if clef.starts_with('f') {
+            shapes.push(rect(x + 1.35 * s, y - 0.6 * s, 0.5 * s, 0.5 * s));
+            shapes.push(rect(x + 1.35 * s, y + 0.1 * s, 0.5 * s, 0.5 * s));
+            x + 1.85 * s
+        } else {
+            x + 1.1 * s
+        }
+    }
+    ///Draw a division at `x`, returning where it ends.
+    fn barline(&self, shapes: &mut Vec<Shape>, x: f64, bar: &str) -> f64 {
+        let s = self.s;
+        let line = 0.12 * s;
+        let vertical = |from: f64, to: f64, x: f64, width: f64| {
+            Shape::Line(vec![(x + width / 2.0, self.y(from)), (x + width / 2.0, self.y(to))], width)
+        };
+        match bar {
+            "::" => {
+                shapes.push(vertical(9.0, 3.0, x, line));
+                shapes.push(vertical(9.0, 3.0, x + 0.35 * s, 0.22 * s));
+                x + 0.57 * s
+            }
+            b if b.starts_with(':') => {
+                shapes.push(vertical(9.0, 3.0, x, line));
+                x + line
+            }
+            b if b.starts_with(';') => {
+                shapes.push(vertical(8.0, 4.0, x, line));
+                x + line
+            }
+            "'" | "`" => {
+                //Virgula: a tick above the top line
+                shapes.push(Shape::Line(vec![(x + 0.1 * s, self.y(11.0)), (x + 0.25 * s, self.y(9.6))], 0.15 * s));
+                x + 0.3 * s
+            }
+            _ => {
+                shapes.push(vertical(10.0, 8.0, x, line));
+                x + line
+            }
+        }
+    }
+}
+
+///Whether a note is a punctum inclinatum (written in upper case).
+fn inclined(note: &Note) -> bool {
+    note.position.is_ascii_uppercase()
+}
+
+///A filled rectangle.
+fn rect(x: f64, y: f64, width: f64, height: f64) -> Shape {
+    Shape::Polygon(vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)])
+}
+
+///A coordinate written with at most two decimals, for SVG and PDF.
+pub(crate) fn number(n: f64) -> String {
+    let s = format!("{:.2}", n);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
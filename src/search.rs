@@ -0,0 +1,287 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Transposition-invariant search for melodic formulas in a corpus of `GabcFile`s. A
+//! `MelodicQuery` stores a melody as diatonic steps relative to its first note, so a formula
+//! matches wherever the same succession of intervals appears, whatever the clef.
+
+use pest::Parser;
+use std::ops::Range;
+use volpiano::VOLPIANO_PITCHES;
+use {GABCParser, GabcFile, NoteElem, Rule};
+
+///A melodic formula to search for, independent of clef and transposition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MelodicQuery {
+    notes: Vec<QueryNote>,
+}
+
+///One note of a `MelodicQuery`.
+#[derive(Debug, Clone, PartialEq)]
+struct QueryNote {
+    ///Diatonic steps above (or below) the first note of the query
+    step: i32,
+    ///Rhythmic signs the matching note must carry, if the query specifies them
+    rhythm: Option<String>,
+}
+
+impl MelodicQuery {
+    ///Create a query from a gabc music string, e.g. "gh/j". Clefs, spacers and barlines are
+    ///ignored; rhythmic signs (dots, episemata and ictus) are kept and must match unless
+    ///`SearchOptions::ignore_rhythmic_signs` is set.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::search::*;
+    ///let q = MelodicQuery::from_gabc("gh/j.").unwrap();
+    ///assert_eq!(q.len(), 3);
+    ///assert!(MelodicQuery::from_gabc("(not music").is_err());
+    ///assert!(MelodicQuery::from_gabc("gh) extra(j").is_err());
+    ///```
+    pub fn from_gabc(music: &str) -> Result<MelodicQuery, String> {
+        let syllable = format!("({})", music);
+        let pairs = GABCParser::parse(Rule::syllable, &syllable).map_err(|e| format!("{}", e))?;
+        //The syllable rule stops at its closing parenthesis, so check that nothing follows it
+        let end = pairs.clone().next().map_or(0, |p| p.into_span().end());
+        if end != syllable.len() {
+            return Err(format!("unexpected input after the music: {}", &syllable[end..]));
+        }
+        let mut positions = Vec::new();
+        for pair in pairs.flatten().filter(|p| p.as_rule() == Rule::note) {
+            let mut position = 0;
+            let mut rhythm = String::new();
+            for p in pair.into_inner() {
+                match p.as_rule() {
+                    Rule::position => position = staff_index(p.as_str().chars().next().unwrap()),
+                    Rule::suffix => rhythm.push_str(&rhythmic_signs(p.as_str())),
+                    _ => {}
+                }
+            }
+            positions.push((position, Some(rhythm)));
+        }
+        MelodicQuery::from_steps(positions)
+    }
+    ///Create a query from a sequence of diatonic intervals between successive notes, e.g.
+    ///`[1, 2]` for a step up followed by a third up.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::search::*;
+    ///let q = MelodicQuery::from_intervals(&[1, 2]);
+    ///assert_eq!(q.len(), 3);
+    ///```
+    pub fn from_intervals(intervals: &[i32]) -> MelodicQuery {
+        let mut step = 0;
+        let mut notes = vec![QueryNote { step, rhythm: None }];
+        for interval in intervals {
+            step += interval;
+            notes.push(QueryNote { step, rhythm: None });
+        }
+        MelodicQuery { notes }
+    }
+    ///Create a query from a Volpiano string, e.g. "1---g-h--k". Clefs, separators, barlines
+    ///and flats are ignored; liquescent (upper-case) pitches are treated like plain ones.
+    ///# Examples
+    ///```
+    ///# use gabc_parser::search::*;
+    ///let q = MelodicQuery::from_volpiano("1---g-h--k").unwrap();
+    ///assert_eq!(q, MelodicQuery::from_intervals(&[1, 2]));
+    ///assert!(MelodicQuery::from_volpiano("1---t").is_err());
+    ///```
+    pub fn from_volpiano(volpiano: &str) -> Result<MelodicQuery, String> {
+        let mut positions = Vec::new();
+        for c in volpiano.chars() {
+            match c {
+                '1' | '2' | '3' | '4' | '5' | '6' | '7' | '-' | ' ' => {}
+                'i' | 'w' | 'x' | 'y' | 'z' | 'I' | 'W' | 'X' | 'Y' | 'Z' => {}
+                '9' | ')' => positions.push((-1, None)),
+                _ => match VOLPIANO_PITCHES.find(c.to_ascii_lowercase()) {
+                    Some(i) => positions.push((i as i32, None)),
+                    None => return Err(format!("invalid Volpiano character: {}", c)),
+                },
+            }
+        }
+        MelodicQuery::from_steps(positions)
+    }
+    ///Number of notes in this query.
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+    ///Whether this query has no notes (never true for a successfully constructed query).
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+    ///Build a query from absolute staff steps, making them relative to the first note.
+    fn from_steps(positions: Vec<(i32, Option<String>)>) -> Result<MelodicQuery, String> {
+        let first = match positions.first() {
+            Some(&(p, _)) => p,
+            None => return Err("query contains no notes".to_string()),
+        };
+        let notes = positions
+            .into_iter()
+            .map(|(p, rhythm)| QueryNote { step: p - first, rhythm })
+            .collect();
+        Ok(MelodicQuery { notes })
+    }
+}
+
+///Options controlling how a `MelodicQuery` is matched.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    ///Treat runs of notes at the same pitch (e.g. a tristropha "jjj") as a single note, in both
+    ///the query and the searched music
+    pub ignore_repeated_notes: bool,
+    ///Match notes regardless of their rhythmic signs, even if the query specifies some
+    pub ignore_rhythmic_signs: bool,
+}
+
+///A single occurrence of a `MelodicQuery`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchMatch {
+    ///Index of the matching file in the searched slice
+    pub file: usize,
+    ///Indexes of the syllables containing the matched notes
+    pub syllables: Range<usize>,
+    ///Byte range of the matched notes in the file's gabc source
+    pub span: Range<usize>,
+}
+
+///Find every occurrence of `query` in `files`. Matches never extend across a barline; an
+///occurrence may span several syllables.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::search::*;
+///let a = GabcFile::new("%%
+///(c4) Ec(g)ce(hj) (::)");
+///let b = GabcFile::new("%%
+///(c3) Al(ij)le(l)lu(l)ia(l) (::)");
+///let q = MelodicQuery::from_gabc("ghj").unwrap();
+///let matches = search(&[a, b], &q, &SearchOptions::default());
+///assert_eq!(matches.len(), 2);
+///assert_eq!(matches[1].file, 1);
+///assert_eq!(matches[1].syllables, 1..3);
+///```
+pub fn search(files: &[GabcFile], query: &MelodicQuery, options: &SearchOptions) -> Vec<SearchMatch> {
+    let query_notes = if options.ignore_repeated_notes {
+        collapse_repeated(&query.notes, |a, b| a.step == b.step)
+    } else {
+        query.notes.clone()
+    };
+    let mut result = Vec::new();
+    for (file_index, file) in files.iter().enumerate() {
+        for segment in note_segments(file, options.ignore_repeated_notes) {
+            if segment.len() < query_notes.len() {
+                continue;
+            }
+            for start in 0..=(segment.len() - query_notes.len()) {
+                let candidate = &segment[start..start + query_notes.len()];
+                let base = candidate[0].pitch - query_notes[0].step;
+                let is_match = candidate.iter().zip(&query_notes).all(|(n, q)| {
+                    n.pitch - base == q.step
+                        && (options.ignore_rhythmic_signs
+                            || q.rhythm.as_ref().is_none_or(|r| *r == n.rhythm))
+                });
+                if is_match {
+                    let last = &candidate[candidate.len() - 1];
+                    result.push(SearchMatch {
+                        file: file_index,
+                        syllables: candidate[0].syllables.start..last.syllables.end,
+                        span: candidate[0].span.start..last.span.end,
+                    });
+                }
+            }
+        }
+    }
+    result
+}
+
+///A note of a searched file, reduced to what matters for matching.
+#[derive(Debug, Clone)]
+struct SearchNote {
+    pitch: i32,
+    rhythm: String,
+    syllables: Range<usize>,
+    span: Range<usize>,
+}
+
+///Split the notes of a file into runs that a match may not cross: barlines and notes with no
+///recognized clef end a run.
+fn note_segments(file: &GabcFile, ignore_repeated_notes: bool) -> Vec<Vec<SearchNote>> {
+    let mut segments = vec![Vec::new()];
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        for elem in &syllable.music {
+            let note = match elem {
+                NoteElem::Note(n) => n,
+                NoteElem::Barline(_) => {
+                    segments.push(Vec::new());
+                    continue;
+                }
+                NoteElem::Spacer(_) | NoteElem::Clef(_) => continue,
+            };
+            match note.pitch_index() {
+                Some(pitch) => segments.last_mut().unwrap().push(SearchNote {
+                    pitch: pitch as i32,
+                    rhythm: rhythmic_signs(note.suffix),
+                    syllables: i..i + 1,
+                    span: note.span.clone(),
+                }),
+                None => segments.push(Vec::new()),
+            }
+        }
+    }
+    if ignore_repeated_notes {
+        segments = segments
+            .iter()
+            .map(|s| collapse_repeated(s, |a, b| a.pitch == b.pitch))
+            .collect();
+    }
+    segments
+}
+
+///Merge runs of equal elements, keeping the last element of each run but extending its
+///syllables and span back to the start of the run (for `SearchNote`s).
+fn collapse_repeated<T: Clone + Absorb, F: Fn(&T, &T) -> bool>(items: &[T], same: F) -> Vec<T> {
+    let mut result: Vec<T> = Vec::new();
+    for item in items {
+        let merged = match result.last_mut() {
+            Some(prev) if same(prev, item) => {
+                prev.extend_with(item);
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            result.push(item.clone());
+        }
+    }
+    result
+}
+
+///Absorb a following repeated note into this one.
+trait Absorb {
+    fn extend_with(&mut self, next: &Self);
+}
+
+impl Absorb for QueryNote {
+    fn extend_with(&mut self, next: &QueryNote) {
+        self.rhythm = next.rhythm.clone();
+    }
+}
+
+impl Absorb for SearchNote {
+    fn extend_with(&mut self, next: &SearchNote) {
+        self.rhythm = next.rhythm.clone();
+        self.syllables.end = next.syllables.end;
+        self.span.end = next.span.end;
+    }
+}
+
+///Position of a gabc staff letter (a-m, either case) counted from 'a'.
+fn staff_index(position: char) -> i32 {
+    position.to_ascii_lowercase() as i32 - 'a' as i32
+}
+
+///Extract the rhythmic signs (dots, episemata and ictus) from a note suffix.
+fn rhythmic_signs(suffix: &str) -> String {
+    suffix.chars().filter(|c| matches!(c, '.' | '_' | '\'')).collect()
+}
@@ -0,0 +1,276 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Melodic similarity between `GabcFile`s and an n-gram index for grouping variant versions of
+//! the same chant. Pitches are compared after resolving clefs, so the same melody written with
+//! different clefs compares equal; alignment and the n-gram index work on intervals, so they
+//! are also insensitive to transposition.
+
+use pest::Parser;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use {parsed_file_to_struct, GABCParser, GabcFile, NoteElem, Rule};
+
+///Score of aligning two equal intervals in `alignment_score()`.
+const MATCH_SCORE: i64 = 2;
+///Score of aligning two different intervals in `alignment_score()`.
+const MISMATCH_SCORE: i64 = -1;
+///Score of aligning an interval against a gap in `alignment_score()`.
+const GAP_SCORE: i64 = -2;
+
+///Overall melodic similarity of two files between 0 (unrelated) and 1 (same intervals), computed
+///from their `alignment_score()` relative to the best possible score for the longer melody.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::similarity::*;
+///let a = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh)ia(g.) (::)");
+///let b = GabcFile::new("%%
+///(c3) Al(hi)le(k)lu(ki)ia(h.) (::)");
+///let c = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh)ia(hg.) (::)");
+///assert_eq!(similarity(&a, &b), 1.0);
+///assert!(similarity(&a, &c) < 1.0);
+///```
+pub fn similarity(a: &GabcFile, b: &GabcFile) -> f64 {
+    let (a, b) = (intervals(&pitches(a)), intervals(&pitches(b)));
+    let best = MATCH_SCORE * max(a.len(), b.len()) as i64;
+    if best == 0 {
+        return 1.0;
+    }
+    max(align(&a, &b), 0) as f64 / best as f64
+}
+
+///Levenshtein distance between the pitch sequences of two files: the number of notes that must
+///be inserted, deleted or changed to turn one melody into the other.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::similarity::*;
+///let a = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh)ia(g.) (::)");
+///let b = GabcFile::new("%%
+///(c4) Al(g)le(j)lu(jh)ia(f.) (::)");
+///assert_eq!(pitch_edit_distance(&a, &b), 2);
+///```
+pub fn pitch_edit_distance(a: &GabcFile, b: &GabcFile) -> usize {
+    let (a, b) = (pitches(a), pitches(b));
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + if x == y { 0 } else { 1 };
+            current.push(*[substitution, previous[j + 1] + 1, current[j] + 1].iter().min().unwrap());
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+///Global (Needleman-Wunsch) alignment score of the interval sequences of two files. Equal
+///intervals score +2, different intervals -1 and gaps -2, so higher is more similar.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::similarity::*;
+///let a = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh)ia(g.) (::)");
+///assert_eq!(alignment_score(&a, &a), 10);
+///```
+pub fn alignment_score(a: &GabcFile, b: &GabcFile) -> i64 {
+    align(&intervals(&pitches(a)), &intervals(&pitches(b)))
+}
+
+///Needleman-Wunsch alignment of two interval sequences.
+fn align(a: &[i32], b: &[i32]) -> i64 {
+    let mut previous: Vec<i64> = (0..=b.len() as i64).map(|j| j * GAP_SCORE).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut current = vec![(i as i64 + 1) * GAP_SCORE];
+        for (j, y) in b.iter().enumerate() {
+            let diagonal = previous[j] + if x == y { MATCH_SCORE } else { MISMATCH_SCORE };
+            let best = max(diagonal, max(previous[j + 1], current[j]) + GAP_SCORE);
+            current.push(best);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+///Clef-resolved pitches of every note in a file, skipping notes with no recognized clef.
+fn pitches(file: &GabcFile) -> Vec<i32> {
+    let mut result = Vec::new();
+    for syllable in &file.syllables {
+        for elem in &syllable.music {
+            if let NoteElem::Note(n) = elem {
+                if let Some(p) = n.pitch_index() {
+                    result.push(p as i32);
+                }
+            }
+        }
+    }
+    result
+}
+
+///Diatonic intervals between successive pitches.
+fn intervals(pitches: &[i32]) -> Vec<i32> {
+    pitches.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+///A file stored in an `NGramIndex`.
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexEntry {
+    name: String,
+    intervals: Vec<i32>,
+}
+
+///A nearest neighbour returned by `NGramIndex::nearest()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbour {
+    ///Name of the indexed file (its file name, for indexes built from a directory)
+    pub name: String,
+    ///Jaccard similarity of the interval n-grams of the query and this file, between 0 and 1
+    pub score: f64,
+}
+
+///Index of interval n-grams over many gabc files, which can be saved to and loaded from disk
+///and queried for the files most similar to a given melody.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::similarity::*;
+///let mut index = NGramIndex::new(3).unwrap();
+///index.add("a", &GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh)ia(g.) (::)"));
+///index.add("b", &GabcFile::new("%%
+///(c4) Ky(d)ri(fe)e(d.) (::)"));
+///let query = GabcFile::new("%%
+///(f3) Al(cd)le(f)lu(fd)ia(c.) (::)");
+///let nearest = index.nearest(&query, 1);
+///assert_eq!(nearest[0].name, "a");
+///assert_eq!(nearest[0].score, 1.0);
+///assert!(NGramIndex::new(0).is_err());
+///```
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NGramIndex {
+    n: usize,
+    entries: Vec<IndexEntry>,
+    ///Entries containing each n-gram; rebuilt rather than stored on disk
+    #[serde(skip)]
+    grams: HashMap<Vec<i32>, Vec<usize>>,
+}
+
+impl NGramIndex {
+    ///Create an empty index of interval n-grams of length `n`. An n-gram length of 0 is an
+    ///invalid input.
+    pub fn new(n: usize) -> io::Result<NGramIndex> {
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "n-gram length must be positive"));
+        }
+        Ok(NGramIndex {
+            n,
+            entries: Vec::new(),
+            grams: HashMap::new(),
+        })
+    }
+    ///Build an index from every ".gabc" file in a directory, named by file name. Fails if `n` is
+    ///0, or if a file cannot be read or parsed.
+    pub fn from_directory<P: AsRef<Path>>(dir: P, n: usize) -> io::Result<NGramIndex> {
+        let mut index = NGramIndex::new(n)?;
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.retain(|p| p.extension().is_some_and(|e| e == "gabc"));
+        paths.sort();
+        for path in paths {
+            let text = fs::read_to_string(&path)?;
+            let parsed = GABCParser::parse(Rule::file, &text).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            })?;
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            index.add(&name, &parsed_file_to_struct(parsed));
+        }
+        Ok(index)
+    }
+    ///Load an index previously written with `save()`. An index with an n-gram length of 0 is
+    ///invalid data.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<NGramIndex> {
+        let text = fs::read_to_string(path)?;
+        let mut index: NGramIndex = serde_json::from_str(&text)?;
+        if index.n == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "n-gram length must be positive"));
+        }
+        for i in 0..index.entries.len() {
+            index.index_grams(i);
+        }
+        Ok(index)
+    }
+    ///Write this index to disk.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+    ///Add a file to the index under the given name.
+    pub fn add(&mut self, name: &str, file: &GabcFile) {
+        self.entries.push(IndexEntry {
+            name: name.to_string(),
+            intervals: intervals(&pitches(file)),
+        });
+        let i = self.entries.len() - 1;
+        self.index_grams(i);
+    }
+    ///Number of files in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    ///Whether the index contains no files.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    ///Find the (at most) `k` indexed files sharing the most interval n-grams with `file`, most
+    ///similar first. Files sharing no n-gram with `file` are never returned.
+    pub fn nearest(&self, file: &GabcFile, k: usize) -> Vec<Neighbour> {
+        let query_intervals = intervals(&pitches(file));
+        let query = self.gram_set(&query_intervals);
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for gram in &query {
+            for &entry in self.grams.get(*gram).into_iter().flatten() {
+                *shared.entry(entry).or_insert(0) += 1;
+            }
+        }
+        let mut result: Vec<(usize, f64)> = shared
+            .into_iter()
+            .map(|(entry, common)| {
+                let size = self.gram_set(&self.entries[entry].intervals).len();
+                (entry, common as f64 / (query.len() + size - common) as f64)
+            })
+            .collect();
+        result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        result
+            .into_iter()
+            .take(k)
+            .map(|(entry, score)| Neighbour {
+                name: self.entries[entry].name.clone(),
+                score,
+            })
+            .collect()
+    }
+    ///Distinct n-grams of an interval sequence.
+    fn gram_set<'a>(&self, intervals: &'a [i32]) -> HashSet<&'a [i32]> {
+        intervals.windows(self.n).collect()
+    }
+    ///Record the n-grams of an entry in the lookup table.
+    fn index_grams(&mut self, entry: usize) {
+        let grams: Vec<Vec<i32>> = self
+            .gram_set(&self.entries[entry].intervals)
+            .into_iter()
+            .map(|g| g.to_vec())
+            .collect();
+        for gram in grams {
+            self.grams.entry(gram).or_default().push(entry);
+        }
+    }
+}
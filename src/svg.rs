@@ -0,0 +1,73 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! SVG output of the square-notation layout of the `render` module: one drawing as wide as a
+//! line of music, with the lines one below the other. Notes, staves and signs are drawn as
+//! plain shapes, and the text as `<text>` in DejaVu Serif, or the viewer's serif font if it does
+//! not have DejaVu.
+
+use font::FAMILY;
+use render::{layout, number, RenderOptions, Shape};
+use {escape_xml, GabcFile};
+
+///Render a `GabcFile` in square notation as an SVG document.
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::render::*;
+///let f = GabcFile::new("%%
+///(c4) Al(gh)le(j)lu(jh~)ia.(g.) *(;) (::)");
+///let svg = f.as_svg(&RenderOptions::default());
+///assert!(svg.contains("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"600\""));
+///assert!(svg.contains(">Al</text>"));
+///assert!(svg.contains("fill=\"#c00000\">*</text>"));
+///```
+pub fn to_svg(file: &GabcFile, options: &RenderOptions) -> String {
+    let layout = layout(file, options);
+    let height: f64 = layout.systems.iter().map(|s| s.height).sum();
+    let mut result = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    result.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        number(layout.width),
+        number(height)
+    ));
+    let mut top = 0.0;
+    for system in layout.systems {
+        result.push_str(&format!("  <g transform=\"translate(0 {})\">\n", number(top)));
+        for shape in system.shapes {
+            result.push_str("    ");
+            result.push_str(&element(&shape));
+            result.push('\n');
+        }
+        result.push_str("  </g>\n");
+        top += system.height;
+    }
+    result.push_str("</svg>\n");
+    result
+}
+
+///An SVG element drawing a shape.
+fn element(shape: &Shape) -> String {
+    let points = |points: &[(f64, f64)]| {
+        points.iter().map(|(x, y)| format!("{},{}", number(*x), number(*y))).collect::<Vec<_>>().join(" ")
+    };
+    match shape {
+        Shape::Polygon(p) => format!("<polygon points=\"{}\"/>", points(p)),
+        Shape::Line(p, width) => format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\"/>",
+            points(p),
+            number(*width)
+        ),
+        Shape::Circle((x, y), r) => format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\"/>", number(*x), number(*y), number(*r)),
+        Shape::Text { x, y, size, text, rubric } => format!(
+            "<text x=\"{}\" y=\"{}\" font-family=\"{}, serif\" font-size=\"{}\"{}>{}</text>",
+            number(*x),
+            number(*y),
+            FAMILY,
+            number(*size),
+            if *rubric { " fill=\"#c00000\"" } else { "" },
+            escape_xml(text)
+        ),
+    }
+}
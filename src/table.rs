@@ -0,0 +1,206 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Tables of syllables, as CSV or TSV, for adding accompaniment chords in a spreadsheet. Each
+//! syllable of a file is a row, after a header row, with the columns:
+//!
+//! - `syllable`: the number of the syllable in the file, counting from 0
+//! - `text`: its text
+//! - `gabc`: its music, as written in the gabc
+//! - `lilypond`: the pitches of its notes, as in `GabcFile::as_lilypond()` with accidentals
+//! - `midi`: the MIDI note numbers of its notes (c' is middle C, 60)
+//! - `division`: the divisions in its music, e.g. "::"
+//! - `chord`: its text above the staff (see `Syllable::annotation()`), for the chord
+//!
+//! Pitches and note numbers are separated by spaces. `merge_chords()` reads the `chord` column of
+//! an edited table back into the gabc as text above the staff (`<alt>`), where GregorioTeX prints
+//! it. Columns can be added or moved in the spreadsheet, as long as the header row is kept.
+
+use events::{events, midi_key, pitch_name, Event};
+use pest::Parser;
+use {text_tokens, GABCParser, GabcFile, NoteElem, Rule};
+
+///Format of a table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    ///Comma-separated values, quoted where needed (RFC 4180)
+    Csv,
+    ///Tab-separated values; tabs and line breaks in fields become spaces
+    Tsv,
+}
+
+///Names of the columns, in order.
+static COLUMNS: [&str; 7] = ["syllable", "text", "gabc", "lilypond", "midi", "division", "chord"];
+
+///Write the syllables of a `GabcFile` as a table.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("%%
+///(c4) Al(gh/ixi)le(j) (::)");
+///assert_eq!(f.as_csv(), "syllable,text,gabc,lilypond,midi,division,chord
+///0,,c4,,,,
+///1,Al,gh/ixi,g a bes,55 57 58,,
+///2,le,j,c',60,,
+///3,,::,,,::,
+///");
+///```
+pub fn to_table(file: &GabcFile, format: Format) -> String {
+    let mut rows = vec![COLUMNS.iter().map(|c| c.to_string()).collect::<Vec<_>>()];
+    let mut notes: Vec<Vec<(String, i32)>> = vec![Vec::new(); file.syllables.len()];
+    for event in events(file) {
+        if let Event::Note(n) = event {
+            notes[n.syllable].push((lilypond_pitch(n.pitch, n.alter), midi_key(n.pitch, n.alter)));
+        }
+    }
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let (_, tokens, _) = text_tokens(syllable.text);
+        let divisions: Vec<&str> = syllable
+            .music
+            .iter()
+            .filter_map(|e| match e {
+                NoteElem::Barline(b) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        rows.push(vec![
+            i.to_string(),
+            tokens.into_iter().map(|t| t.text).collect::<Vec<_>>().join(" "),
+            syllable.music.iter().map(|e| e.to_gabc()).collect(),
+            notes[i].iter().map(|n| n.0.as_str()).collect::<Vec<_>>().join(" "),
+            notes[i].iter().map(|n| n.1.to_string()).collect::<Vec<_>>().join(" "),
+            divisions.join(" "),
+            syllable.annotation().unwrap_or("").to_string(),
+        ]);
+    }
+    let mut result = String::new();
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|f| field(f, format)).collect();
+        result.push_str(&fields.join(if format == Format::Csv { "," } else { "\t" }));
+        result.push('\n');
+    }
+    result
+}
+
+///The Lilypond name of a pitch with an alteration, e.g. "bes" or "c'".
+fn lilypond_pitch(pitch: usize, alter: i32) -> String {
+    let (letter, octave) = pitch_name(pitch);
+    let accidental = match alter {
+        -1 => "es",
+        1 => "is",
+        _ => "",
+    };
+    let marks = if octave >= 3 { "'".repeat(octave as usize - 3) } else { ",".repeat(3 - octave as usize) };
+    format!("{}{}{}", letter, accidental, marks)
+}
+
+///A field as written in a table.
+fn field(text: &str, format: Format) -> String {
+    match format {
+        Format::Csv if text.contains([',', '"', '\n', '\r']) => format!("\"{}\"", text.replace('"', "\"\"")),
+        Format::Csv => text.to_string(),
+        Format::Tsv => text.replace(['\t', '\n', '\r'], " "),
+    }
+}
+
+///Read the `chord` column of a table of the syllables of a gabc file (as written by
+///`to_table()`, in either format, and perhaps edited) into the gabc, as text above the staff at
+///the start of each syllable's text. A syllable whose chord is empty loses any text above the
+///staff it had; syllables missing from the table are unchanged. Everything else in the gabc is
+///kept as it is. It is an error if the gabc does not parse, if the table has no `syllable` or
+///`chord` column, if a syllable number is not in the file, or if a chord contains "(", ")", "<"
+///or ">".
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::table::*;
+///let gabc = "%%
+///(c4) Al(gh/ixi)le(j) (::)";
+///let table = "syllable\tchord\n1\tGm\n2\tC\n";
+///let merged = merge_chords(gabc, table).unwrap();
+///assert_eq!(merged, "%%
+///(c4) <alt>Gm</alt>Al(gh/ixi)<alt>C</alt>le(j) (::)");
+///assert_eq!(GabcFile::new(&merged).syllables[1].annotation(), Some("Gm"));
+///assert!(merge_chords(gabc, "syllable\tchord\n7\tF\n").is_err());
+///```
+pub fn merge_chords(gabc: &str, table: &str) -> Result<String, String> {
+    let rows = read_table(table);
+    let header = rows.first().ok_or("empty table")?;
+    let column = |name: &str| header.iter().position(|c| c.trim() == name).ok_or(format!("no {} column", name));
+    let (syllable_column, chord_column) = (column("syllable")?, column("chord")?);
+    GABCParser::parse(Rule::file, gabc).map_err(|e| format!("{}", e))?;
+    let file = GabcFile::new(gabc);
+    let mut chords: Vec<Option<String>> = vec![None; file.syllables.len()];
+    for row in &rows[1..] {
+        let number = row.get(syllable_column).map_or("", |s| s.trim());
+        if number.is_empty() {
+            continue;
+        }
+        let i: usize = number.parse().map_err(|_| format!("invalid syllable number: {}", number))?;
+        if i >= chords.len() {
+            return Err(format!("no syllable {} in the file", i));
+        }
+        let chord = row.get(chord_column).map_or("", |s| s.trim());
+        if chord.contains(['(', ')', '<', '>']) {
+            return Err(format!("invalid chord: {}", chord));
+        }
+        chords[i] = Some(chord.to_string());
+    }
+    //Replace the text of the syllables from the end, so the spans before stay valid
+    let mut result = gabc.to_string();
+    for (syllable, chord) in file.syllables.iter().zip(chords).rev() {
+        let chord = match chord {
+            Some(c) => c,
+            None => continue,
+        };
+        let start = syllable.span.start;
+        let mut text = syllable.text.to_string();
+        if let Some(alt) = text.find("<alt>") {
+            let end = text[alt..].find("</alt>").map_or(text.len(), |e| alt + e + "</alt>".len());
+            text.replace_range(alt..end, "");
+        }
+        if !chord.is_empty() {
+            let indent = text.len() - text.trim_start().len();
+            text.insert_str(indent, &format!("<alt>{}</alt>", chord));
+        }
+        result.replace_range(start..start + syllable.text.len(), &text);
+    }
+    Ok(result)
+}
+
+///Split a table into rows of fields: tab-separated if its first line has a tab, otherwise
+///comma-separated with quotes as in RFC 4180.
+fn read_table(table: &str) -> Vec<Vec<String>> {
+    let first = table.lines().next().unwrap_or("");
+    if first.contains('\t') {
+        return table.lines().filter(|l| !l.trim().is_empty()).map(|l| l.split('\t').map(String::from).collect()).collect();
+    }
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = table.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.trim().is_empty()));
+    rows
+}
@@ -0,0 +1,170 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! A text preview of a `GabcFile` for terminals: the four-line staff drawn with box characters,
+//! one row for each staff position (with rows above and below the staff only where they are
+//! used), and the text of the syllables underneath. Each note takes one column at its written
+//! position (■, ◆ for puncta inclinata and ▪ for liquescents), followed by a dot for a punctum
+//! mora; flats, naturals and sharps are ♭, ♮ and ♯. Clefs are written as "C" or "F" on their
+//! line, and divisions as vertical marks of their usual heights. Lines are wrapped between
+//! syllables to fit the terminal, and each new line starts with the clef in force.
+
+use {text_tokens, GabcFile, NoteElem, Syllabic};
+
+///A column of the preview: what is written at each staff position, from "a" up.
+type Column = [Option<char>; 13];
+
+///Draw a `GabcFile` as text, wrapping lines to a number of columns. A syllable wider than the
+///terminal is not divided.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("%%
+///(c3) Al(eh/hi)le(h.) (::)");
+///assert_eq!(f.as_terminal(80), "\
+///─────────────┬┬─
+///       ■     ││
+///C───■─■──■·──┼┼─
+///             ││
+///─────────────┼┼─
+///   ■         ││
+///─────────────┴┴─
+///   Al-   le
+///");
+///```
+pub fn to_terminal(file: &GabcFile, columns: usize) -> String {
+    let lyrics = file.syllable_lyrics();
+    let mut result = String::new();
+    let mut system: Vec<Column> = Vec::new();
+    let mut text: Vec<char> = Vec::new();
+    let mut clef: Option<&str> = None;
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let (_, tokens, _) = text_tokens(syllable.text);
+        let mut words: Vec<char> = tokens.into_iter().map(|t| t.text).collect::<Vec<_>>().join(" ").chars().collect();
+        if matches!(lyrics[i], Some((_, Syllabic::Begin)) | Some((_, Syllabic::Middle))) {
+            words.push('-');
+        }
+        let mut music = music(&syllable.music);
+        if music.is_empty() && words.is_empty() {
+            continue;
+        }
+        let width = music.len().max(words.len());
+        if !system.is_empty() && system.len() + width > columns {
+            write_system(&mut result, &system, &text);
+            system.clear();
+            text.clear();
+            if let (Some(c), false) = (clef, matches!(syllable.music.first(), Some(NoteElem::Clef(_)))) {
+                system.extend(clef_columns(c));
+                text.resize(system.len(), ' ');
+            }
+        }
+        music.resize(width, [None; 13]);
+        system.extend(music);
+        text.extend(&words);
+        text.resize(system.len(), ' ');
+        //A column between syllables
+        system.push([None; 13]);
+        text.push(' ');
+        for elem in &syllable.music {
+            if let NoteElem::Clef(c) = elem {
+                clef = Some(c);
+            }
+        }
+    }
+    if !system.is_empty() {
+        write_system(&mut result, &system, &text);
+    }
+    result
+}
+
+///Whether a staff position is on a line of the staff.
+fn on_line(position: usize) -> bool {
+    matches!(position, 3 | 5 | 7 | 9)
+}
+
+///Write the rows of a line of music and its text, leaving out the rows above and below the staff
+///if they are empty and the column after the last syllable.
+fn write_system(result: &mut String, system: &[Column], text: &[char]) {
+    let system = &system[..system.len() - 1];
+    let used: Vec<usize> = (0..13).filter(|&p| system.iter().any(|c| c[p].is_some())).collect();
+    let low = used.first().map_or(3, |&p| p.min(3));
+    let high = used.last().map_or(9, |&p| p.max(9));
+    for position in (low..=high).rev() {
+        let background = if on_line(position) { '─' } else { ' ' };
+        let row: String = system.iter().map(|c| c[position].unwrap_or(background)).collect();
+        result.push_str(row.trim_end());
+        result.push('\n');
+    }
+    let text: String = text.iter().collect();
+    result.push_str(text.trim_end());
+    result.push('\n');
+}
+
+///The columns of a syllable's music.
+fn music(music: &[NoteElem]) -> Vec<Column> {
+    let mut columns = Vec::new();
+    for elem in music {
+        match elem {
+            NoteElem::Note(n) => {
+                let position = (n.position.to_ascii_lowercase() as u8 - b'a') as usize;
+                let mut column = [None; 13];
+                let sign = ['x', 'y', '#'].iter().position(|s| n.suffix.contains(*s));
+                column[position] = Some(match sign {
+                    Some(s) => ['♭', '♮', '♯'][s],
+                    None if n.position.is_ascii_uppercase() => '◆',
+                    None if n.suffix.contains(['~', '<', '>']) => '▪',
+                    None => '■',
+                });
+                //Ledger lines
+                if position <= 1 && column[1].is_none() {
+                    column[1] = Some('─');
+                } else if position >= 11 && column[11].is_none() {
+                    column[11] = Some('─');
+                }
+                columns.push(column);
+                if sign.is_none() && n.suffix.contains('.') {
+                    let mut dot = [None; 13];
+                    dot[position] = Some('·');
+                    columns.push(dot);
+                }
+            }
+            NoteElem::Spacer(" ") => columns.extend(vec![[None; 13]; 2]),
+            NoteElem::Spacer("!") | NoteElem::Spacer("@") | NoteElem::Spacer("/0") => {}
+            NoteElem::Spacer(_) => columns.push([None; 13]),
+            NoteElem::Clef(c) => columns.extend(clef_columns(c)),
+            NoteElem::Barline(bar) => {
+                let (low, high) = match *bar {
+                    "'" | "`" => (10, 11),
+                    b if b.starts_with(':') => (3, 9),
+                    b if b.starts_with(';') => (4, 8),
+                    _ => (8, 10),
+                };
+                let mut column = [None; 13];
+                for (position, cell) in column.iter_mut().enumerate().take(high + 1).skip(low) {
+                    *cell = Some(match position {
+                        p if !on_line(p) => '│',
+                        p if p == high => '┬',
+                        p if p == low => '┴',
+                        _ => '┼',
+                    });
+                }
+                columns.push([None; 13]);
+                columns.push(column);
+                if *bar == "::" {
+                    columns.push(column);
+                }
+                columns.push([None; 13]);
+            }
+        }
+    }
+    columns
+}
+
+///The columns of a clef: its letter on its line, and a space.
+fn clef_columns(clef: &str) -> Vec<Column> {
+    let mut column = [None; 13];
+    let line = clef[1..].parse::<usize>().unwrap_or(3);
+    column[2 * line + 1] = Some(clef.chars().next().unwrap().to_ascii_uppercase());
+    vec![column, [None; 13]]
+}
@@ -0,0 +1,425 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Re-underlay of new text onto an existing melody (contrafactum). Every syllable of the original
+//! sung text that carries notes is a slot; the new syllables are aligned to the slots in order,
+//! splitting a neume between several new syllables or merging several neumes onto one new
+//! syllable where the syllable counts differ. Barlines, rubrics and other syllables without sung
+//! text are kept as they are. Like the psalm tone engine, this returns gabc source; parse it
+//! with `GabcFile::new()` to get a `GabcFile`.
+
+use latin::{is_acute, Word};
+use std::ops::Range;
+use {GabcFile, Note, NoteElem, Syllable};
+
+///Most new syllables a single neume may be split between, or most neumes that may be merged onto
+///a single new syllable.
+const MAX_GROUP: usize = 4;
+///Cost of a second syllable sharing a neume; each further syllable costs more.
+const SPLIT_COST: i64 = 20;
+///Cost of each new syllable that has to repeat a note because its neume is too short to split.
+const REPEAT_COST: i64 = 40;
+///Cost of each extra neume merged onto a syllable.
+const MERGE_COST: i64 = 20;
+///Cost of continuing a word across a barline or rubric.
+const BREAK_COST: i64 = 50;
+///Cost of a syllable whose accent does not match the melody (`Strategy::AccentAware` only).
+const ACCENT_COST: i64 = 15;
+
+///How new syllables are distributed when their number differs from the number of slots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    ///Align syllables in order, splitting the longest neumes and merging the shortest ones
+    Sequential,
+    ///As `Sequential`, but also try to keep accented syllables of the new text on the notes
+    ///that carried accented syllables in the original
+    AccentAware,
+}
+
+///Why a place in a re-underlaid melody may need checking by hand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmbiguityKind {
+    ///A neume was divided between several new syllables
+    SplitNeume,
+    ///A neume had fewer notes than the syllables sharing it, so a note was repeated
+    RepeatedNote,
+    ///Several neumes were sung on one new syllable
+    MergedNeumes,
+    ///An accented new syllable falls on a note that was not accented in the original
+    AccentMismatch,
+}
+
+///A place in a re-underlaid melody that may need checking by hand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ambiguity {
+    ///Indexes of the affected new syllables, counted across all words of the new text
+    pub text: Range<usize>,
+    ///Indexes of the affected syllables of the original `GabcFile`
+    pub original: Range<usize>,
+    ///What makes this place ambiguous
+    pub kind: AmbiguityKind,
+}
+
+///The result of `underlay()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Underlay {
+    ///The new gabc file, with the original header
+    pub gabc: String,
+    ///Places where the alignment had to split or merge neumes or missed an accent
+    pub ambiguities: Vec<Ambiguity>,
+}
+
+///A syllable of the new text.
+struct NewSyllable {
+    text: String,
+    accented: bool,
+    word_start: bool,
+}
+
+///A syllable of the original melody that receives new text.
+struct Slot<'f, 'a: 'f> {
+    ///Index in the original file's syllables
+    index: usize,
+    music: &'f [NoteElem<'a>],
+    notes: usize,
+    accented: bool,
+    ///Whether the next slot follows this one with nothing but clefs in between
+    joined: bool,
+}
+
+///New syllables sung on a run of slots: either one syllable on several slots or several
+///syllables on one slot.
+struct Group {
+    text: Range<usize>,
+    slots: Range<usize>,
+}
+
+///Set new text to the melody of `file`. The new text is given as syllabified words, e.g. from
+///`latin::words()`. Fails if the file has no sung syllables with notes, or if the syllable
+///counts are too different to align (more than `MAX_GROUP` syllables per neume or neumes per
+///syllable).
+///# Examples
+///```
+///# use gabc_parser::*;
+///# use gabc_parser::latin::words;
+///# use gabc_parser::underlay::*;
+///let f = GabcFile::new("name:Test;
+///%%
+///(c4) Al(gh)le(j)lu(jh/ih)ia.(g.) (::)");
+///let u = underlay(&f, &words("Gloria tibi"), Strategy::Sequential).unwrap();
+///assert_eq!(u.gabc, "name:Test;
+///%%
+///(c4) Glo(gh)ri(j)a(jh) ti(ih)bi(g.) (::)");
+///assert_eq!(u.ambiguities[0].kind, AmbiguityKind::SplitNeume);
+///assert_eq!(u.ambiguities[0].original, 3..4);
+///let u = underlay(&f, &words("Lauda"), Strategy::Sequential).unwrap();
+///assert_eq!(u.gabc, "name:Test;
+///%%
+///(c4) Lau(gh)da(j/jh/ih/g.) (::)");
+///```
+pub fn underlay(file: &GabcFile, text: &[Word], strategy: Strategy) -> Result<Underlay, String> {
+    let syllables = new_syllables(text);
+    let slots = slots(file);
+    if syllables.is_empty() {
+        return Err("no text to underlay".to_string());
+    }
+    if slots.is_empty() {
+        return Err("the melody has no sung syllables".to_string());
+    }
+    let groups = align(&syllables, &slots, strategy)?;
+    let mut ambiguities = Vec::new();
+    for group in &groups {
+        let original = slots[group.slots.start].index..slots[group.slots.end - 1].index + 1;
+        let mut report = |kind| {
+            ambiguities.push(Ambiguity {
+                text: group.text.clone(),
+                original: original.clone(),
+                kind,
+            })
+        };
+        if group.slots.len() > 1 {
+            report(AmbiguityKind::MergedNeumes);
+        } else if group.text.len() > slots[group.slots.start].notes {
+            report(AmbiguityKind::RepeatedNote);
+        } else if group.text.len() > 1 {
+            report(AmbiguityKind::SplitNeume);
+        }
+        if strategy == Strategy::AccentAware {
+            let expected = expected_accents(group, &slots);
+            if syllables[group.text.clone()].iter().zip(expected).any(|(s, e)| s.accented && !e) {
+                report(AmbiguityKind::AccentMismatch);
+            }
+        }
+    }
+    Ok(Underlay {
+        gabc: write_gabc(file, &syllables, &slots, &groups),
+        ambiguities,
+    })
+}
+
+///Flatten words into syllables, marking accents and word starts.
+fn new_syllables(text: &[Word]) -> Vec<NewSyllable> {
+    let mut result = Vec::new();
+    for word in text {
+        for (i, s) in word.syllables.iter().enumerate() {
+            result.push(NewSyllable {
+                text: s.clone(),
+                accented: word.accent == Some(i),
+                word_start: i == 0,
+            });
+        }
+    }
+    result
+}
+
+///Find the slots of a file: syllables with notes belonging to a sung (non-rubric) word.
+fn slots<'f, 'a>(file: &'f GabcFile<'a>) -> Vec<Slot<'f, 'a>> {
+    let mut sung = vec![false; file.syllables.len()];
+    let mut accented = vec![false; file.syllables.len()];
+    for word in file.words().into_iter().filter(|w| !w.rubric) {
+        let range = word.syllables.clone();
+        for i in range.clone() {
+            sung[i] = true;
+        }
+        //Accents marked in the text win; otherwise guess them from the word
+        if range.clone().any(|i| file.syllables[i].text.chars().any(is_acute)) {
+            for i in range {
+                accented[i] = file.syllables[i].text.chars().any(is_acute);
+            }
+        } else {
+            let latin = Word::new(&word.text);
+            if let (Some(a), true) = (latin.accent, latin.syllables.len() == range.len()) {
+                accented[range.start + a] = true;
+            }
+        }
+    }
+    let mut result: Vec<Slot> = Vec::new();
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        let notes = syllable.music.iter().filter(|e| matches!(e, NoteElem::Note(_))).count();
+        if !sung[i] || notes == 0 {
+            continue;
+        }
+        if let Some(last) = result.last_mut() {
+            last.joined = file.syllables[last.index + 1..i]
+                .iter()
+                .all(is_clef_only);
+        }
+        result.push(Slot {
+            index: i,
+            music: &syllable.music,
+            notes,
+            accented: accented[i],
+            joined: false,
+        });
+    }
+    result
+}
+
+///Find the cheapest alignment of syllables to slots by dynamic programming.
+fn align(syllables: &[NewSyllable], slots: &[Slot], strategy: Strategy) -> Result<Vec<Group>, String> {
+    let (n, m) = (syllables.len(), slots.len());
+    //best[i][j]: cost of aligning the first i syllables to the first j slots, and the last group
+    let mut best: Vec<Vec<Option<(i64, usize, usize)>>> = vec![vec![None; m + 1]; n + 1];
+    best[0][0] = Some((0, 0, 0));
+    for i in 0..=n {
+        for j in 0..=m {
+            let cost = match best[i][j] {
+                Some((c, _, _)) => c,
+                None => continue,
+            };
+            let moves = (1..=MAX_GROUP).map(|a| (a, 1)).chain((2..=MAX_GROUP).map(|b| (1, b)));
+            for (a, b) in moves {
+                if i + a > n || j + b > m || !slots[j..j + b - 1].iter().all(|s| s.joined) {
+                    continue;
+                }
+                let group = Group {
+                    text: i..i + a,
+                    slots: j..j + b,
+                };
+                let total = cost + group_cost(&group, syllables, slots, strategy);
+                if best[i + a][j + b].is_none_or(|(c, _, _)| total < c) {
+                    best[i + a][j + b] = Some((total, a, b));
+                }
+            }
+        }
+    }
+    if best[n][m].is_none() {
+        return Err(format!("cannot align {} syllables to {} neumes", n, m));
+    }
+    let mut groups = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 {
+        let (_, a, b) = best[i][j].unwrap();
+        groups.push(Group {
+            text: i - a..i,
+            slots: j - b..j,
+        });
+        i -= a;
+        j -= b;
+    }
+    groups.reverse();
+    Ok(groups)
+}
+
+///Cost of singing a group's syllables on its slots.
+fn group_cost(group: &Group, syllables: &[NewSyllable], slots: &[Slot], strategy: Strategy) -> i64 {
+    let (a, b) = (group.text.len() as i64, group.slots.len() as i64);
+    let mut cost = 0;
+    if a > 1 {
+        let notes = slots[group.slots.start].notes as i64;
+        cost += SPLIT_COST * a * (a - 1) / 2;
+        //Prefer splitting long neumes, and avoid repeating notes
+        cost += if notes < a { REPEAT_COST * (a - notes) } else { (8 - notes).max(0) };
+    }
+    if b > 1 {
+        //Prefer merging short neumes
+        let notes: usize = slots[group.slots.clone()].iter().map(|s| s.notes).sum();
+        cost += MERGE_COST * (b - 1) + notes as i64;
+    }
+    if group.slots.start > 0 && !slots[group.slots.start - 1].joined && !syllables[group.text.start].word_start {
+        cost += BREAK_COST;
+    }
+    if strategy == Strategy::AccentAware {
+        let expected = expected_accents(group, slots);
+        let mismatches = syllables[group.text.clone()]
+            .iter()
+            .zip(expected)
+            .filter(|(s, e)| s.accented != *e)
+            .count();
+        cost += ACCENT_COST * mismatches as i64;
+    }
+    cost
+}
+
+///Whether each syllable of a group is sung where the original had an accent. When a neume is
+///split, the last syllable takes the bulk of it and so inherits its accent.
+fn expected_accents(group: &Group, slots: &[Slot]) -> Vec<bool> {
+    let accented = slots[group.slots.clone()].iter().any(|s| s.accented);
+    group.text.clone().map(|i| accented && i + 1 == group.text.end).collect()
+}
+
+///Write the new gabc file: the original header, then the original syllables with each run of
+///slots replaced by its group. The music of merged slots is separated by a spacer ("/"), so that
+///their neumes stay apart. The original clefs are dropped and clefs written where the notes
+///need them, since splitting and merging may move the syllables they were on.
+fn write_gabc(file: &GabcFile, syllables: &[NewSyllable], slots: &[Slot], groups: &[Group]) -> String {
+    let spacer = NoteElem::Spacer("/");
+    let mut result = String::new();
+    for (key, value) in &file.attributes {
+        result.push_str(&format!("{}:{};\n", key, value));
+    }
+    result.push_str("%%\n");
+    let mut clef = "";
+    let mut groups = groups.iter().peekable();
+    for (i, syllable) in file.syllables.iter().enumerate() {
+        if is_clef_only(syllable) {
+            continue;
+        }
+        let group = match groups.peek() {
+            Some(g) if slots[g.slots.start].index == i => groups.next().unwrap(),
+            _ if slots.iter().any(|s| s.index == i) => continue, //merged into the previous group
+            _ => {
+                let music: Vec<&NoteElem> = syllable.music.iter().collect();
+                push_syllable(&mut result, syllable.text, &music, &mut clef);
+                continue;
+            }
+        };
+        let first = &slots[group.slots.start];
+        let mut parts: Vec<Vec<&NoteElem>> = Vec::new();
+        let repeated = repeated_note(first.music);
+        if group.slots.len() > 1 {
+            let mut merged = Vec::new();
+            for slot in &slots[group.slots.clone()] {
+                if !merged.is_empty() && !slot.music.is_empty() {
+                    merged.push(&spacer);
+                }
+                merged.extend(slot.music.iter());
+            }
+            parts.push(merged);
+        } else {
+            let (repeats, split) = split_music(first.music, group.text.len());
+            parts.extend((0..repeats).map(|_| vec![&repeated]));
+            parts.extend(split);
+        }
+        for (s, music) in syllables[group.text.clone()].iter().zip(parts) {
+            let text = if s.word_start { format!(" {}", s.text) } else { s.text.clone() };
+            push_syllable(&mut result, &text, &music, &mut clef);
+        }
+    }
+    result
+}
+
+///Write a syllable, writing a clef before the first note and wherever the clef changes.
+fn push_syllable<'a>(result: &mut String, text: &str, music: &[&NoteElem<'a>], clef: &mut &'a str) {
+    let mut notes = String::new();
+    for elem in music {
+        match elem {
+            NoteElem::Note(n) => {
+                if n.current_clef != *clef && n.current_clef != "no clef set" {
+                    if clef.is_empty() {
+                        result.push_str(&format!("({})", n.current_clef));
+                    } else {
+                        notes.push_str(n.current_clef);
+                        notes.push(' ');
+                    }
+                    *clef = n.current_clef;
+                }
+                notes.push_str(&elem.to_gabc());
+            }
+            NoteElem::Spacer(s) | NoteElem::Barline(s) => notes.push_str(s),
+            NoteElem::Clef(_) => {}
+        }
+    }
+    result.push_str(&format!("{}({})", text, notes));
+}
+
+///Whether a syllable has no text and no music but clefs (which are rewritten as needed).
+fn is_clef_only(syllable: &Syllable) -> bool {
+    syllable.text.trim().is_empty() && syllable.music.iter().all(|e| matches!(e, NoteElem::Clef(_)))
+}
+
+///The first note of a neume without its prefix and suffix, for syllables that have to repeat it.
+fn repeated_note<'a>(music: &[NoteElem<'a>]) -> NoteElem<'a> {
+    match music.iter().find(|e| matches!(e, NoteElem::Note(_))) {
+        Some(NoteElem::Note(n)) => NoteElem::Note(Note {
+            prefix: "",
+            position: n.position,
+            suffix: "",
+            current_clef: n.current_clef,
+            span: n.span.clone(),
+        }),
+        _ => unreachable!("slots always have notes"),
+    }
+}
+
+///Divide a neume between `count` syllables. Each syllable but the last takes one group of notes
+///between spacers (or a single note, if there are too few groups) and the last takes the rest.
+///If there are fewer notes than syllables, the number of syllables left without notes is
+///returned alongside the parts; they should repeat the first note.
+fn split_music<'f, 'a>(music: &'f [NoteElem<'a>], count: usize) -> (usize, Vec<Vec<&'f NoteElem<'a>>>) {
+    //Indexes where a new part may begin
+    let note_starts: Vec<usize> = (1..music.len()).filter(|&i| matches!(music[i], NoteElem::Note(_))).collect();
+    let group_starts: Vec<usize> = note_starts
+        .iter()
+        .cloned()
+        .filter(|&i| matches!(music[i - 1], NoteElem::Spacer(_)))
+        .collect();
+    let starts = if group_starts.len() + 1 >= count { group_starts } else { note_starts };
+    let cuts = starts.len().min(count - 1);
+    let mut bounds = vec![0];
+    bounds.extend(&starts[..cuts]);
+    bounds.push(music.len());
+    let mut parts = Vec::new();
+    for w in bounds.windows(2) {
+        let mut part: Vec<&NoteElem> = music[w[0]..w[1]].iter().collect();
+        //A spacer at a cut is meaningless once the notes are on different syllables
+        if w[1] != music.len() {
+            while let Some(NoteElem::Spacer(_)) = part.last() {
+                part.pop();
+            }
+        }
+        parts.push(part);
+    }
+    (count - cuts - 1, parts)
+}
@@ -0,0 +1,271 @@
+//Copyright (c) 2018 Lydia Simmons
+//This software is licensed under the GNU General Public License v3.0.
+//See the LICENSE file in this distribution for license terms.
+
+//! Volpiano output, as used by the Cantus Index and other chant databases. Volpiano writes the
+//! melody on a treble clef ("1") an octave above the pitch of `GabcFile::as_lilypond()`, so
+//! that e.g. the final of the first mode is `d`. Notes of a neume are written together, neumes
+//! are separated by "-", syllables by "--" and words by "---". Liquescent notes are upper case.
+//! Divisions become "3", and double bars "4". Flats written in the gabc are written before the
+//! note as in the Cantus Volpiano protocol: "w" (low B, `b`), "x" (E, `e`), "i" (B, `j`), "z"
+//! (high E, `m`) or "y" (high B, `q`), so that e.g. a B-flat is "ij". Naturals are the same
+//! letters in upper case.
+//!
+//! `from_volpiano()` reads Volpiano, with the text sung to it, back into gabc.
+
+use events::{events, Event};
+use formatter::{format_gabc, FormatOptions};
+use latin::Word;
+use {choose_clef, clef_offset, GabcFile, Syllabic};
+
+///Volpiano pitch letters in ascending order, starting from A (the staff step above '9').
+pub(crate) static VOLPIANO_PITCHES: &str = "abcdefghjklmnopqrs";
+
+///Volpiano flat signs and the indexes in `VOLPIANO_PITCHES` of the notes they flatten, as in
+///the Cantus Volpiano protocol; naturals are the same letters in upper case.
+static FLATS: [(char, usize); 5] = [('w', 1), ('x', 4), ('i', 8), ('z', 11), ('y', 15)];
+
+///Translate a `GabcFile` into Volpiano, with only the first `syllables` sung syllables if a
+///number is given. A melody too high for Volpiano is written an octave lower.
+///# Examples
+///```
+///# use gabc_parser::*;
+///let f = GabcFile::new("%%
+///(c4) Al(gh)le(j/ixi~)lu(j) ia.(g.) (::)");
+///assert_eq!(f.as_volpiano(), "1---gh--k-iJ--k---g---4");
+///assert_eq!(f.volpiano_incipit(2), "1---gh--k-iJ");
+///```
+pub fn to_volpiano(file: &GabcFile, syllables: Option<usize>) -> String {
+    let lyrics = file.syllable_lyrics();
+    let events = events(file);
+    let high = events.iter().any(|e| matches!(e, Event::Note(n) if n.pitch >= VOLPIANO_PITCHES.len()));
+    let low = events.iter().any(|e| matches!(e, Event::Note(n) if n.pitch < 7));
+    let shift = if high && !low { 7 } else { 0 };
+    let mut result = String::from("1");
+    //Hyphens to write before the next note or division
+    let mut pending = 3;
+    let mut previous_syllable = None;
+    let mut count = 0;
+    for event in events {
+        match event {
+            Event::Note(n) => {
+                let needed = if previous_syllable == Some(n.syllable) {
+                    if n.joined { 0 } else { 1 }
+                } else {
+                    count += 1;
+                    if syllables.is_some_and(|s| count > s) {
+                        break;
+                    }
+                    let continues = previous_syllable.is_some()
+                        && matches!(lyrics[n.syllable], Some((_, Syllabic::Middle)) | Some((_, Syllabic::End)));
+                    if continues { 2 } else { 3 }
+                };
+                previous_syllable = Some(n.syllable);
+                result.push_str(&"-".repeat(needed.max(pending)));
+                pending = 0;
+                //Notes still too high after the shift are written an octave lower on their own
+                let mut pitch = n.pitch - shift;
+                while pitch >= VOLPIANO_PITCHES.len() {
+                    pitch -= 7;
+                }
+                if n.accidental {
+                    if let Some(&(sign, _)) = FLATS.iter().find(|f| f.1 == pitch) {
+                        result.push(if n.alter == 0 { sign.to_ascii_uppercase() } else { sign });
+                    }
+                }
+                let letter = VOLPIANO_PITCHES.chars().nth(pitch).unwrap();
+                result.push(if n.liquescent { letter.to_ascii_uppercase() } else { letter });
+            }
+            Event::Barline(bar) => {
+                if syllables.is_some_and(|s| count >= s) {
+                    break;
+                }
+                if result == "1" {
+                    continue;
+                }
+                result.push_str("---");
+                result.push(if bar == "::" { '4' } else { '3' });
+                pending = 3;
+            }
+        }
+    }
+    result
+}
+
+///A unit of Volpiano music.
+enum Token {
+    ///A note by staff step above '9' (low G), and whether it is liquescent
+    Note(usize, bool),
+    ///A flat (or a natural if true) on a staff step
+    Sign(usize, bool),
+    ///A run of hyphens of the given length
+    Gap(usize),
+    Bar(&'static str),
+}
+
+///A word of music as gabc syllables, or a division between words.
+enum Unit {
+    Word(Vec<String>),
+    Bar(&'static str),
+}
+
+///Read Volpiano and the text sung to it into gabc. The Volpiano must use the treble clef ("1");
+///"-" separates neumes, "--" syllables and "---" words. Flats and naturals are read as in the
+///output of `to_volpiano()`. Barlines ("3", "5" or "6") become ":" and double barlines ("4")
+///"::"; line breaks ("7") and repeated treble clefs are ignored. Any other sign, such as the
+///bass clef ("2"), is an error. The text is divided into words at whitespace and into syllables at hyphens, or by
+///Latin syllabification (see the `latin` module) if it has none; punctuation standing alone
+///goes with the word before it. Each word of text is sung to a
+///word of music: when the two divide into different numbers of syllables, the last syllable of
+///music takes the remaining text or the last syllables of music take none. Text left over after
+///the music is written with empty music. Volpiano does not tell a division inside a melisma
+///from one between words, so every division is taken to end a word. The clef is chosen to centre the melody on the staff,
+///and the gabc is written in canonical form (see the `formatter` module).
+///# Examples
+///```
+///# use gabc_parser::volpiano::*;
+///assert_eq!(from_volpiano("1---gh--k-iJ--k---g---4", "Al-le-lu ia.").unwrap(), "%%
+///(c3) Al(ef)le(h/gxg~)lu(h) ia.(e) (::)
+///");
+/////"Maria" has three syllables but is sung to two
+///assert_eq!(from_volpiano("1---g--h---hj--k---3", "Ave Maria").unwrap(), "%%
+///(c3) A(e)ve(f) Ma(fg)ria(h) (:)
+///");
+///assert!(from_volpiano("1---c--s", "Deus").is_err());
+///assert!(from_volpiano("2---g", "Deus").is_err());
+///assert!(from_volpiano("1---g--2---g", "Deus").is_err());
+///```
+pub fn from_volpiano(volpiano: &str, text: &str) -> Result<String, String> {
+    let tokens = read_volpiano(volpiano)?;
+    let steps: Vec<usize> = tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Note(step, _) | Token::Sign(step, _) => Some(*step),
+            _ => None,
+        })
+        .collect();
+    //Volpiano's low G has no pitch index, so a melody using it is read an octave higher
+    let pitch = |step: usize| if steps.contains(&0) { step + 6 } else { step - 1 };
+    let pitches: Vec<usize> = steps.iter().map(|s| pitch(*s)).collect();
+    let clef = choose_clef(&pitches).ok_or("the range of the melody is too wide for a clef")?;
+    let offset = clef_offset(clef).unwrap();
+    let position = |step: usize| (b'a' + (pitch(step) - offset) as u8) as char;
+    let mut units = Vec::new();
+    let mut word: Vec<String> = Vec::new();
+    let mut syllable = String::new();
+    let mut gap = 0;
+    //Where the neume being read starts in the syllable
+    let mut neume = 0;
+    for token in tokens {
+        match token {
+            Token::Gap(n) => gap = n,
+            Token::Bar(bar) => {
+                finish(&mut units, &mut word, &mut syllable, 3);
+                units.push(Unit::Bar(bar));
+                gap = 0;
+                neume = 0;
+            }
+            Token::Note(step, liquescent) => {
+                finish(&mut units, &mut word, &mut syllable, gap);
+                if gap > 0 {
+                    neume = syllable.len();
+                }
+                gap = 0;
+                syllable.push(position(step));
+                if liquescent {
+                    syllable.push('~');
+                }
+            }
+            Token::Sign(step, natural) => {
+                //Signs are written before the neume they fall in
+                finish(&mut units, &mut word, &mut syllable, gap);
+                if gap > 0 {
+                    neume = syllable.len();
+                }
+                gap = 0;
+                let sign = format!("{}{}", position(step), if natural { 'y' } else { 'x' });
+                syllable.insert_str(neume, &sign);
+            }
+        }
+    }
+    finish(&mut units, &mut word, &mut syllable, 3);
+    let mut text_words: Vec<Vec<String>> = Vec::new();
+    for w in text.split_whitespace() {
+        match text_words.last_mut().and_then(|previous| previous.last_mut()) {
+            //Punctuation standing alone, such as "*", is not sung
+            Some(last) if !w.chars().any(char::is_alphabetic) => last.push_str(&format!(" {}", w)),
+            _ if w.contains('-') => text_words.push(w.split('-').filter(|s| !s.is_empty()).map(String::from).collect()),
+            _ => text_words.push(Word::new(w).syllables),
+        }
+    }
+    let mut text_words = text_words.into_iter();
+    let mut body = format!("({})", clef);
+    for unit in units {
+        match unit {
+            Unit::Bar(bar) => body.push_str(&format!(" ({})", bar)),
+            Unit::Word(music) => {
+                let syllables: Vec<String> = text_words.next().unwrap_or_default();
+                body.push(' ');
+                for (i, m) in music.iter().enumerate() {
+                    let t = if i + 1 == music.len() {
+                        syllables.get(i..).map(|s| s.concat()).unwrap_or_default()
+                    } else {
+                        syllables.get(i).cloned().unwrap_or_default()
+                    };
+                    body.push_str(&format!("{}({})", t.replace(['(', '%'], ""), m));
+                }
+            }
+        }
+    }
+    for w in text_words {
+        body.push_str(&format!(" {}()", w.concat().replace(['(', '%'], "")));
+    }
+    format_gabc(&format!("%%\n{}\n", body), &FormatOptions::default())
+}
+
+///Close the syllable being read if a gap of `gap` hyphens ends it, and the word if the gap ends
+///that too; a single hyphen separates neumes within the syllable.
+fn finish(units: &mut Vec<Unit>, word: &mut Vec<String>, syllable: &mut String, gap: usize) {
+    if gap == 1 && !syllable.is_empty() {
+        syllable.push('/');
+    }
+    if gap >= 2 && !syllable.is_empty() {
+        word.push(std::mem::take(syllable));
+    }
+    if gap >= 3 && !word.is_empty() {
+        units.push(Unit::Word(std::mem::take(word)));
+    }
+}
+
+///Divide Volpiano into tokens.
+fn read_volpiano(volpiano: &str) -> Result<Vec<Token>, String> {
+    let volpiano: String = volpiano.chars().filter(|c| !c.is_whitespace()).collect();
+    let rest = volpiano.strip_prefix('1').ok_or("Volpiano must begin with the treble clef (\"1\")")?;
+    let mut tokens = Vec::new();
+    for c in rest.chars() {
+        let token = match c {
+            '-' => {
+                if let Some(Token::Gap(n)) = tokens.last_mut() {
+                    *n += 1;
+                    continue;
+                }
+                Token::Gap(1)
+            }
+            '9' => Token::Note(0, false),
+            ')' => Token::Note(0, true),
+            '3' | '5' | '6' => Token::Bar(":"),
+            '4' => Token::Bar("::"),
+            '1' | '7' => continue,
+            c => {
+                let lower = c.to_ascii_lowercase();
+                match (FLATS.iter().find(|f| f.0 == lower), VOLPIANO_PITCHES.find(lower)) {
+                    (Some(&(_, i)), _) => Token::Sign(i + 1, c.is_ascii_uppercase()),
+                    (None, Some(i)) => Token::Note(i + 1, c.is_ascii_uppercase()),
+                    (None, None) => return Err(format!("invalid Volpiano character: '{}'", c)),
+                }
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
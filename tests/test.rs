@@ -3,26 +3,29 @@
 //See the LICENSE file in this distribution for license terms.
 
 extern crate gabc_parser;
+extern crate midly;
 extern crate pest;
+#[cfg(feature = "png-pdf")]
+extern crate tiny_skia;
 use gabc_parser::*;
 use pest::Parser;
 
-static FILE: &'static str = "office-part:Tractus;
+static FILE: &str = "office-part:Tractus;
 mode:8;
 %%
 (c3) Pó(eh/hi)pu(h)lus(h) Si(hi)on,(hgh.) *(;) ec(hihi)ce(e.) (::)";
 
-static LYRICS: &'static str = " Pó -- pu -- lus Si -- on, \\set stanza = \" *\"  ec -- ce  ";
+static LYRICS: &str = " Pó -- pu -- lus Si -- on, \\set stanza = \" *\"  ec -- ce  ";
 
-static NOTES: &'static str = "
+static NOTES: &str = "
 g(c' c' d')
 c'
 c'
 c'(d')
-c'(b c')
+c'(b \\augmentum c')
 \\divisioMaior
 c'(d' c' d')
-g
+\\augmentum g
 \\finalis
 ";
 
@@ -62,6 +65,31 @@ fn test_syllable_to_ly() {
     let num_syllable = Syllable::new(" 3. Po(cde)", "c3");
     assert_eq!("e(f g)", num_syllable.ly_notes());
     assert_eq!(" \"3._Po\"", num_syllable.ly_text());
+    //Shapes and rhythmic signs become gregorian.ly commands, and an episema spans its notes
+    let signs = Syllable::new("quod(g_h_/iw!jvIH'ihhg.)", "c4");
+    assert_eq!(
+        "g\\episemInitium(a\\episemFinis \\quilisma b \\virga c' b a\\ictus b a a \\augmentum g)",
+        signs.ly_notes()
+    );
+    let liquescents = Syllable::new("Ky(fo~h<g>)", "c4");
+    assert_eq!("\\oriscus \\deminutum f(\\auctum \\ascendens a \\auctum \\descendens g)", liquescents.ly_notes());
+}
+
+#[test]
+fn test_lilypond_vaticana() {
+    let f = GabcFile::new("name:Signs;\n%%\n(c4) quod(g_h_/iw!jvIH'ihhg.) Ky(fo~h<g>) (::)");
+    let vaticana = f.as_lilypond_vaticana();
+    assert!(vaticana.starts_with("\\include \"gregorian.ly\""));
+    assert!(vaticana.contains("\\new VaticanaStaff <<"));
+    assert!(vaticana.contains("\\new VaticanaVoice = \"melody\" \\chant"));
+    assert!(vaticana.contains(&f.ly_notes()));
+    assert!(vaticana.contains("\\quilisma b \\virga c'"));
+    assert!(vaticana.contains("\\oriscus \\deminutum f(\\auctum \\ascendens a"));
+    //The music and lyrics are those of the modern transcription
+    let modern = f.as_lilypond();
+    let score = |ly: &str| ly.find("\\score").unwrap();
+    assert_eq!(vaticana[..score(&vaticana)], modern[..score(&modern)]);
+    assert!(!modern.contains("Vaticana"));
 }
 
 #[test]
@@ -83,3 +111,1199 @@ fn test_raw_parsing() {
     let bad_note = GABCParser::parse(Rule::note, "this is not a note");
     assert!(bad_note.is_err());
 }
+
+#[test]
+fn test_melodic_search() {
+    use gabc_parser::search::*;
+    let c3 = GabcFile::new(FILE);
+    let c4 = GabcFile::new("%%\n(c4) Pó(fi/ij)pu(i)lus(i) (::)");
+    let files = [c3, c4];
+    let query = MelodicQuery::from_gabc("ehhi").unwrap();
+    let matches = search(&files, &query, &SearchOptions::default());
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].syllables, 1..2);
+    assert_eq!(&FILE[matches[0].span.clone()], "eh/hi");
+    assert_eq!(matches[1].file, 1);
+    let collapsed = MelodicQuery::from_intervals(&[3, 1, -1]);
+    assert!(search(&files, &collapsed, &SearchOptions::default()).is_empty());
+    let options = SearchOptions {
+        ignore_repeated_notes: true,
+        ..SearchOptions::default()
+    };
+    let matches = search(&files, &collapsed, &options);
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].syllables, 1..5);
+    let dotted = MelodicQuery::from_gabc("hgh").unwrap();
+    assert_eq!(search(&files, &dotted, &SearchOptions::default()).len(), 1);
+    let options = SearchOptions {
+        ignore_rhythmic_signs: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(search(&files, &dotted, &options).len(), 2);
+}
+
+#[test]
+fn test_ngram_index() {
+    use gabc_parser::similarity::*;
+    let index = NGramIndex::from_directory("examples", 4).unwrap();
+    assert_eq!(index.len(), 5);
+    let error = NGramIndex::from_directory("examples", 0).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    let path = std::env::temp_dir().join("gabc_parser_test_index.json");
+    index.save(&path).unwrap();
+    let loaded = NGramIndex::load(&path).unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("\"n\":4"));
+    std::fs::write(&path, saved.replace("\"n\":4", "\"n\":0")).unwrap();
+    let error = NGramIndex::load(&path).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+    let text = std::fs::read_to_string("examples/populus_sion.gabc").unwrap();
+    let populus = GabcFile::new(&text);
+    let nearest = loaded.nearest(&populus, 2);
+    assert_eq!(nearest[0].name, "populus_sion.gabc");
+    assert_eq!(nearest[0].score, 1.0);
+    assert!(nearest[1].score < 1.0);
+    let g = GabcFile::new(FILE);
+    let variant_text = FILE.replace("(hgh.)", "(hg.)");
+    let variant = GabcFile::new(&variant_text);
+    assert_eq!(similarity(&g, &g), 1.0);
+    assert!(similarity(&g, &variant) > 0.5);
+    assert_eq!(pitch_edit_distance(&g, &variant), 1);
+}
+
+#[test]
+fn test_psalm_tone() {
+    use gabc_parser::psalm::*;
+    let tone = tone(8).unwrap();
+    let verses = [
+        Verse::from_pointed("Di-xit Dó-mi-nus Dó-mi-no mé-o: * Se-de a dex-tris mé-is.").unwrap(),
+        Verse::from_pointed("Quó-ni-am con-fir-má-ta est † su-per nos mi-se-ri-cór-di-a é-jus: * et vé-ri-tas Dó-mi-ni má-net in ae-tér-num.").unwrap(),
+    ];
+    let gabc = tone.psalm_gabc(&verses, "G", false).unwrap();
+    assert_eq!(
+        gabc,
+        "mode:8;
+annotation:8 G;
+%%
+(c4) Di(g)xit(h) Dó(j)mi(j)nus(j) Dó(j)mi(j)no(j) mé(k)o:(j) *(:) Se(j)de(j) a(j) dex(h)tris(j) mé(h)is.(g) (::)
+Quó(j)ni(j)am(j) con(j)fir(j)má(j)ta(h) est(h) †(,) su(j)per(j) nos(j) mi(j)se(j)ri(j)cór(j)di(j)a(j) é(k)jus:(j) *(:) et(j) vé(j)ri(j)tas(j) Dó(j)mi(j)ni(j) má(j)net(j) in(h) ae(j)tér(h)num.(g) (::)
+"
+    );
+    let f = GabcFile::new(&gabc);
+    assert_eq!(f.syllables.iter().filter(|s| s.text.trim() == "*").count(), 2);
+    assert!(Verse::from_pointed("no mediant here").is_err());
+    assert!(tone.psalm_gabc(&verses, "Z", false).is_err());
+    let unpointed = [Verse::from_pointed("Di-xit Do-mi-nus: * se-de.").unwrap()];
+    assert!(tone.psalm_gabc(&unpointed, "G", false).is_err());
+    //A cadence with no accents cannot be sung
+    let mut no_accents = tone.clone();
+    no_accents.mediant = Cadence::new(&["g"], &[]);
+    assert!(no_accents.psalm_gabc(&verses, "G", false).is_err());
+}
+
+#[test]
+fn test_latin_syllabification() {
+    use gabc_parser::latin::*;
+    let text = std::fs::read_to_string("examples/hy--ut_queant_laxis--solesmes.gabc").unwrap();
+    let hymn = GabcFile::new(&text);
+    let expected: Vec<&str> = hymn.syllables[1..13]
+        .iter()
+        .map(|s| s.text.trim())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let syllables = gabc_syllables("UT queant laxis resonáre fibris");
+    assert_eq!(syllables.iter().map(|s| s.trim()).collect::<Vec<_>>(), expected);
+    assert_eq!(syllables[1], " que");
+    let words = words("Mira gestórum famuli tuorum, alleluia.");
+    let accents: Vec<Option<usize>> = words.iter().map(|w| w.accent).collect();
+    assert_eq!(accents, vec![Some(0), Some(1), Some(0), Some(1), Some(2)]);
+    assert_eq!(words[4].syllables, vec!["al", "le", "lu", "ia."]);
+    assert_eq!(Word::new("*").accent, None);
+    assert_eq!(Word::new("et").accent, None);
+    for monosyllable in ["cui", "Huic,"] {
+        let w = Word::new(monosyllable);
+        assert_eq!(w.syllables, vec![monosyllable]);
+        assert_eq!(w.accent, None);
+    }
+    assert_eq!(Word::new("fluit").syllables, vec!["flu", "it"]);
+}
+
+#[test]
+fn test_words_and_text() {
+    let text = std::fs::read_to_string("examples/populus_sion.gabc").unwrap();
+    let populus = GabcFile::new(&text);
+    assert!(populus.text().starts_with("Pópulus Sion, ecce Dóminus véniet "));
+    let rubrics: Vec<String> = populus.words().into_iter().filter(|w| w.rubric).map(|w| w.text).collect();
+    assert_eq!(rubrics, vec!["*", "Ps.", ":*"]);
+    assert!(populus.text().contains(" vocis suæ, in lætítia cordis vestri. Qui "));
+    let text = std::fs::read_to_string("examples/tr--ab_ortu_solis--solesmes.gabc").unwrap();
+    let tract = GabcFile::new(&text);
+    let words = tract.words();
+    let verse = words.iter().position(|w| w.text == "℣.").unwrap();
+    assert!(words[verse].rubric);
+    assert_eq!(words[verse + 1].text, "Et");
+    assert_eq!(&text[words[verse + 1].span.clone()], " <sp>V/</sp>. {E}t(g)");
+    let text = std::fs::read_to_string("examples/an--salve_regina_simple_tone--solesmes.gabc").unwrap();
+    let salve = GabcFile::new(&text);
+    assert!(salve.text().contains(" misericórdiæ: Víta,"));
+}
+
+#[test]
+fn test_underlay() {
+    use gabc_parser::latin::words;
+    use gabc_parser::underlay::*;
+    let f = GabcFile::new(FILE);
+    //Merging keeps every note and neume, and words are not carried across the rubric
+    let u = underlay(&f, &words("Ecce venit Rex"), Strategy::Sequential).unwrap();
+    assert!(u.gabc.ends_with("(c3) Ec(eh/hi)ce(h/h) ve(hi)nit(hgh.) *(;) Rex(hihi/e.) (::)"));
+    assert!(u.ambiguities.iter().all(|a| a.kind == AmbiguityKind::MergedNeumes));
+    assert_eq!(u.ambiguities[1].original, 7..9);
+    let new = GabcFile::new(&u.gabc);
+    assert_eq!(new.attributes, f.attributes);
+    let pitches = |f: &GabcFile| -> Vec<String> {
+        f.syllables.iter().flat_map(|s| s.music.iter()).filter(|e| !matches!(e, NoteElem::Spacer(_))).map(|e| e.to_ly().to_string()).collect()
+    };
+    assert_eq!(pitches(&new), pitches(&f));
+    let u = underlay(&f, &words("Laudate Dominum omnes gentes"), Strategy::AccentAware).unwrap();
+    assert!(u.gabc.ends_with("(c3) Lau(eh)da(hi)te(h) Do(h)mi(h)num(i) om(h)nes(gh.) *(;) gen(hihi)tes(e.) (::)"));
+    let splits = u.ambiguities.iter().filter(|a| a.kind == AmbiguityKind::SplitNeume).count();
+    assert_eq!(splits, 3);
+    //Clef changes are written before the first note they govern
+    let f = GabcFile::new("%%\n(c4) A(g)men.(hg) (::c3) Al(h)le(ij)lu(i)ia.(hi) (::)");
+    let u = underlay(&f, &words("Gloria tibi Domine"), Strategy::AccentAware).unwrap();
+    assert_eq!(u.gabc, "%%\n(c4) Glo(g)ri(h)a(g) (::) ti(c3 h)bi(ij) Do(i)mi(h)ne(i) (::)");
+    assert_eq!(u.ambiguities[1].kind, AmbiguityKind::AccentMismatch);
+    assert_eq!(GabcFile::new(&u.gabc).syllables[5].music[0], NoteElem::Clef("c3"));
+    assert!(underlay(&f, &words(""), Strategy::Sequential).is_err());
+    assert!(underlay(&f, &words(&"a ".repeat(30)), Strategy::Sequential).is_err());
+}
+
+#[test]
+fn test_gabc_round_trip() {
+    let mut paths: Vec<_> = std::fs::read_dir("examples")
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "gabc"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let text = std::fs::read_to_string(&path).unwrap();
+        let file = GabcFile::new(&text);
+        let gabc = file.to_gabc();
+        assert_eq!(GabcFile::new(&gabc), file, "{} does not round-trip", path.display());
+        assert_eq!(GabcFile::new(&gabc).to_gabc(), gabc);
+    }
+    let f = GabcFile::new(FILE);
+    assert_eq!(f.syllables[0].music, vec![NoteElem::Clef("c3")]);
+    assert_eq!(f.to_gabc(), format!("{}\n", FILE));
+    //Comments, blank lines and CRLF line endings are layout, not content
+    let laid_out = "% transcribed from the Liber\r\nname:Test;\r\n\r\n%%\r\n% verse 1\r\n(c4) Al(gh/h.)\r\n\r\nle(j) % end\r\n(::)\r\n";
+    let file = GabcFile::new(laid_out);
+    let gabc = file.to_gabc();
+    assert!(!gabc.contains("verse 1") && !gabc.contains("end"));
+    assert_eq!(GabcFile::new(&gabc), file);
+    assert_ne!(GabcFile::new(&gabc).syllables[1].span, file.syllables[1].span);
+}
+
+#[test]
+fn test_cst() {
+    use gabc_parser::cst::*;
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let cst = Cst::parse(&text).unwrap();
+        assert_eq!(cst.to_string(), text);
+        assert_eq!(cst.gabc_file(), GabcFile::new(&text));
+        assert_eq!(cst.syllables().len(), cst.gabc_file().syllables.len());
+    }
+    //Comments, blank lines, trailing spaces and CRLF line endings are kept
+    let text = "% header comment\r\nname:Test; \r\n\r\nmode:8;\r\n%%\r\n(c4) A(g)men.(hg) % body comment\r\n(::)\r\n";
+    let mut cst = Cst::parse(text).unwrap();
+    assert_eq!(cst.to_string(), text);
+    let comments: Vec<&str> = cst
+        .root()
+        .leaves()
+        .into_iter()
+        .filter(|n| n.rule() == Some(Rule::line_comment))
+        .map(|n| n.text(cst.source()))
+        .collect();
+    assert_eq!(comments, vec!["% header comment", "% body comment"]);
+    assert_eq!(cst.gabc_file().attributes, vec![("name", "Test"), ("mode", "8")]);
+    //A targeted edit only touches its own range
+    let range = cst.replace_syllable(2, "men(h)").unwrap();
+    assert_eq!(&cst.source()[..range.start], &text[..range.start]);
+    assert_eq!(&cst.source()[range.end..], &text[range.start + "men.(hg)".len()..]);
+    assert_eq!(cst.gabc_file().syllables[2].music.len(), 1);
+    assert!(cst.replace_syllable(2, "men(h) A(g)").is_err());
+    assert!(cst.replace_syllable(2, "men(h").is_err());
+    assert!(cst.replace_syllable(9, "men(h)").is_err());
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = 5..2;
+    assert!(cst.replace(reversed, "").is_err());
+    assert!(cst.replace(0..text.len() + 1, "").is_err());
+    assert_eq!(Cst::parse(cst.source()).unwrap(), cst);
+    cst.set_attribute("mode", "1").unwrap();
+    assert!(cst.source().starts_with("% header comment\r\nname:Test; \r\n\r\nmode:1;\r\n%%"));
+    assert!(Cst::parse("name:Test;\n(c4) A(g)").is_err());
+}
+
+#[test]
+fn test_formatter() {
+    use gabc_parser::formatter::*;
+    let options = FormatOptions {
+        break_after: vec!["::".to_string()],
+        max_width: 60,
+        ..FormatOptions::default()
+    };
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let original = GabcFile::new(&text);
+        let formatted = format_gabc(&text, &options).unwrap();
+        assert!(is_formatted(&formatted, &options).unwrap(), "{} is not idempotent", path.display());
+        let reparsed = GabcFile::new(&formatted);
+        assert_eq!(reparsed.words(), original.words().into_iter().map(|mut w| {
+            w.span = reparsed.syllables[w.syllables.start].span.start..reparsed.syllables[w.syllables.end - 1].span.end;
+            w
+        }).collect::<Vec<_>>());
+        let music = |f: &GabcFile| -> Vec<String> { f.syllables.iter().map(|s| s.music.iter().map(|e| e.to_gabc()).collect()).collect() };
+        assert_eq!(music(&reparsed), music(&original));
+        //Only a single long word may exceed the width
+        for line in formatted.lines().skip_while(|l| *l != "%%").skip(1) {
+            let mut depth = 0;
+            let word_breaks = line.chars().filter(|&c| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                c == ' ' && depth == 0
+            });
+            assert!(line.chars().count() <= 60 || word_breaks.count() == 0, "{}", line);
+        }
+    }
+    let formatted = format_gabc("name:Test;\n%%\n(c4) A(g)men.(hg) (;) A(g)men.(hg) (::)", &options).unwrap();
+    assert_eq!(formatted.lines().count(), 3);
+    let options = FormatOptions {
+        break_after: vec![";".to_string()],
+        ..options
+    };
+    let formatted = format_gabc("name:Test;\n%%\n(c4) A(g)men.(hg) (;) A(g)men.(hg) (::)", &options).unwrap();
+    assert_eq!(formatted, "name: Test;\n%%\n(c4) A(g)men.(hg) (;)\nA(g)men.(hg) (::)\n");
+    //Comments are kept, wherever they are
+    let commented = "% header\nmode:8;% mode\nname:Test;\n% before the body\n%%\n% first line\n(c4) A(g)men.(hg) (;) % half bar\nA(g)%in a word\nmen.(hg) (::)  % final\n% last line";
+    let formatted = format_gabc(commented, &options).unwrap();
+    assert_eq!(formatted, "name: Test;\n% header\nmode: 8; % mode\n% before the body\n%%\n% first line\n(c4) A(g)men.(hg) (;) % half bar\nA(g)%in a word\nmen.(hg) (::)% final\n% last line\n");
+    assert!(is_formatted(&formatted, &options).unwrap());
+    //A space before a comment makes its line break layout, so "(;) % half bar\nA(g)" is one word
+    let words = |f: &GabcFile| f.words().into_iter().map(|w| w.text).collect::<Vec<_>>();
+    assert_eq!(words(&GabcFile::new(&formatted)), words(&GabcFile::new(commented)));
+    assert_eq!(words(&GabcFile::new(commented)), vec!["Amen.", "A", "men."]);
+}
+
+///Partial content models of the MusicXML 4.0 elements written by `as_musicxml()`, copied by hand
+///from the MusicXML XSD: each element's children in schema order. Children in the same group are
+///an unordered choice. This is a quick structural check of the output that runs without the
+///schema: elements and attributes missing from this table are not checked. Validation against
+///the XSD itself is `test_musicxml_xsd()`.
+static MUSICXML_CONTENT: &[(&str, &[&[&str]])] = &[
+    ("score-partwise", &[&["work"], &["movement-number"], &["movement-title"], &["identification"], &["defaults"], &["credit"], &["part-list"], &["part"]]),
+    ("work", &[&["work-number"], &["work-title"], &["opus"]]),
+    ("identification", &[&["creator"], &["rights"], &["encoding"], &["source"], &["relation"], &["miscellaneous"]]),
+    ("encoding", &[&["encoding-date", "encoder", "software", "encoding-description", "supports"]]),
+    ("miscellaneous", &[&["miscellaneous-field"]]),
+    ("part-list", &[&["part-group", "score-part"]]),
+    ("score-part", &[&["identification"], &["part-link"], &["part-name"], &["part-name-display"], &["part-abbreviation"]]),
+    ("part", &[&["measure"]]),
+    ("measure", &[&["note", "backup", "forward", "direction", "attributes", "harmony", "figured-bass", "print", "sound", "listening", "barline", "grouping", "link", "bookmark"]]),
+    ("attributes", &[&["footnote"], &["level"], &["divisions"], &["key"], &["time"], &["staves"], &["part-symbol"], &["instruments"], &["clef"], &["staff-details"], &["transpose"]]),
+    ("key", &[&["cancel"], &["fifths"], &["mode"]]),
+    ("time", &[&["senza-misura", "beats", "beat-type"]]),
+    ("clef", &[&["sign"], &["line"], &["clef-octave-change"]]),
+    ("note", &[&["grace"], &["cue"], &["chord"], &["pitch", "unpitched", "rest"], &["duration"], &["tie"], &["instrument"], &["voice"], &["type"], &["dot"], &["accidental"], &["time-modification"], &["stem"], &["notehead"], &["notehead-text"], &["staff"], &["beam"], &["notations"], &["lyric"], &["play"], &["listen"]]),
+    ("pitch", &[&["step"], &["alter"], &["octave"]]),
+    ("notations", &[&["tied", "slur", "tuplet", "glissando", "slide", "ornaments", "technical", "articulations", "dynamics", "fermata", "arpeggiate", "non-arpeggiate", "accidental-mark", "other-notation"]]),
+    ("articulations", &[&["accent", "strong-accent", "staccato", "tenuto", "detached-legato", "staccatissimo", "spiccato", "scoop", "plop", "doit", "falloff", "breath-mark", "caesura", "stress", "unstress", "soft-accent", "other-articulation"]]),
+    ("lyric", &[&["syllabic"], &["text"], &["extend"], &["end-line"], &["end-paragraph"]]),
+    ("barline", &[&["bar-style"], &["footnote"], &["level"], &["wavy-line"], &["segno"], &["coda"], &["fermata"], &["ending"], &["repeat"]]),
+];
+
+///Parse an XML document that may have a DOCTYPE declaration.
+fn parse_xml(xml: &str) -> roxmltree::Document<'_> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    roxmltree::Document::parse_with_options(xml, options).unwrap()
+}
+
+///Check a MusicXML document against `MUSICXML_CONTENT` and the enumerations of the values used.
+fn check_musicxml_structure(xml: &str) {
+    let document = parse_xml(xml);
+    let root = document.root_element();
+    assert_eq!(root.tag_name().name(), "score-partwise");
+    assert_eq!(root.attribute("version"), Some("4.0"));
+    for node in root.descendants().filter(|n| n.is_element()) {
+        let name = node.tag_name().name();
+        if let Some((_, model)) = MUSICXML_CONTENT.iter().find(|(n, _)| *n == name) {
+            let mut position = 0;
+            for child in node.children().filter(|n| n.is_element()) {
+                let child_name = child.tag_name().name();
+                let p = model.iter().position(|group| group.contains(&child_name));
+                let p = p.unwrap_or_else(|| panic!("<{}> may not contain <{}>", name, child_name));
+                assert!(p >= position, "<{}> out of order in <{}>", child_name, name);
+                position = p;
+            }
+        }
+        let text = node.text().unwrap_or("");
+        let allowed: &[&str] = match name {
+            "step" => &["A", "B", "C", "D", "E", "F", "G"],
+            "type" => &["eighth", "quarter"],
+            "stem" => &["none"],
+            "syllabic" => &["single", "begin", "middle", "end"],
+            "accidental" => &["flat", "sharp", "natural"],
+            "bar-style" => &["regular", "dotted", "dashed", "heavy", "light-light", "light-heavy", "heavy-light", "heavy-heavy", "tick", "short", "none"],
+            _ => continue,
+        };
+        assert!(allowed.contains(&text), "invalid <{}> value {}", name, text);
+    }
+    let ids: Vec<&str> = root.descendants().filter(|n| n.has_tag_name("score-part")).map(|n| n.attribute("id").unwrap()).collect();
+    for part in root.children().filter(|n| n.has_tag_name("part")) {
+        assert!(ids.contains(&part.attribute("id").unwrap()));
+        for (i, measure) in part.children().filter(|n| n.is_element()).enumerate() {
+            assert_eq!(measure.attribute("number"), Some((i + 1).to_string().as_str()));
+        }
+    }
+}
+
+#[test]
+fn test_musicxml() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        check_musicxml_structure(&GabcFile::new(&text).as_musicxml());
+    }
+    let f = GabcFile::new(FILE);
+    let xml = f.as_musicxml();
+    check_musicxml_structure(&xml);
+    let document = parse_xml(&xml);
+    let notes: Vec<_> = document.descendants().filter(|n| n.has_tag_name("note")).collect();
+    assert_eq!(notes.len(), 16);
+    //Pó(eh/hi) starts on g', as in the Lilypond output, with a slur over the syllable
+    let first = notes[0];
+    let child = |n: roxmltree::Node, name: &str| n.descendants().find(|c| c.has_tag_name(name)).and_then(|c| c.text()).map(|t| t.to_string());
+    assert_eq!(child(first, "step").as_deref(), Some("G"));
+    assert_eq!(child(first, "octave").as_deref(), Some("4"));
+    assert_eq!(child(first, "text").as_deref(), Some("Pó"));
+    assert_eq!(child(first, "syllabic").as_deref(), Some("begin"));
+    assert!(first.descendants().any(|c| c.has_tag_name("slur") && c.attribute("type") == Some("start")));
+    assert!(notes[3].descendants().any(|c| c.has_tag_name("slur") && c.attribute("type") == Some("stop")));
+    assert_eq!(child(notes[10], "type").as_deref(), Some("quarter"));
+    assert_eq!(child(notes[9], "type").as_deref(), Some("eighth"));
+    //"(;)" and "(::)" end measures
+    assert_eq!(document.descendants().filter(|n| n.has_tag_name("measure")).count(), 2);
+    assert!(xml.contains("<miscellaneous-field name=\"mode\">8</miscellaneous-field>"));
+    //A flat sign alters the following notes at its pitch until the next barline
+    let flat = GabcFile::new("%%\n(c4) A(ixhi)men(i) (,) al(ihi) (:) ia(i)\n").as_musicxml();
+    check_musicxml_structure(&flat);
+    assert_eq!(flat.matches("<alter>-1</alter>").count(), 2);
+    assert_eq!(flat.matches("<accidental>flat</accidental>").count(), 1);
+    assert_eq!(flat.matches("<breath-mark/>").count(), 1);
+}
+
+///Validate the MusicXML output of every example against the MusicXML 4.0 XSD with
+///`xmllint --schema`, offline. The schema files from the MusicXML 4.0 release (musicxml.xsd,
+///and the xlink.xsd and xml.xsd it imports) belong in tests/musicxml/; until they are committed
+///there, this test is ignored.
+#[test]
+#[ignore = "needs the MusicXML 4.0 XSD in tests/musicxml/, which is not yet vendored"]
+fn test_musicxml_xsd() {
+    let mut documents = vec![GabcFile::new(FILE).as_musicxml()];
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "gabc") {
+            documents.push(GabcFile::new(&std::fs::read_to_string(&path).unwrap()).as_musicxml());
+        }
+    }
+    let path = std::env::temp_dir().join("gabc_parser_test.musicxml");
+    for xml in documents {
+        std::fs::write(&path, &xml).unwrap();
+        let output = std::process::Command::new("xmllint")
+            .args(["--noout", "--nonet", "--schema", "tests/musicxml/musicxml.xsd"])
+            .arg(&path)
+            .output()
+            .expect("xmllint is needed to validate MusicXML");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_musicxml_import() {
+    //Pitches, rhythm and text survive a round trip through MusicXML
+    fn notes(f: &GabcFile) -> Vec<(Option<usize>, bool)> {
+        f.syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter_map(|e| match e {
+                NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#']) => Some((n.pitch_index(), n.suffix.contains('.'))),
+                _ => None,
+            })
+            .collect()
+    }
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let gabc = musicxml::from_musicxml(&f.as_musicxml()).unwrap();
+        let g = GabcFile::new(&gabc);
+        assert_eq!(notes(&g), notes(&f));
+        let lyrics = |f: &GabcFile| f.syllable_lyrics().into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(lyrics(&g), lyrics(&f));
+        let attributes = |f: &GabcFile| {
+            let mut a: Vec<_> = f.attributes.iter().map(|(k, v)| (k.trim().to_string(), v.trim().to_string())).collect();
+            a.sort();
+            a
+        };
+        assert_eq!(attributes(&g), attributes(&f));
+        assert_eq!(musicxml::from_musicxml(&g.as_musicxml()).unwrap(), gabc);
+    }
+    //As exported from MuseScore: measures in 4/4, a tie, an elision and a melisma without slurs
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+  <movement-title>Kyrie</movement-title>
+  <part-list><score-part id="P1"><part-name>Voice</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>4</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <direction><direction-type><words>Lento</words></direction-type></direction>
+      <note><pitch><step>F</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>eighth</type>
+        <lyric number="1"><syllabic>begin</syllabic><text>Ky</text></lyric></note>
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>eighth</type>
+        <lyric number="1"><syllabic>end</syllabic><text>ri</text><elision/><text>e</text></lyric></note>
+      <note><pitch><step>A</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>eighth</type></note>
+      <note><rest/><duration>2</duration><voice>1</voice></note>
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>4</duration><voice>1</voice><type>quarter</type>
+        <notations><tied type="start"/></notations>
+        <lyric number="1"><syllabic>single</syllabic><text>e</text></lyric></note>
+    </measure>
+    <measure number="2">
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>eighth</type>
+        <notations><tied type="stop"/></notations></note>
+      <note><pitch><step>A</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>eighth</type>
+        <notations><articulations><breath-mark/></articulations></notations>
+        <lyric number="1"><syllabic>single</syllabic><text>lei</text></lyric></note>
+      <barline location="right"><bar-style>light-heavy</bar-style></barline>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+    let gabc = musicxml::from_musicxml(xml).unwrap();
+    assert_eq!(gabc, "name: Kyrie;\n%%\n(c4) Ky(f)ri e(g/h) e(ixi.) lei(h,) (::)\n");
+    let chord = xml.replace("<note><rest/>", "<note><chord/><rest/>");
+    assert!(musicxml::from_musicxml(&chord).is_err());
+    let voices = xml.replace("<voice>1</voice><type>quarter", "<voice>2</voice><type>quarter");
+    assert!(musicxml::from_musicxml(&voices).is_err());
+}
+
+#[test]
+fn test_mei() {
+    let ns = "http://www.music-encoding.org/ns/mei";
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let mei = f.as_mei();
+        let document = parse_xml(&mei);
+        let root = document.root_element();
+        assert_eq!(root.tag_name().namespace(), Some(ns));
+        assert_eq!(root.attribute("meiversion"), Some("5.0+Neumes"));
+        let layer = document.descendants().find(|n| n.has_tag_name("layer")).unwrap();
+        for child in layer.children().filter(|n| n.is_element()) {
+            assert!(["syllable", "clef", "divLine"].contains(&child.tag_name().name()));
+        }
+        for syllable in layer.children().filter(|n| n.has_tag_name("syllable")) {
+            for child in syllable.children().filter(|n| n.is_element()) {
+                assert!(["syl", "neume", "accid"].contains(&child.tag_name().name()));
+            }
+        }
+        for neume in document.descendants().filter(|n| n.has_tag_name("neume")) {
+            assert!(neume.children().filter(|n| n.is_element()).all(|n| n.has_tag_name("nc")));
+        }
+        //Every note that is not an accidental sign is a neume component, two for a bivirga
+        let notes: usize = f
+            .syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .map(|e| match e {
+                NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#']) => n.suffix.matches('v').count().max(n.suffix.matches('s').count()).max(1),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(document.descendants().filter(|n| n.has_tag_name("nc")).count(), notes);
+    }
+    let f = GabcFile::new(
+        "name:Test;\ntranscriber:A & B;\nbook:Graduale;\nmode:8;\n%%\n(c4) Ky(ixhi/jo~)ri(Ivs)e(e@f!gwh) (;) (f3) e(h) (::)\n",
+    );
+    let mei = f.as_mei();
+    let document = parse_xml(&mei);
+    let text = |name: &str| document.descendants().find(|n| n.has_tag_name(name)).and_then(|n| n.text());
+    assert_eq!(text("persName"), Some("A & B"));
+    assert_eq!(text("mode"), Some("8"));
+    assert!(mei.contains("<bibl>\n            <title>Graduale</title>"));
+    assert!(mei.contains("<clef shape=\"F\" line=\"3\"/>"));
+    assert!(mei.contains("<accid accid=\"f\" ploc=\"b\" oloc=\"3\"/>"));
+    assert!(mei.contains("<oriscus/><liquescent/>") || mei.contains("<liquescent/><oriscus/>"));
+    assert!(mei.contains("tilt=\"se\""));
+    assert!(mei.contains("<strophicus/>"));
+    assert!(mei.contains("<quilisma/>"));
+    assert_eq!(document.descendants().filter(|n| n.has_tag_name("neume")).count(), 5);
+    assert_eq!(document.descendants().filter(|n| n.has_tag_name("divLine")).count(), 2);
+    let syls: Vec<_> = document.descendants().filter(|n| n.has_tag_name("syl")).map(|n| n.attribute("wordpos")).collect();
+    assert_eq!(syls, vec![Some("i"), Some("m"), Some("t"), None]);
+}
+
+#[test]
+fn test_mei_import() {
+    //Pitch, shape and text survive a round trip through MEI
+    fn notes(f: &GabcFile) -> Vec<(Option<usize>, bool, String)> {
+        let shape = |c: char| match c {
+            'V' => Some('v'),
+            'v' | 'o' | 'w' | 's' | '~' | '<' | '>' | 'x' | 'y' | '#' => Some(c),
+            _ => None,
+        };
+        f.syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter_map(|e| match e {
+                NoteElem::Note(n) => {
+                    let mut suffix: String = n.suffix.chars().filter_map(shape).collect();
+                    if let Some(sign) = suffix.chars().find(|c| matches!(c, 'x' | 'y' | '#')) {
+                        suffix = sign.to_string();
+                    }
+                    //A punctum inclinatum cannot also be a virga in MEI
+                    if n.position.is_uppercase() {
+                        suffix.retain(|c| c != 'v');
+                    }
+                    //Each note of a bivirga or distropha becomes a note of its own
+                    let repeats = suffix.matches('v').count().max(suffix.matches('s').count()).max(1);
+                    suffix = suffix.replace("vv", "v").replace("vv", "v").replace("ss", "s").replace("ss", "s");
+                    Some(vec![(n.pitch_index(), n.position.is_uppercase(), suffix); repeats])
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let import = mei::from_mei(&f.as_mei()).unwrap();
+        assert!(import.unsupported.is_empty(), "{:?}", import.unsupported);
+        let g = GabcFile::new(&import.gabc);
+        assert_eq!(notes(&g), notes(&f));
+        let lyrics = |f: &GabcFile| f.syllable_lyrics().into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(lyrics(&g), lyrics(&f));
+        assert_eq!(g.attributes[0].0, "name");
+    }
+    //An MEI 4 document in the style of Neon, with layout and constructs that gabc lacks
+    let mei = r#"<?xml version="1.0" encoding="UTF-8"?>
+<mei xmlns="http://www.music-encoding.org/ns/mei" meiversion="4.0.1">
+  <meiHead><fileDesc><titleStmt><title>Salve: Regina</title></titleStmt><pubStmt/></fileDesc></meiHead>
+  <music>
+    <facsimile><surface><zone xml:id="z1" ulx="0" uly="0" lrx="10" lry="10"/></surface></facsimile>
+    <body><mdiv><score>
+      <scoreDef><staffGrp><staffDef n="1" lines="4" notationtype="neume" clef.shape="F" clef.line="3"/></staffGrp></scoreDef>
+      <section><staff n="1" facs="z1"><layer n="1">
+        <sb n="1"/>
+        <syllable><syl wordpos="i" con="d">Sal</syl><neume><nc pname="f" oct="4"/><nc pname="g" oct="4" tilt="n"/></neume></syllable>
+        <syllable><syl wordpos="t">ve</syl><neume><nc pname="a" oct="4"><liquescent/></nc></neume><neume><nc pname="g" oct="4" tilt="se" con="g"/><nc pname="f" oct="4" tilt="se"/></neume></syllable>
+        <custos pname="a" oct="3"/>
+        <sb n="2"/>
+        <clef shape="C" line="4"/>
+        <division form="maior"/>
+        <syllable><syl>Re</syl><accid accid="f" ploc="b" oloc="3"/><neume><nc pname="b" oct="3"/><nc pname="c" oct="5"/></neume></syllable>
+      </layer></staff></section>
+    </score></mdiv></body>
+  </music>
+</mei>"#;
+    let import = mei::from_mei(mei).unwrap();
+    assert_eq!(import.gabc, "name: Salve, Regina;\n%%\n(f3) Sal(hiv)ve(j~/IH) (c4) (:)\nRe(ixi)\n");
+    assert_eq!(
+        import.unsupported,
+        vec![
+            ("<sb>".to_string(), 2),
+            ("<nc con>".to_string(), 1),
+            ("<custos>".to_string(), 1),
+            ("<nc> outside the staff".to_string(), 1)
+        ]
+    );
+    //A clef given as an element of the staff definition, and the direction of liquescents
+    let mei = r#"<mei xmlns="http://www.music-encoding.org/ns/mei"><music><body><mdiv><score>
+      <scoreDef><staffGrp><staffDef n="1" lines="4"><clef shape="C" line="3"/></staffDef></staffGrp></scoreDef>
+      <section><staff n="1"><layer n="1">
+        <syllable><syl>A</syl><neume><nc pname="c" oct="4"><liquescent curve="a"/></nc></neume></syllable>
+        <syllable><syl>men</syl><neume><nc pname="d" oct="4"><liquescent curve="c"/></nc><nc pname="e" oct="4"><liquescent curve="x"/></nc></neume></syllable>
+      </layer></staff></section>
+    </score></mdiv></body></music></mei>"#;
+    let import = mei::from_mei(mei).unwrap();
+    assert_eq!(import.gabc, "%%\n(c3) A(h<) men(i>j~)\n");
+    assert_eq!(import.unsupported, vec![("<liquescent curve=\"x\">".to_string(), 1)]);
+    let f = GabcFile::new("%%\n(c4) A(g<) men(h>i~)\n");
+    assert_eq!(mei::from_mei(&f.as_mei()).unwrap().gabc, f.to_gabc());
+    assert!(mei::from_mei("<score-partwise/>").is_err());
+    assert!(mei::from_mei("<mei>").is_err());
+}
+
+#[test]
+fn test_json() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let document = GabcFile::from_json(&f.as_json()).unwrap();
+        assert_eq!(document, json::Document::from(&f));
+        let gabc = document.to_gabc();
+        assert_eq!(gabc, f.to_gabc());
+        assert_eq!(GabcFile::new(&gabc), f);
+    }
+    let json = r#"{"version": 1, "attributes": [], "syllables": [
+        {"text": "", "music": [{"type": "clef", "value": "c4"}]},
+        {"text": "A", "music": [{"type": "note", "position": "g"}, {"type": "barline", "value": "::"}]}
+    ]}"#;
+    assert_eq!(json::gabc_from_json(json).unwrap(), "%%\n(c4)A(g::)\n");
+    for (from, to) in [
+        ("\"version\": 1", "\"version\": 0"),
+        ("\"version\": 1, ", ""),
+        ("\"position\"", "\"pitch\""),
+        ("\"g\"", "\"n\""),
+        ("\"g\"", "\"gh\""),
+        ("\"text\": \"A\"", "\"text\": \"A(\""),
+        ("\"::\"", "\"|\""),
+        ("\"barline\"", "\"clef\""),
+    ]
+    .iter()
+    {
+        assert!(GabcFile::from_json(&json.replace(from, to)).is_err(), "{}", to);
+    }
+}
+
+#[test]
+fn test_abc() {
+    //Pitches, rhythm and text survive a round trip through ABC
+    fn notes(f: &GabcFile) -> Vec<(Option<usize>, bool)> {
+        f.syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter_map(|e| match e {
+                NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#']) => Some((n.pitch_index(), n.suffix.contains('.'))),
+                _ => None,
+            })
+            .collect()
+    }
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let abc = f.as_abc();
+        assert!(abc.lines().all(|l| !l.starts_with("w:") || !l.contains("  ")));
+        let gabc = abc::from_abc(&abc).unwrap();
+        let g = GabcFile::new(&gabc);
+        assert_eq!(notes(&g), notes(&f));
+        let lyrics = |f: &GabcFile| f.syllable_lyrics().into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(lyrics(&g), lyrics(&f));
+        //The formatter puts the header in canonical order
+        let attributes = |f: &GabcFile| {
+            let mut a: Vec<_> = f.attributes.iter().map(|(k, v)| (k.trim().to_string(), v.trim().to_string())).collect();
+            a.sort();
+            a
+        };
+        assert_eq!(attributes(&g), attributes(&f));
+        //Reading and writing again changes nothing
+        assert_eq!(abc::from_abc(&g.as_abc()).unwrap(), gabc);
+    }
+    let abc = "X:1
+T:Ut queant laxis
+Z:A. Transcriber
+N:mode: 2
+M:none
+L:1/8
+K:C clef=treble % no key signature
+(CD) F (ED) | (EF) G | %a comment
+w:ut_ que-ant_ la-_xis
+_B B2 =B ||
+w:re-_so
+";
+    assert_eq!(
+        abc::from_abc(abc).unwrap(),
+        "name: Ut queant laxis;\ntranscriber: A. Transcriber;\nmode: 2;\n%%\n(c4) ut(cd) que(f)ant(ed) (:)\nla(ef)xis(g) (:)\nre(ixi/i.)so(iyi) (::)\n"
+    );
+    for (abc, error) in [
+        ("X:1\nK:G\nG\n", "unsupported key: G"),
+        ("X:1\nL:1/4\nK:C\nG\n", "unsupported unit note length: 1/4"),
+        ("X:1\nK:C\nG3\n", "unsupported note length: G3"),
+        ("X:1\nK:C\n[GB]\n", "unsupported ABC: ["),
+        ("X:1\nG\n", "music before the K: field: G"),
+    ] {
+        assert_eq!(abc::from_abc(abc), Err(error.to_string()));
+    }
+}
+
+#[test]
+fn test_midi() {
+    use gabc_parser::midi::MidiOptions;
+    use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
+    //(delta, key) of each note on and off, and the lyrics of a file
+    fn read(smf: &[u8]) -> (Vec<(u32, u8, bool)>, Vec<String>) {
+        let smf = Smf::parse(smf).unwrap();
+        assert_eq!(smf.header.format, midly::Format::SingleTrack);
+        assert_eq!(smf.header.timing, midly::Timing::Metrical(480.into()));
+        let mut notes = Vec::new();
+        let mut lyrics = Vec::new();
+        let mut time = 0;
+        for event in &smf.tracks[0] {
+            time += u32::from(event.delta);
+            match event.kind {
+                TrackEventKind::Midi { message: MidiMessage::NoteOn { key, .. }, .. } => notes.push((time, key.as_int(), true)),
+                TrackEventKind::Midi { message: MidiMessage::NoteOff { key, .. }, .. } => notes.push((time, key.as_int(), false)),
+                TrackEventKind::Meta(MetaMessage::Lyric(text)) => lyrics.push(String::from_utf8(text.to_vec()).unwrap()),
+                _ => {}
+            }
+        }
+        assert!(matches!(smf.tracks[0].last().unwrap().kind, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+        (notes, lyrics)
+    }
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let (notes, lyrics) = read(&f.as_midi(&MidiOptions::default()));
+        let sung = f
+            .syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter(|e| matches!(e, NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#'])))
+            .count();
+        assert_eq!(notes.len(), 2 * sung);
+        assert_eq!(lyrics.len(), f.syllable_lyrics().iter().flatten().count());
+        assert!(notes.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+    let f = GabcFile::new("name:Test;\n%%\n(c4) Al(j_ixi)le(h.) (::) lu(j)\n");
+    let smf = f.as_midi(&MidiOptions::default());
+    let (notes, lyrics) = read(&smf);
+    //c' with an episema, b-flat, a with a mora, a rest of two plain notes, then c'
+    assert_eq!(
+        notes,
+        vec![(0, 60, true), (360, 60, false), (360, 58, true), (600, 58, false), (600, 57, true), (1080, 57, false), (1560, 60, true), (1800, 60, false)]
+    );
+    assert_eq!(lyrics, vec!["Al-", "le", "lu"]);
+    let smf = Smf::parse(&smf).unwrap();
+    assert!(smf.tracks[0].iter().any(|e| e.kind == TrackEventKind::Meta(MetaMessage::TrackName(b"Test"))));
+    assert!(smf.tracks[0].iter().any(|e| e.kind == TrackEventKind::Meta(MetaMessage::Tempo(833_333.into()))));
+    let options = MidiOptions {
+        tempo: 60,
+        transposition: -3,
+        mora_length: 3.0,
+        pause_length: 0.0,
+        ..Default::default()
+    };
+    let (notes, _) = read(&f.as_midi(&options));
+    assert_eq!(notes[0], (0, 57, true));
+    assert_eq!(notes[5], (1320, 54, false));
+    assert_eq!(notes[6], (1320, 57, true));
+    //A double mora lengthens the note by two morae
+    let (notes, _) = read(&GabcFile::new("%%\n(c4) A(h..) men(h.)\n").as_midi(&MidiOptions::default()));
+    assert_eq!(notes, vec![(0, 57, true), (720, 57, false), (720, 57, true), (1200, 57, false)]);
+}
+
+#[test]
+#[cfg(feature = "schema")]
+fn test_json_schema() {
+    //The checked-in schema is the one the library generates
+    let schema = std::fs::read_to_string("gabc.schema.json").unwrap();
+    assert_eq!(schema, json::schema());
+}
+
+#[test]
+fn test_table() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let tsv = f.as_tsv();
+        let rows: Vec<Vec<&str>> = tsv.lines().map(|l| l.split('\t').collect()).collect();
+        assert_eq!(rows.len(), f.syllables.len() + 1);
+        assert_eq!(rows[0], ["syllable", "text", "gabc", "lilypond", "midi", "division", "chord"]);
+        for row in &rows[1..] {
+            assert_eq!(row.len(), 7, "{:?}", path);
+            assert_eq!(row[3].split_whitespace().count(), row[4].split_whitespace().count());
+        }
+        //Chords added in a spreadsheet, some needing quotes in CSV, come back into the gabc
+        let mut csv = String::new();
+        for (i, line) in f.as_csv().lines().enumerate() {
+            csv.push_str(line);
+            if i > 0 && i % 3 == 0 {
+                csv.push_str(if i % 2 == 0 { "C7" } else { "\"F, G\"" });
+            }
+            csv.push('\n');
+        }
+        let merged = table::merge_chords(&text, &csv).unwrap();
+        let g = GabcFile::new(&merged);
+        for (i, syllable) in g.syllables.iter().enumerate() {
+            let expected = match i + 1 {
+                k if k % 3 == 0 && k % 2 == 0 => Some("C7"),
+                k if k % 3 == 0 => Some("F, G"),
+                _ => None,
+            };
+            assert_eq!(syllable.annotation(), expected, "{:?}", path);
+        }
+        //Nothing else changes, and the chords are in the next export
+        let unannotated = merged.replace("<alt>C7</alt>", "").replace("<alt>F, G</alt>", "");
+        assert_eq!(unannotated, text);
+        assert_eq!(g.as_csv(), csv);
+        //The chords are not sung: the Lilypond lyrics are those of the original
+        assert!(!g.as_lilypond().contains("alt>"), "{:?}", path);
+        assert_eq!(g.as_lilypond(), f.as_lilypond());
+        //Empty chords remove them again
+        assert_eq!(table::merge_chords(&merged, &f.as_tsv()).unwrap(), text);
+    }
+    assert!(table::merge_chords("%%\n(c4) A(g)", "syllable,text\n1,A\n").is_err());
+    assert!(table::merge_chords("%%\n(c4) A(g)", "syllable,chord\n1,A(\n").is_err());
+}
+
+#[test]
+fn test_kern() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let kern = f.as_kern();
+        let body: Vec<Vec<&str>> = kern.lines().filter(|l| !l.starts_with("!!")).map(|l| l.split('\t').collect()).collect();
+        assert_eq!(body[0], ["**kern", "**text"]);
+        assert_eq!(body.last().unwrap(), &["*-", "*-"]);
+        //Two non-empty tokens on every line, with no spaces, and barlines across both spines
+        for line in &body {
+            assert_eq!(line.len(), 2, "{:?}", path);
+            assert!(line.iter().all(|t| !t.is_empty() && !t.contains(' ')), "{:?}", path);
+            assert_eq!(line[0].starts_with('='), line[1].starts_with('='));
+        }
+        let notes: Vec<&Vec<&str>> = body.iter().filter(|l| !l[0].starts_with(['*', '='])).collect();
+        let sung = f
+            .syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter(|e| matches!(e, NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#'])))
+            .count();
+        assert_eq!(notes.len(), sung, "{:?}", path);
+        //Slurs open and close in turn
+        let slurs: String = notes.iter().flat_map(|l| l[0].chars()).filter(|c| "()".contains(*c)).collect();
+        assert_eq!(slurs, "()".repeat(slurs.len() / 2), "{:?}", path);
+        //The text spine reads as the file's words
+        let words: String = notes.iter().map(|l| l[1]).filter(|t| *t != ".").collect::<Vec<_>>().join(" ");
+        let words = words.replace("- -", "").replace('\u{a0}', " ");
+        let lyrics: Vec<String> = f.syllable_lyrics().into_iter().flatten().map(|(t, _)| t).collect();
+        assert_eq!(words.replace(' ', ""), lyrics.concat().replace(' ', ""), "{:?}", path);
+    }
+}
+
+#[test]
+fn test_volpiano() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let volpiano = f.as_volpiano();
+        assert!(volpiano.starts_with("1---"));
+        assert!(!volpiano.contains("----"));
+        let letters = volpiano.chars().filter(|c| "abcdefghjklmnopqrs".contains(c.to_ascii_lowercase())).count();
+        let sung = f
+            .syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter(|e| matches!(e, NoteElem::Note(n) if !n.suffix.contains(['x', 'y', '#'])))
+            .count();
+        assert_eq!(letters, sung);
+        assert!(search::MelodicQuery::from_volpiano(&volpiano).is_ok());
+        for n in 1..10 {
+            let incipit = f.volpiano_incipit(n);
+            assert!(volpiano.starts_with(&incipit));
+            assert!(!incipit.ends_with('-'));
+        }
+        //Read back with the text hyphenated as in the gabc, where no division falls inside a syllable
+        let inside = f.syllables.iter().any(|s| {
+            let mut music = s.music.iter().skip_while(|e| !matches!(e, NoteElem::Barline(_)));
+            music.any(|e| matches!(e, NoteElem::Note(_)))
+        });
+        if inside {
+            continue;
+        }
+        let mut words = String::new();
+        for (text, syllabic) in f.syllable_lyrics().into_iter().flatten() {
+            words.push_str(&text);
+            words.push_str(if matches!(syllabic, Syllabic::Begin | Syllabic::Middle) { "-" } else { " " });
+        }
+        let gabc = volpiano::from_volpiano(&volpiano, &words).unwrap();
+        let g = GabcFile::new(&gabc);
+        assert_eq!(g.as_volpiano(), volpiano, "{:?}", path);
+        assert_eq!(volpiano::from_volpiano(&g.as_volpiano(), &words).unwrap(), gabc);
+    }
+    let f = GabcFile::new(FILE);
+    assert_eq!(f.as_volpiano(), "1---gk-kl--k--k---kl--kjk---3---klkl--g---4");
+    assert_eq!(f.volpiano_incipit(3), "1---gk-kl--k--k");
+    //Flats and naturals, and a melody too high for Volpiano
+    let f = GabcFile::new("%%\n(c4) A(ixi) b(iyi) c(c) (:) (f1) d(mlm) (::)\n");
+    assert_eq!(f.as_volpiano(), "1---ij---Ij---c---3---pop---4");
+    let f = GabcFile::new("%%\n(c4) A(bxb) b(exe) c(ixi) d(lxl) (c1) e(jxj) f(jyj)\n");
+    assert_eq!(f.as_volpiano(), "1---wb---xe---ij---zm---yq---Yq");
+    //Kyrie X (Orbis factor) opens on a B-flat, written "ij" as in Cantus
+    let text = std::fs::read_to_string("examples/ky--kyrie_ad_lib_x_-_orbis_factor--solesmes.gabc").unwrap();
+    assert_eq!(GabcFile::new(&text).volpiano_incipit(4), "1---hij--hg--hd---3---fhged");
+    let f = GabcFile::new("%%\n(f1) A(lm) b(m)\n");
+    assert_eq!(f.as_volpiano(), "1---op---p");
+    //Low G, leftover text and music without text
+    assert_eq!(
+        volpiano::from_volpiano("1---9d--f---g-h---3", "Ad te levavi").unwrap(),
+        "%%\n(c2) Ad(cg)(i) te(j/k) (:)\nlevavi()\n"
+    );
+    assert_eq!(volpiano::from_volpiano("1---f--g--h", "").unwrap(), "%%\n(c4) (f)(g)(h)\n");
+    assert!(volpiano::from_volpiano("1---f-?", "A").is_err());
+    //A Cantus B-flat ("ij") flattens the B, as in the gabc of Kyrie X, and every sign is read
+    assert_eq!(volpiano::from_volpiano("1---hij--hg--hd", "Ky-ri-e").unwrap(), "%%\n(c4) Ky(ixhi)ri(hg)e(hd)\n");
+    let signs = volpiano::from_volpiano("1---wb---xe---ij---zm---Ij---Wb", "a b c d e f").unwrap();
+    assert_eq!(GabcFile::new(&signs).as_volpiano(), "1---wb---xe---ij---zm---Ij---Wb");
+    let high = volpiano::from_volpiano("1---yq---Yq", "a b").unwrap();
+    assert_eq!(GabcFile::new(&high).as_volpiano(), "1---yq---Yq");
+    assert!(volpiano::from_volpiano("1---g--2---g", "A men").is_err());
+}
+
+#[test]
+fn test_svg() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let mut systems = Vec::new();
+        for width in [600.0, 300.0].iter() {
+            let options = render::RenderOptions {
+                line_width: *width,
+                ..Default::default()
+            };
+            let svg = f.as_svg(&options);
+            let doc = parse_xml(&svg);
+            let root = doc.root_element();
+            assert_eq!(root.tag_name().name(), "svg");
+            assert_eq!(root.attribute("width"), Some(width.to_string().as_str()));
+            let groups: Vec<_> = root.children().filter(|n| n.is_element()).collect();
+            //Every system has a staff of four lines across its width
+            for g in &groups {
+                let staff = g
+                    .children()
+                    .filter(|n| n.has_tag_name("polyline"))
+                    .filter(|n| {
+                        let points: Vec<&str> = n.attribute("points").unwrap().split(' ').collect();
+                        points.len() == 2 && points[0].starts_with("0,") && points[1].starts_with(&format!("{},", width))
+                    })
+                    .count();
+                assert_eq!(staff, 4, "{:?}", path);
+            }
+            //Every sung syllable is written under the staff
+            let texts: Vec<&str> = doc.descendants().filter(|n| n.has_tag_name("text")).filter_map(|n| n.text()).collect();
+            for (lyric, _) in f.syllable_lyrics().into_iter().flatten() {
+                assert!(texts.iter().any(|t| t.contains(&lyric)), "{:?}: {}", path, lyric);
+            }
+            systems.push(groups.len());
+        }
+        assert!(systems[1] > systems[0]);
+    }
+    //A custos at the end of the first line, showing the first note of the second
+    let f = GabcFile::new("%%\n(c4) A(g)B(h)C(i)D(j)E(k)F(l)(::)");
+    let options = render::RenderOptions {
+        staff_size: 10.0,
+        line_width: 80.0,
+    };
+    let svg = f.as_svg(&options);
+    assert_eq!(svg.matches("<g ").count(), 3);
+    assert!(svg.contains("<polygon points=\"71,27 76,27 76,33 71,33\"/>"));
+    //The word continues on the next line
+    assert_eq!(svg.matches(">-</text>").count(), 2);
+}
+
+#[test]
+fn test_terminal() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let notes = f
+            .syllables
+            .iter()
+            .flat_map(|s| s.music.iter())
+            .filter(|e| matches!(e, NoteElem::Note(_)))
+            .count();
+        let preview = f.as_terminal(80);
+        assert!(preview.lines().all(|l| l.chars().count() <= 80), "{:?}", path);
+        assert_eq!(preview.chars().filter(|c| "■◆▪♭♮♯".contains(*c)).count(), notes, "{:?}", path);
+        assert!(f.as_terminal(40).lines().count() > preview.lines().count());
+    }
+    //Ledger lines, and the clef repeated on the next line
+    let f = GabcFile::new("%%\n(c4) A(m)B(a)(::)");
+    let expected = [
+        "   ■", "   ─", "", "C────", "", "─────", "", "─────", "", "─────", "   A-",
+        "C──", "", "───", "", "───", "", "───", "", "  ─", "  ■", "  B",
+        "C──┬┬─", "   ││", "───┼┼─", "   ││", "───┼┼─", "   ││", "───┴┴─", "",
+    ];
+    assert_eq!(f.as_terminal(5).lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_html() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        for css in [true, false].iter() {
+            let options = html::HtmlOptions {
+                css: *css,
+                ..Default::default()
+            };
+            let html = f.as_html(&options);
+            let doc = parse_xml(&html);
+            assert_eq!(doc.descendants().any(|n| n.has_tag_name("style")), *css);
+            assert_eq!(doc.descendants().filter(|n| n.has_tag_name("svg")).count(), 1);
+            assert_eq!(doc.descendants().filter(|n| n.has_tag_name("dt")).count(), f.attributes.len());
+            //Every syllable has a row pointing to its source, and the text links to the rows
+            let rows: Vec<_> = doc.descendants().filter(|n| n.has_tag_name("tr") && n.has_attribute("id")).collect();
+            assert_eq!(rows.len(), f.syllables.len());
+            for (syllable, row) in f.syllables.iter().zip(&rows) {
+                let start: usize = row.attribute("data-start").unwrap().parse().unwrap();
+                let end: usize = row.attribute("data-end").unwrap().parse().unwrap();
+                assert_eq!(start..end, syllable.span);
+                assert_eq!(text[start..end].trim_start(), syllable.to_gabc().trim_start(), "{:?}", path);
+            }
+            for link in doc.descendants().filter(|n| n.has_tag_name("a")) {
+                let id = &link.attribute("href").unwrap()[1..];
+                let row = rows.iter().find(|r| r.attribute("id") == Some(id)).unwrap();
+                assert_eq!(link.attribute("data-start"), row.attribute("data-start"));
+            }
+            //The text reads as the file's words, hyphenated, with any rubrics in spans
+            let paragraph = doc.descendants().find(|n| n.has_tag_name("p")).unwrap();
+            let words: String = paragraph
+                .descendants()
+                .filter(|n| n.is_text() && !n.parent().unwrap().has_tag_name("span"))
+                .filter_map(|n| n.text())
+                .collect();
+            let letters = |t: &str| t.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect::<String>();
+            assert_eq!(letters(&words), letters(&f.text()), "{:?}", path);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "png-pdf")]
+fn test_png_pdf() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "gabc") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        let f = GabcFile::new(&text);
+        let options = render::RenderOptions::default();
+        let image = tiny_skia::Pixmap::decode_png(&f.as_png(&options, 1.5).unwrap()).unwrap();
+        assert_eq!(image.width(), 900);
+        let svg = f.as_svg(&options);
+        let height: f64 = svg.split("height=\"").nth(1).unwrap().split('"').next().unwrap().parse().unwrap();
+        assert!((f64::from(image.height()) - height * 1.5).abs() <= 1.0);
+        let pixels = image.pixels();
+        assert!(pixels.iter().any(|p| p.red() == 0 && p.alpha() == 255));
+        assert!(pixels.iter().any(|p| p.red() == 255 && p.green() == 255));
+        //Rubrics are red
+        if text.contains(" *(") {
+            assert!(pixels.iter().any(|p| p.red() > 150 && p.green() < 50), "{:?}", path);
+        }
+        //A4 pages, pages with room for one line each, and a single page
+        let mut pages = Vec::new();
+        let mut systems = 0;
+        for height in [Some(841.89), Some(160.0), None].iter() {
+            let options = pdf::PdfOptions {
+                page_height: *height,
+                ..Default::default()
+            };
+            let pdf = f.as_pdf(&options);
+            check_pdf(&pdf);
+            let text = String::from_utf8_lossy(&pdf);
+            pages.push(text.matches("/Type /Page ").count());
+            systems = text.matches("\nq 1 0 0 1 ").count();
+        }
+        assert!(pages[0] <= pages[1]);
+        assert_eq!(pages[1], systems);
+        assert_eq!(pages[2], 1);
+    }
+}
+
+///Check the structure of a PDF file: every object in the cross-reference table is where the table
+///says, and the font is embedded.
+#[cfg(feature = "png-pdf")]
+fn check_pdf(pdf: &[u8]) {
+    let text = String::from_utf8_lossy(pdf);
+    assert!(text.starts_with("%PDF-1.7\n"));
+    let start: usize = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+    assert!(pdf[start..].starts_with(b"xref\n0 "));
+    let xref = String::from_utf8_lossy(&pdf[start..]);
+    let count: usize = xref.lines().nth(1).unwrap()[2..].parse().unwrap();
+    for (n, line) in xref.lines().skip(3).take(count - 1).enumerate() {
+        let offset: usize = line[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", n + 1).as_bytes()));
+    }
+    assert!(text.contains("/FontFile2 "));
+    assert!(text.contains("/ToUnicode "));
+}